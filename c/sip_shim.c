@@ -8,6 +8,9 @@
 #include <stdio.h>
 #include <math.h>
 #include <inttypes.h>
+#include <stdlib.h>
+#include <time.h>
+#include <unistd.h>
 
 typedef void (*b2b_pcm_cb)(const int16_t* samples, size_t nsamples, void* user);
 
@@ -18,8 +21,11 @@ static struct aufilt g_tap;
 static struct tmr g_aa_tmr;
 static struct tmr g_sink_m_tmr;
 static struct tmr g_mx_m_tmr;
+static struct tmr g_src_tx_tmr;
 static struct log g_log;
 static const char *g_role = NULL;
+// audio_codecs of every UA we allocate; set by sip_set_audio_codecs()
+static char g_codecs[64] = "pcmu";
 // forward decl for sink metrics tick
 static void sink_metrics_tick(void *arg);
 static struct ausrc *g_src = NULL;
@@ -29,7 +35,77 @@ static uint8_t  g_src_ch = 1;
 static uint32_t g_src_ptime = 20;
 static size_t   g_src_sampc = 160; // 20ms @ 8kHz mono
 static volatile bool g_src_started = false;
-static struct { uint64_t last_ms; uint32_t pkt; uint32_t min_int; uint32_t max_int; } g_src_m;
+static struct { uint64_t last_ms; uint32_t pkt; uint32_t min_int; uint32_t max_int; uint32_t tx_last; } g_src_m;
+/* RTP packets the source's call stream has sent, summed across calls.
+ * Polled on the main thread (src_tx_tick), read by the source thread. */
+static RE_ATOMIC uint32_t g_src_tx_pkts;
+
+/* --log-format json: the Rust side sets B2B_LOG_FORMAT=json and we emit the
+ * same {ts, role, pid, level, event, msg, fields} records it does. */
+static bool log_json(void)
+{
+    static int json = -1;
+    if (json < 0) {
+        const char *f = getenv("B2B_LOG_FORMAT");
+        json = (f && strcmp(f, "json") == 0) ? 1 : 0;
+    }
+    return json == 1;
+}
+
+static const char *role_name(void)
+{
+    if (!g_role) return "";
+    if (strcmp(g_role, "SRC ") == 0) return "source";
+    if (strcmp(g_role, "SINK") == 0) return "sink";
+    if (strcmp(g_role, "MIX ") == 0) return "mixer";
+    return "";
+}
+
+static void json_escape(char *dst, size_t sz, const char *src)
+{
+    size_t o = 0;
+    for (; src && *src && o + 7 < sz; src++) {
+        unsigned char c = (unsigned char)*src;
+        if (c == '"' || c == '\\') {
+            dst[o++] = '\\';
+            dst[o++] = (char)c;
+        } else if (c == '\n') {
+            dst[o++] = '\\';
+            dst[o++] = 'n';
+        } else if (c < 0x20) {
+            o += (size_t)snprintf(dst + o, sz - o, "\\u%04x", c);
+        } else {
+            dst[o++] = (char)c;
+        }
+    }
+    dst[o] = 0;
+}
+
+/* Print one log line: `msg` as-is in text mode, a JSON record otherwise.
+ * `fields` is the body of a JSON object (may be empty). */
+static void emit(const char *level, const char *event, const char *msg, const char *fields)
+{
+    if (!log_json()) {
+        (void)re_printf("%s\n", msg);
+        fflush(NULL);
+        return;
+    }
+    char esc[1024];
+    char ts[32];
+    struct timespec now;
+    struct tm tm;
+    clock_gettime(CLOCK_REALTIME, &now);
+    gmtime_r(&now.tv_sec, &tm);
+    snprintf(ts, sizeof(ts), "%04d-%02d-%02dT%02d:%02d:%02d.%03ldZ",
+             tm.tm_year + 1900, tm.tm_mon + 1, tm.tm_mday,
+             tm.tm_hour, tm.tm_min, tm.tm_sec, now.tv_nsec / 1000000);
+    json_escape(esc, sizeof(esc), msg);
+    (void)re_printf("{\"ts\":\"%s\",\"role\":\"%s\",\"pid\":%d,\"level\":\"%s\","
+                    "\"event\":\"%s\",\"msg\":\"%s\",\"fields\":{%s}}\n",
+                    ts, role_name(), (int)getpid(), level, event, esc,
+                    fields ? fields : "");
+    fflush(NULL);
+}
 
 struct b2b_src_st {
     struct ausrc_prm prm;
@@ -73,9 +149,18 @@ static int b2b_src_thread(void *arg)
                 size_t cur = g_src_ab ? aubuf_cur_size(g_src_ab) : 0;
                 size_t bytes_per_ms = (g_src_srate * g_src_ch * 2) / 1000;
                 uint32_t back_ms = bytes_per_ms ? (uint32_t)(cur / bytes_per_ms) : 0;
-                re_printf("SRC_METRICS5s pkts=250 int_min=%ums int_max=%ums backlog_ms=%u\n",
-                          g_src_m.min_int, g_src_m.max_int, back_ms);
-                fflush(NULL);
+                /* Packets actually sent since the last line, not frames read. */
+                uint32_t sent = re_atomic_rlx(&g_src_tx_pkts);
+                uint32_t pkts = sent - g_src_m.tx_last;
+                g_src_m.tx_last = sent;
+                char msg[160], fields[160];
+                re_snprintf(msg, sizeof(msg),
+                            "SRC_METRICS5s pkts=%u int_min=%ums int_max=%ums backlog_ms=%u",
+                            pkts, g_src_m.min_int, g_src_m.max_int, back_ms);
+                re_snprintf(fields, sizeof(fields),
+                            "\"pkts\":%u,\"int_min_ms\":%u,\"int_max_ms\":%u,\"backlog_ms\":%u",
+                            pkts, g_src_m.min_int, g_src_m.max_int, back_ms);
+                emit("info", "metrics", msg, fields);
                 g_src_m.min_int = 0; g_src_m.max_int = 0;
             }
         } while (next <= now);
@@ -138,7 +223,7 @@ static int dech(struct aufilt_dec_st *st, struct auframe *af)
     (void)st;
     if (!g_cb || !af || !af->sampv || af->fmt != AUFMT_S16LE)
         return 0;
-    // Deliver mono/8k frames as provided by the audio pipeline
+    // Deliver mono frames at the codec rate as provided by the audio pipeline
     g_cb((const int16_t*)af->sampv, af->sampc, g_user);
     // count frames for sink metrics (one decoded frame per 20ms)
     if (g_role && strcmp(g_role, "SINK") == 0) {
@@ -159,12 +244,22 @@ static void reg_filter(void)
 
 static void log_adapter(uint32_t level, const char *msg)
 {
-    (void)level;
     if (!msg) return;
     /* Do not prefix; the Rust orchestrator tags + timestamps every line. */
-    (void)re_printf("%s", msg);
-    /* ensure line is flushed promptly even when not attached to a TTY */
-    fflush(NULL);
+    if (log_json()) {
+        static const char *levels[] = { "debug", "info", "warn", "error" };
+        char line[1024];
+        size_t n = strlen(msg);
+        while (n && (msg[n - 1] == '\n' || msg[n - 1] == '\r')) n--;
+        if (n >= sizeof(line)) n = sizeof(line) - 1;
+        memcpy(line, msg, n);
+        line[n] = 0;
+        emit(level <= LEVEL_ERROR ? levels[level] : "info", "sip", line, NULL);
+    } else {
+        (void)re_printf("%s", msg);
+        /* ensure line is flushed promptly even when not attached to a TTY */
+        fflush(NULL);
+    }
     // crude drop detector for sink
     if (g_role && strcmp(g_role, "SINK") == 0) {
         if (strstr(msg, "jbuf: drop")) {
@@ -225,7 +320,10 @@ int sip_sink_init(const char* bind_addr)
 
     // Allocate a UA with a dummy AOR; it will act as UAS and auto-answer.
     if (!g_ua) {
-        err |= ua_alloc(&g_ua, "sip:anon@0.0.0.0;regint=0;catchall=yes;audio_codecs=pcmu");
+        char aor[160];
+        re_snprintf(aor, sizeof(aor),
+                    "sip:anon@0.0.0.0;regint=0;catchall=yes;audio_codecs=%s", g_codecs);
+        err |= ua_alloc(&g_ua, aor);
         if (err) return err;
         (void)ua_set_autoanswer_value(g_ua, "yes");
         ua_set_catchall(g_ua, true);
@@ -250,6 +348,25 @@ int sip_sink_shutdown(void)
 }
 
 // Source (outbound) APIs
+/* Fold the current call's RTP TX counter into g_src_tx_pkts. The counter
+ * is per stream, so a new call (or a stream that restarted) starts over. */
+static void src_tx_tick(void *arg)
+{
+    static const struct stream *last_strm;
+    static uint32_t last_n;
+    (void)arg;
+    struct call *c = g_ua ? ua_call(g_ua) : NULL;
+    const struct stream *strm = c ? audio_strm(call_audio(c)) : NULL;
+    uint32_t n = strm ? stream_metric_get_tx_n_packets(strm) : 0;
+    if (strm != last_strm || n < last_n) {
+        last_strm = strm;
+        last_n = 0;
+    }
+    re_atomic_rlx_add(&g_src_tx_pkts, n - last_n);
+    last_n = n;
+    tmr_start(&g_src_tx_tmr, 100, src_tx_tick, NULL);
+}
+
 int sip_source_start(const char* target_uri, uint32_t srate, uint8_t ch, uint32_t ptime_ms)
 {
     int err = 0;
@@ -285,7 +402,10 @@ int sip_source_start(const char* target_uri, uint32_t srate, uint8_t ch, uint32_
 
     // Create a UA if needed and dial target
     if (!g_ua) {
-        err |= ua_alloc(&g_ua, "sip:anon@0.0.0.0;regint=0;audio_codecs=pcmu");
+        char aor[160];
+        re_snprintf(aor, sizeof(aor),
+                    "sip:anon@0.0.0.0;regint=0;audio_codecs=%s", g_codecs);
+        err |= ua_alloc(&g_ua, aor);
         if (err) return err;
     }
     if (target_uri && *target_uri) {
@@ -307,6 +427,7 @@ int sip_source_start(const char* target_uri, uint32_t srate, uint8_t ch, uint32_
         from[9 + host_len] = '\0';
         err |= ua_connect(g_ua, &call, from, target_uri, VIDMODE_OFF);
     }
+    tmr_start(&g_src_tx_tmr, 100, src_tx_tick, NULL);
     return err;
 }
 
@@ -334,6 +455,7 @@ int sip_source_tx_enable(int enable)
 
 int sip_source_shutdown(void)
 {
+    tmr_cancel(&g_src_tx_tmr);
     if (g_src) { mem_deref(g_src); g_src = NULL; }
     if (g_src_ab) { mem_deref(g_src_ab); g_src_ab = NULL; }
     return 0;
@@ -377,6 +499,17 @@ const char* brs_codecs_csv(void)
     return buf;
 }
 
+// Restrict the codecs of UAs allocated from now on, e.g. "g722" or
+// "l16/48000/1". Without the auresamp module the codec rate must match the
+// ausrc/auplay rate, so callers pick the codec for their sample rate.
+int sip_set_audio_codecs(const char* list)
+{
+    if (!list || !*list || strlen(list) >= sizeof(g_codecs))
+        return EINVAL;
+    str_ncpy(g_codecs, list, sizeof(g_codecs));
+    return 0;
+}
+
 // --------------------- MIXER (bridge) ----------------------
 static struct ua *g_mx_in = NULL;   // inbound UA (server)
 static struct ua *g_mx_out = NULL;  // outbound UA (client)
@@ -975,21 +1108,23 @@ static void mx_metrics_tick(void *arg)
     mtx_unlock(&g_mx_lock);
 
     if (g_role && strcmp(g_role, "MIX ") == 0) {
-        re_printf("MIX_METRICS5s legs=%u in_frames=%u out_frames=%u in_samples=%" PRIu64
-                  " out_samples=%" PRIu64 " tone_on=%u silence_in=%u underrun=%u "
-                  "bridge_ms=%u(%u..%u)\n",
-                  legs,
-                  in_frames,
-                  out_frames,
-                  in_samples,
-                  out_samples,
-                  tone_frames,
-                  silence_frames,
-                  underrun_frames,
-                  cur_ms,
-                  min_ms,
-                  max_ms);
-        fflush(NULL);
+        char msg[320], fields[320];
+        re_snprintf(msg, sizeof(msg),
+                    "MIX_METRICS5s legs=%u in_frames=%u out_frames=%u in_samples=%" PRIu64
+                    " out_samples=%" PRIu64 " tone_on=%u silence_in=%u underrun=%u "
+                    "bridge_ms=%u(%u..%u)",
+                    legs, in_frames, out_frames, in_samples, out_samples,
+                    tone_frames, silence_frames, underrun_frames,
+                    cur_ms, min_ms, max_ms);
+        re_snprintf(fields, sizeof(fields),
+                    "\"legs\":%u,\"in_frames\":%u,\"out_frames\":%u,"
+                    "\"in_samples\":%" PRIu64 ",\"out_samples\":%" PRIu64 ","
+                    "\"tone_on\":%u,\"silence_in\":%u,\"underrun\":%u,"
+                    "\"bridge_ms\":%u,\"bridge_min_ms\":%u,\"bridge_max_ms\":%u",
+                    legs, in_frames, out_frames, in_samples, out_samples,
+                    tone_frames, silence_frames, underrun_frames,
+                    cur_ms, min_ms, max_ms);
+        emit("info", "metrics", msg, fields);
     }
 
     tmr_start(&g_mx_m_tmr, 5000, mx_metrics_tick, NULL);
@@ -1036,7 +1171,11 @@ int sip_mixer_init(const char* bind_addr, const char* target_uri,
     }
 
     if (!g_mx_in) {
-        err |= ua_alloc(&g_mx_in, "sip:anon@0.0.0.0;regint=0;catchall=yes;audio_codecs=pcmu;audio_player=b2b_mix,inbound");
+        char aor[192];
+        re_snprintf(aor, sizeof(aor),
+                    "sip:anon@0.0.0.0;regint=0;catchall=yes;audio_codecs=%s;"
+                    "audio_player=b2b_mix,inbound", g_codecs);
+        err |= ua_alloc(&g_mx_in, aor);
         if (err)
             return err;
         (void)ua_set_autoanswer_value(g_mx_in, "yes");
@@ -1046,7 +1185,10 @@ int sip_mixer_init(const char* bind_addr, const char* target_uri,
     tmr_start(&g_aa_tmr, 50, aa_tick, NULL);
 
     if (!g_mx_out) {
-        err |= ua_alloc(&g_mx_out, "sip:anon@0.0.0.0;regint=0;audio_codecs=pcmu");
+        char aor[160];
+        re_snprintf(aor, sizeof(aor),
+                    "sip:anon@0.0.0.0;regint=0;audio_codecs=%s", g_codecs);
+        err |= ua_alloc(&g_mx_out, aor);
         if (err)
             return err;
     }
@@ -1155,8 +1297,12 @@ static void sink_metrics_tick(void *arg)
 {
     (void)arg;
     if (g_role && strcmp(g_role, "SINK") == 0) {
-        re_printf("SINK_METRICS5s rx_frames=%u drops=%u\n", g_sink_m.frames1s, g_sink_m.drops1s);
-        fflush(NULL);
+        char msg[96], fields[96];
+        re_snprintf(msg, sizeof(msg), "SINK_METRICS5s rx_frames=%u drops=%u",
+                    g_sink_m.frames1s, g_sink_m.drops1s);
+        re_snprintf(fields, sizeof(fields), "\"rx_frames\":%u,\"drops\":%u",
+                    g_sink_m.frames1s, g_sink_m.drops1s);
+        emit("info", "metrics", msg, fields);
         g_sink_m.frames1s = 0; g_sink_m.drops1s = 0;
     }
     tmr_start(&g_sink_m_tmr, 5000, sink_metrics_tick, NULL);
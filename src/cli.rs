@@ -1,7 +1,8 @@
 use clap::{Parser, ValueEnum};
+use serde::Deserialize;
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
 pub enum RoleKind {
     Orchestrator,
     Source,
@@ -22,19 +23,88 @@ pub enum ColorChoice {
     Never,
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+/// Launch artifact written by `--export`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// POSIX sh script
+    Shell,
+    /// Docker Compose file
+    Compose,
+    /// systemd service units plus a target
+    Systemd,
+}
+
+/// What the orchestrator does when a role stops passing packets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum StallAction {
+    Warn,
+    /// Restart the role, up to its `max_restarts` stall restarts (counted
+    /// apart from crash restarts).
+    Restart,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum BufferMode {
     Fixed,
     Adaptive,
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+#[derive(Debug, Clone, Copy, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum JbufType {
     Off,
     Fixed,
     Adaptive,
 }
 
+/// Audio rate used end to end: decoder, shims, mixer and sink output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SampleRate {
+    /// Narrowband, PCMU
+    #[value(name = "8k", alias = "8000")]
+    Narrow,
+    /// Wideband, G.722
+    #[value(name = "16k", alias = "16000")]
+    Wide,
+    /// Full band, L16; needs --ptime-ms 15 or less to fit a 1500-byte MTU
+    #[value(name = "48k", alias = "48000")]
+    Full,
+}
+
+impl SampleRate {
+    pub fn hz(self) -> u32 {
+        match self {
+            SampleRate::Narrow => 8000,
+            SampleRate::Wide => 16000,
+            SampleRate::Full => 48000,
+        }
+    }
+
+    /// Codec carrying this rate unchanged; the shims cannot resample
+    /// between codec and audio device.
+    pub fn codec(self) -> &'static str {
+        match self {
+            SampleRate::Narrow => "pcmu",
+            SampleRate::Wide => "g722",
+            SampleRate::Full => "l16/48000/1",
+        }
+    }
+}
+
+/// Resampler filter length for audio files not already at `--sample-rate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResampleQuality {
+    /// 8 zero crossings per side: flat to ~75% of Nyquist, ~60 dB rejection
+    Fast,
+    /// 16 zero crossings per side: flat to 85% of Nyquist, ~80 dB rejection
+    Medium,
+    /// 32 zero crossings per side: flat to 90% of Nyquist, ~95 dB rejection
+    High,
+}
+
 #[derive(Parser, Debug)]
 #[command(
     name = "b2b",
@@ -64,9 +134,31 @@ pub struct Cli {
     pub color: ColorChoice,
 
     // Source
+    /// Audio to send: WAV, FLAC, Ogg/Vorbis, MP3 or AAC/M4A (detected by content)
     #[arg(long, value_name = "FILE")]
     pub audio_file: Option<PathBuf>,
 
+    /// Play an M3U file, every audio file under a directory, or a quoted
+    /// glob (`prompts/*.wav`, `**` for any depth) in a loop
+    #[arg(long, value_name = "M3U|DIR|GLOB", conflicts_with = "audio_file")]
+    pub playlist: Option<String>,
+
+    /// Play the playlist in a random order, reshuffled every pass
+    #[arg(long, requires = "playlist")]
+    pub shuffle: bool,
+
+    /// Shuffle seed, to repeat a run's order [default: time-based, logged]
+    #[arg(long, requires = "shuffle")]
+    pub seed: Option<u64>,
+
+    /// Overlap consecutive playlist entries; 0 plays them back to back
+    #[arg(long, default_value_t = 0, value_name = "MS")]
+    pub crossfade_ms: u32,
+
+    /// Anti-aliasing filter quality when resampling the audio file
+    #[arg(long, default_value = "medium", value_enum)]
+    pub resample_quality: ResampleQuality,
+
     #[arg(long, default_value_t = 1000)]
     pub prebuffer_ms: u32,
 
@@ -77,11 +169,19 @@ pub struct Cli {
     #[arg(long, default_value_t = 20)]
     pub ptime_ms: u32,
 
-    #[arg(long, default_value = "pcmu")]
-    pub codec: String,
+    /// Audio sample rate of every role; the orchestrator passes it on
+    #[arg(long, default_value = "8k", value_enum)]
+    pub sample_rate: SampleRate,
+
+    /// Codec to offer/accept [default: pcmu, g722 or l16/48000/1 to match
+    /// --sample-rate]
+    #[arg(long)]
+    pub codec: Option<String>,
 
     // Sink
-    #[arg(long, default_value = "aplay -f S16_LE -r 8000 -c 1 -t raw")]
+    /// Playout command fed raw S16_LE mono on stdin; `{rate}` expands to
+    /// the sample rate in Hz
+    #[arg(long, default_value = "aplay -f S16_LE -r {rate} -c 1 -t raw")]
     pub aplay_cmd: String,
 
     // Sink buffers (playout + RTP jitter)
@@ -120,16 +220,104 @@ pub struct Cli {
     #[arg(long, value_name = "FILE")]
     pub plan: Option<PathBuf>,
 
+    /// Apply the plan's [profile.NAME] overlay (repeatable, in order)
+    #[arg(long, value_name = "NAME")]
+    pub profile: Vec<String>,
+
+    /// Override a plan value, e.g. sink.jbuf_max_ms=300 (repeatable, applied last)
+    #[arg(long, value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+
+    /// Per-role SIGTERM-to-SIGKILL window when stopping (plan: stop_grace_ms)
     #[arg(long, default_value_t = 5000)]
     pub grace_ms: u64,
 
+    /// Deadline for the whole shutdown; roles still running get SIGKILL
     #[arg(long, default_value_t = 15000)]
     pub kill_ms: u64,
 
     #[arg(long, default_value_t = false)]
     pub dry_run: bool,
 
+    /// Write launch artifacts reproducing the plan instead of running it
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub export: Option<ExportFormat>,
+
+    /// Write --export files into DIR instead of printing them
+    #[arg(long, value_name = "DIR", requires = "export")]
+    pub export_dir: Option<PathBuf>,
+
+    /// Reload the plan when it or an included file changes (SIGHUP always reloads)
+    #[arg(long, default_value_t = false)]
+    pub watch: bool,
+
+    /// Full-screen dashboard: a pane per role, live metrics and a filterable log
+    #[arg(long, default_value_t = false)]
+    pub tui: bool,
+
+    /// Parse and validate the plan, then exit without spawning roles
+    #[arg(long, default_value_t = false)]
+    pub validate_plan: bool,
+
     /// Orchestrator readiness wait timeout
     #[arg(long, default_value_t = 10000)]
     pub ready_ms: u64,
+
+    /// Flag a role as stalled after this long without packet flow (0 = off)
+    #[arg(long, default_value_t = 10000)]
+    pub stall_ms: u64,
+
+    /// Action on a stalled role
+    #[arg(long, default_value = "warn", value_enum)]
+    pub stall_action: StallAction,
+
+    /// Write per-role raw logs and a merged log under DIR (overrides the plan's [logs] dir)
+    #[arg(long, value_name = "DIR")]
+    pub log_dir: Option<PathBuf>,
+
+    /// Rotate log files at this size in MiB
+    #[arg(long, value_name = "MIB")]
+    pub log_rotate_mb: Option<u64>,
+
+    /// Rotate log files after this long, e.g. "1h" or "30m"
+    #[arg(long, value_name = "DURATION")]
+    pub log_rotate_every: Option<String>,
+
+    /// Write a JSON run report to FILE when the orchestrator exits
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<PathBuf>,
+
+    /// Also write the run report as Markdown
+    #[arg(long, value_name = "FILE")]
+    pub report_md: Option<PathBuf>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate(arg: &str) -> Result<SampleRate, clap::Error> {
+        Cli::try_parse_from(["b2b", "--role", "source", "--sample-rate", arg])
+            .map(|c| c.sample_rate)
+    }
+
+    #[test]
+    fn sample_rate_takes_short_and_hz_spellings() {
+        for (arg, want, hz) in [
+            ("8k", SampleRate::Narrow, 8000),
+            ("8000", SampleRate::Narrow, 8000),
+            ("16k", SampleRate::Wide, 16000),
+            ("16000", SampleRate::Wide, 16000),
+            ("48k", SampleRate::Full, 48000),
+            ("48000", SampleRate::Full, 48000),
+        ] {
+            let got = rate(arg).unwrap();
+            assert_eq!((got, got.hz()), (want, hz), "{arg}");
+        }
+        let cli = Cli::try_parse_from(["b2b", "--role", "source"]).unwrap();
+        assert_eq!(cli.sample_rate, SampleRate::Narrow);
+        for bad in ["44k", "44100", "8", ""] {
+            assert!(rate(bad).is_err(), "{bad}");
+        }
+    }
 }
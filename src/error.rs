@@ -0,0 +1,155 @@
+use crate::{logging, orchestrator};
+use std::process::ExitStatus;
+
+/// Process exit codes from the requirements doc. Anything unclassified
+/// exits with 1.
+pub const EXIT_USAGE: u8 = 64;
+pub const EXIT_SIP: u8 = 65;
+pub const EXIT_AUDIO: u8 = 66;
+pub const EXIT_TIMEOUT: u8 = 67;
+pub const EXIT_PANIC: u8 = 70;
+/// A plan's `[expect]` assertions failed on an otherwise clean run.
+pub const EXIT_EXPECT: u8 = 1;
+
+/// Failure classes shared by every role. Raise them with `bail!` or attach
+/// them with `.context(..)`; `exit_code` finds them anywhere in the chain.
+#[derive(Debug, thiserror::Error)]
+pub enum B2bError {
+    /// Invalid arguments or plan.
+    #[error("{0}")]
+    Usage(String),
+    /// SIP/UA setup or call failure.
+    #[error("{0}")]
+    Sip(String),
+    /// Decode, aplay or PCM pipeline failure.
+    #[error("{0}")]
+    Audio(String),
+    /// Readiness, call setup or shutdown deadline missed.
+    #[error("{0}")]
+    Timeout(String),
+    /// `[expect]` assertions failed.
+    #[error("{0}")]
+    Expect(String),
+    /// A supervised role failed; the orchestrator exits with its code.
+    #[error("role {role} {status}")]
+    Role {
+        role: String,
+        status: String,
+        code: u8,
+    },
+}
+
+impl B2bError {
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            B2bError::Usage(_) => EXIT_USAGE,
+            B2bError::Sip(_) => EXIT_SIP,
+            B2bError::Audio(_) => EXIT_AUDIO,
+            B2bError::Timeout(_) => EXIT_TIMEOUT,
+            B2bError::Expect(_) => EXIT_EXPECT,
+            B2bError::Role { code, .. } => *code,
+        }
+    }
+}
+
+pub fn exit_code(err: &anyhow::Error) -> u8 {
+    err.chain()
+        .find_map(|e| e.downcast_ref::<B2bError>())
+        .map_or(1, B2bError::exit_code)
+}
+
+/// Exit code the orchestrator reports for a child's exit status. A child
+/// killed by a signal it was not sent (SIGSEGV, SIGABRT) counts as a crash.
+pub fn status_code(status: &ExitStatus) -> u8 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if status.signal().is_some() {
+            return EXIT_PANIC;
+        }
+    }
+    match status.code() {
+        Some(c) => u8::try_from(c).unwrap_or(1),
+        None => 1,
+    }
+}
+
+/// Log panics on the role's tag and exit with `EXIT_PANIC`, whichever
+/// thread panicked. `process::exit` runs no destructors, so a `--tui`
+/// dashboard gives the terminal back first and the panic lands on stderr.
+pub fn install_panic_hook(tag: String) {
+    std::panic::set_hook(Box::new(move |info| {
+        orchestrator::restore_terminal();
+        let msg = info.to_string().replace('\n', " ");
+        logging::println_tag(&tag, &format!("panic: {msg}"));
+        std::process::exit(EXIT_PANIC as i32);
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn each_error_kind_has_its_exit_code() {
+        let role = |code| B2bError::Role {
+            role: "sink".into(),
+            status: "exited with code 65".into(),
+            code,
+        };
+        for (err, code) in [
+            (B2bError::Usage("bad flag".into()), 64),
+            (B2bError::Sip("no route".into()), 65),
+            (B2bError::Audio("decode".into()), 66),
+            (B2bError::Timeout("no READY".into()), 67),
+            (B2bError::Expect("1 of 2 failed".into()), 1),
+            (role(65), 65),
+            (role(70), 70),
+        ] {
+            let name = format!("{err:?}");
+            assert_eq!(err.exit_code(), code, "{name}");
+            // Found anywhere in the chain, under added context too.
+            let wrapped = Err::<(), _>(err).context("starting").unwrap_err();
+            assert_eq!(exit_code(&wrapped), code, "{name}");
+        }
+        assert_eq!(
+            (
+                EXIT_USAGE,
+                EXIT_SIP,
+                EXIT_AUDIO,
+                EXIT_TIMEOUT,
+                EXIT_PANIC,
+                EXIT_EXPECT
+            ),
+            (64, 65, 66, 67, 70, 1)
+        );
+    }
+
+    #[test]
+    fn unclassified_errors_exit_with_one() {
+        assert_eq!(exit_code(&anyhow::anyhow!("plain")), 1);
+        let io = std::io::Error::other("disk");
+        assert_eq!(exit_code(&anyhow::Error::new(io).context("writing")), 1);
+    }
+
+    #[test]
+    fn child_status_maps_to_exit_code() {
+        // Raw wait statuses: exit code in the second byte, signal in the first.
+        for (raw, code) in [
+            (0, 0),
+            (65 << 8, 65),
+            (1 << 8, 1),
+            (255 << 8, 255),
+            (libc::SIGSEGV, 70),
+            (libc::SIGKILL, 70),
+        ] {
+            assert_eq!(
+                status_code(&ExitStatus::from_raw(raw)),
+                code,
+                "raw {raw:#x}"
+            );
+        }
+    }
+}
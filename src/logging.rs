@@ -1,8 +1,104 @@
-use crate::cli::{Cli, ColorChoice};
+use crate::cli::{Cli, ColorChoice, LogFormat};
 use owo_colors::OwoColorize;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 use std::io::{Write, stderr, stdout};
+use std::sync::{Mutex, OnceLock};
 use time::{OffsetDateTime, macros::format_description};
 
+pub type LineFn = Box<dyn Fn(&str) + Send + Sync>;
+
+static MIRROR: OnceLock<LineFn> = OnceLock::new();
+/// Replaces stderr for console lines while `--tui` owns the terminal.
+static CONSOLE: Mutex<Option<LineFn>> = Mutex::new(None);
+/// Role kind of this process, set when `--log-format json` is on.
+static JSON_ROLE: OnceLock<String> = OnceLock::new();
+
+/// One `--log-format json` line. Rust roles and the C shim emit the same
+/// shape; `msg` is always the text the line would have had in text mode.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    pub ts: String,
+    pub role: String,
+    /// Plan instance name, added by the orchestrator when it forwards a
+    /// child's line.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    pub pid: u32,
+    pub level: String,
+    pub event: String,
+    pub msg: String,
+    #[serde(default)]
+    pub fields: Map<String, Value>,
+}
+
+impl Record {
+    fn new(event: &str, msg: &str, fields: Value) -> Self {
+        Self {
+            ts: ts_rfc3339(),
+            role: JSON_ROLE.get().cloned().unwrap_or_default(),
+            instance: None,
+            pid: std::process::id(),
+            level: level_of(msg).into(),
+            event: event.into(),
+            msg: strip_ansi(msg),
+            fields: match fields {
+                Value::Object(m) => m,
+                _ => Map::new(),
+            },
+        }
+    }
+
+    pub fn json_line(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+}
+
+/// Parse a child's JSON log line; anything else is plain text.
+pub fn parse_record(line: &str) -> Option<Record> {
+    if !line.starts_with('{') {
+        return None;
+    }
+    serde_json::from_str(line).ok()
+}
+
+/// Level implied by a text line: roles log their terminal error as
+/// `error: ...` and panics as `panic: ...`.
+fn level_of(msg: &str) -> &'static str {
+    if msg.starts_with("error:") || msg.starts_with("panic:") {
+        "error"
+    } else if msg.starts_with("warn") {
+        "warn"
+    } else {
+        "info"
+    }
+}
+
+/// Also hand every console line, timestamped and without ANSI codes, to
+/// `f` (the orchestrator's merged log file).
+pub fn set_mirror(f: impl Fn(&str) + Send + Sync + 'static) {
+    let _ = MIRROR.set(Box::new(f));
+}
+
+/// Send console lines to `f` instead of stderr; `None` restores stderr.
+pub fn set_console(f: Option<LineFn>) {
+    if let Ok(mut c) = CONSOLE.lock() {
+        *c = f;
+    }
+}
+
+fn console_line(line: &str) {
+    if let Ok(c) = CONSOLE.lock() {
+        if let Some(f) = c.as_ref() {
+            f(line);
+            return;
+        }
+    }
+    let mut e = stderr().lock();
+    let _ = writeln!(e, "{}", line);
+    let _ = e.flush();
+}
+
 pub fn init(args: &Cli) {
     // Colors handled per-line; nothing global to init for now.
     if matches!(args.color, ColorChoice::Never) {
@@ -10,6 +106,17 @@ pub fn init(args: &Cli) {
             std::env::set_var("NO_COLOR", "1");
         }
     }
+    if matches!(args.log_format, LogFormat::Json) {
+        let _ = JSON_ROLE.set(format!("{:?}", args.role).to_ascii_lowercase());
+        // Read by the C shim for its own log and metrics lines.
+        unsafe {
+            std::env::set_var("B2B_LOG_FORMAT", "json");
+        }
+    }
+}
+
+fn json() -> bool {
+    JSON_ROLE.get().is_some()
 }
 
 pub fn ts() -> String {
@@ -18,7 +125,24 @@ pub fn ts() -> String {
     now.format(&fmt).unwrap_or_else(|_| "--:--:--.---Z".into())
 }
 
+fn ts_rfc3339() -> String {
+    let now = OffsetDateTime::now_utc();
+    let fmt =
+        format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z");
+    now.format(&fmt).unwrap_or_default()
+}
+
 pub fn println_tag(tag: &str, s: &str) {
+    event(tag, "log", s, Value::Null);
+}
+
+/// Log a typed event (`sip`, `metrics`, ...). `fields` carries the
+/// structured values in JSON mode; text mode prints `s` only.
+pub fn event(tag: &str, event: &str, s: &str, fields: Value) {
+    if json() {
+        write_record(&Record::new(event, s, fields));
+        return;
+    }
     let child_raw = std::env::var("B2B_CHILD_RAW").ok().as_deref() == Some("1");
     if child_raw {
         // Child processes emit raw lines; orchestrator adds timestamp + role.
@@ -28,57 +152,225 @@ pub fn println_tag(tag: &str, s: &str) {
         return;
     }
     let t = ts();
+    if let Some(mirror) = MIRROR.get() {
+        mirror(&strip_ansi(&format!("[{t}] {tag} {s}")));
+    }
     let line = if std::env::var("NO_COLOR").is_ok() {
         format!("[{t}] {tag} {s}")
     } else {
         format!("{} {} {}", format!("[{t}]").dimmed(), tag.bold(), s)
     };
-    let mut e = stderr().lock();
-    let _ = writeln!(e, "{}", line);
-    let _ = e.flush();
+    console_line(&line);
 }
 
-pub fn role_tag(role: &str) -> String {
-    // Color palette aligned with HLD.
-    match role.to_ascii_lowercase().as_str() {
-        "orchestrator" => {
-            if std::env::var("NO_COLOR").is_ok() {
-                "[ORCH]".into()
-            } else {
-                "[ORCH]".yellow().to_string()
-            }
-        }
-        "source" => {
-            if std::env::var("NO_COLOR").is_ok() {
-                "[SRC ]".into()
-            } else {
-                "[SRC ]".green().to_string()
-            }
+fn write_record(rec: &Record) {
+    let line = rec.json_line();
+    if std::env::var("B2B_CHILD_RAW").ok().as_deref() == Some("1") {
+        let mut e = stderr().lock();
+        let _ = writeln!(e, "{}", line);
+        let _ = e.flush();
+        return;
+    }
+    if let Some(mirror) = MIRROR.get() {
+        mirror(&line);
+    }
+    console_line(&line);
+}
+
+/// Print one line read from a child. JSON records pass through (tagged
+/// with the instance name) in JSON mode and are re-rendered as colored
+/// text otherwise; plain text lines are wrapped the other way round.
+pub fn forward(tag: &str, kind: &str, name: &str, pid: u32, line: &str) {
+    let rec = parse_record(line);
+    if json() {
+        let mut rec = rec.unwrap_or_else(|| Record {
+            role: kind.into(),
+            pid,
+            ..Record::new("log", line, Value::Null)
+        });
+        if name != rec.role {
+            rec.instance = Some(name.into());
         }
-        "mixer" => {
-            if std::env::var("NO_COLOR").is_ok() {
-                "[MIX ]".into()
-            } else {
-                "[MIX ]".magenta().to_string()
-            }
+        write_record(&rec);
+        return;
+    }
+    let Some(rec) = rec else {
+        println_tag(tag, line);
+        return;
+    };
+    // Keep the child's own timestamp: "2025-01-01T12:00:00.000Z" -> "12:00:00.000Z".
+    let t = rec.ts.split_once('T').map_or(rec.ts.as_str(), |(_, t)| t);
+    if let Some(mirror) = MIRROR.get() {
+        mirror(&strip_ansi(&format!("[{t}] {tag} {}", rec.msg)));
+    }
+    let line = if std::env::var("NO_COLOR").is_ok() {
+        format!("[{t}] {tag} {}", rec.msg)
+    } else {
+        let msg = match rec.level.as_str() {
+            "error" => rec.msg.red().to_string(),
+            "warn" => rec.msg.yellow().to_string(),
+            "debug" => rec.msg.dimmed().to_string(),
+            _ => rec.msg.clone(),
+        };
+        format!("{} {} {}", format!("[{t}]").dimmed(), tag.bold(), msg)
+    };
+    console_line(&line);
+}
+
+/// Drop ANSI escape sequences (colors) from `s`.
+pub fn strip_ansi(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\x1b' {
+            out.push(c);
+            continue;
         }
-        "sink" => {
-            if std::env::var("NO_COLOR").is_ok() {
-                "[SINK]".into()
-            } else {
-                "[SINK]".blue().to_string()
+        // CSI: ESC [ params... final byte in @..~
+        if chars.next() == Some('[') {
+            for c in chars.by_ref() {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
             }
         }
-        _ => "[b2b]".into(),
+    }
+    out
+}
+
+pub fn role_tag(role: &str) -> String {
+    let label = match role.to_ascii_lowercase().as_str() {
+        "orchestrator" => "[ORCH]",
+        "source" => "[SRC ]",
+        "mixer" => "[MIX ]",
+        "sink" => "[SINK]",
+        _ => return "[b2b]".into(),
+    };
+    paint(role, label)
+}
+
+/// Tag for a named role instance, colored by its kind. Instances named after
+/// their kind (single-pipeline plans) keep the plain role tag.
+pub fn instance_tag(kind: &str, name: &str) -> String {
+    if name.eq_ignore_ascii_case(kind) {
+        return role_tag(kind);
+    }
+    paint(kind, &format!("[{}]", name.to_ascii_uppercase()))
+}
+
+fn paint(role: &str, label: &str) -> String {
+    if std::env::var("NO_COLOR").is_ok() {
+        return label.into();
+    }
+    // Color palette aligned with HLD.
+    match role.to_ascii_lowercase().as_str() {
+        "orchestrator" => label.yellow().to_string(),
+        "source" => label.green().to_string(),
+        "mixer" => label.magenta().to_string(),
+        "sink" => label.blue().to_string(),
+        _ => label.into(),
     }
 }
 
 pub fn ready_line(role: &str, sip: &str, codec: &str, ptime_ms: u32) {
     // READY lines are machine-parsed by the orchestrator; keep them on stdout but flush.
+    let text = format!("READY role={role} sip={sip} codec={codec} ptime={ptime_ms}ms");
+    let line = if json() {
+        let fields = serde_json::json!({
+            "role": role, "sip": sip, "codec": codec, "ptime_ms": ptime_ms,
+        });
+        Record::new("ready", &text, fields).json_line()
+    } else {
+        text
+    };
     let mut o = stdout().lock();
-    let _ = writeln!(
-        o,
-        "READY role={role} sip={sip} codec={codec} ptime={ptime_ms}ms"
-    );
+    let _ = writeln!(o, "{line}");
     let _ = o.flush();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn record_round_trips_through_json() {
+        let rec = Record {
+            ts: "2025-01-01T12:00:00.000Z".into(),
+            role: "sink".into(),
+            instance: Some("sink-b".into()),
+            pid: 4242,
+            level: "warn".into(),
+            event: "metrics".into(),
+            msg: "warning: late packet".into(),
+            fields: json!({"late": 3, "codec": "pcmu"})
+                .as_object()
+                .cloned()
+                .unwrap(),
+        };
+        let back = parse_record(&rec.json_line()).expect("a JSON record");
+        assert_eq!(back.json_line(), rec.json_line());
+        assert_eq!(back.instance.as_deref(), Some("sink-b"));
+        assert_eq!(back.fields["late"], 3);
+
+        // Children never set `instance`, and `fields` may be left out.
+        let child = r#"{"ts":"t","role":"source","pid":1,"level":"info","event":"log","msg":"hi"}"#;
+        let rec = parse_record(child).expect("a JSON record");
+        assert!(rec.instance.is_none() && rec.fields.is_empty());
+        assert!(!rec.json_line().contains("instance"));
+    }
+
+    #[test]
+    fn plain_text_is_not_a_record() {
+        assert!(parse_record("READY role=sink sip=udp:5062").is_none());
+        assert!(parse_record("{not json").is_none());
+        assert!(parse_record(r#"{"msg":"missing fields"}"#).is_none());
+    }
+
+    #[test]
+    fn strip_ansi_drops_color_codes_only() {
+        assert_eq!(strip_ansi("plain [text] ~"), "plain [text] ~");
+        assert_eq!(
+            strip_ansi("\x1b[1m\x1b[31merror:\x1b[0m boom"),
+            "error: boom"
+        );
+        assert_eq!(strip_ansi("\x1b[38;5;208mé\x1b[39m"), "é");
+        // A lone ESC at the end is dropped, not kept half-parsed.
+        assert_eq!(strip_ansi("tail\x1b"), "tail");
+    }
+
+    #[test]
+    fn level_follows_the_text_prefix() {
+        assert_eq!(level_of("error: no route"), "error");
+        assert_eq!(level_of("panic: at src/x.rs"), "error");
+        assert_eq!(level_of("warning: slow"), "warn");
+        assert_eq!(level_of("INVITE sent"), "info");
+    }
+
+    static LINES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    #[test]
+    fn forward_renders_json_lines_and_wraps_plain_ones() {
+        set_console(Some(Box::new(|line: &str| {
+            LINES.lock().unwrap().push(strip_ansi(line));
+        })));
+        let tag = "[FWD-TEST]";
+        let child = r#"{"ts":"2025-01-01T12:34:56.789Z","role":"sink","pid":7,"level":"error","event":"log","msg":"error: no audio"}"#;
+        forward(tag, "sink", "sink", 7, child);
+        forward(tag, "sink", "sink", 7, "plain line");
+        set_console(None);
+
+        let lines: Vec<String> = LINES
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|l| l.contains(tag))
+            .cloned()
+            .collect();
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        // The child's own timestamp is kept and only its text is shown.
+        assert_eq!(lines[0], "[12:34:56.789Z] [FWD-TEST] error: no audio");
+        // Plain text gets the orchestrator's timestamp.
+        assert!(lines[1].starts_with('[') && lines[1].ends_with("Z] [FWD-TEST] plain line"));
+    }
+}
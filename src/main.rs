@@ -1,23 +1,45 @@
 mod cli;
+mod error;
 mod logging;
 mod media;
+mod metrics;
 mod orchestrator;
+mod playlist;
 mod roles;
 mod sip;
 mod sip_shim;
 mod util;
 
-use anyhow::Result;
 use clap::Parser;
+use std::process::ExitCode;
 
-fn main() -> Result<()> {
-    let args = cli::Cli::parse();
+fn main() -> ExitCode {
+    let args = match cli::Cli::try_parse() {
+        Ok(args) => args,
+        // --help and --version
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            let _ = e.print();
+            return ExitCode::from(error::EXIT_USAGE);
+        }
+    };
     logging::init(&args);
+    let tag = logging::role_tag(&format!("{:?}", args.role));
+    error::install_panic_hook(tag.clone());
 
-    match args.role {
+    let res = match args.role {
         cli::RoleKind::Orchestrator => orchestrator::run(&args),
         cli::RoleKind::Source => roles::source::run(&args),
         cli::RoleKind::Sink => roles::sink::run(&args),
         cli::RoleKind::Mixer => roles::mixer::run(&args),
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // Children log the terminal error; the orchestrator keeps the
+            // last one for its failure summary.
+            logging::println_tag(&tag, &format!("error: {e:#}"));
+            ExitCode::from(error::exit_code(&e))
+        }
     }
 }
@@ -1,76 +1,1473 @@
-use anyhow::{Context, Result};
-use std::{fs::File, path::Path};
+use crate::{
+    cli::ResampleQuality,
+    playlist::{Item, Playlist},
+};
+use anyhow::{Context, Result, anyhow, bail};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Condvar, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
 use symphonia::core::{
-    audio::SampleBuffer, codecs::DecoderOptions, formats::FormatOptions, io::MediaSourceStream,
-    meta::MetadataOptions, probe::Hint,
+    audio::SampleBuffer,
+    codecs::{CODEC_TYPE_AAC, CODEC_TYPE_ALAC, CODEC_TYPE_NULL, CODEC_TYPE_OPUS, CodecType},
+    codecs::{Decoder, DecoderOptions},
+    errors::Error,
+    formats::{FormatOptions, FormatReader, SeekMode, SeekTo},
+    io::MediaSourceStream,
+    meta::MetadataOptions,
+    probe::Hint,
+    units::Time,
 };
 
-pub struct PcmFrames {
-    pub data: Vec<i16>, // interleaved mono
-    pub sample_rate: u32,
-}
-
-// Decode an MP3 file fully to 8 kHz mono PCM with simple linear resample.
-pub fn decode_mp3_to_pcm_8k<P: AsRef<Path>>(path: P) -> Result<PcmFrames> {
-    let file = File::open(&path)
-        .with_context(|| format!("open audio file: {}", path.as_ref().display()))?;
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-    let mut hint = Hint::new();
-    hint.with_extension("mp3");
-    let fmt_opts = FormatOptions::default();
-    let meta_opts = MetadataOptions::default();
-    let probed = symphonia::default::get_probe().format(&hint, mss, &fmt_opts, &meta_opts)?;
-    let mut format = probed.format;
-    let track = format.default_track().context("no default track")?;
-    let dec_opts = DecoderOptions::default();
-    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &dec_opts)?;
-    let src_rate = track.codec_params.sample_rate.unwrap_or(48000) as usize;
-    let channels = track.codec_params.channels.map(|c| c.count()).unwrap_or(1);
-    let mut pcm: Vec<i16> = Vec::new();
-
-    while let Ok(p) = format.next_packet() {
-        let packet = p;
-        let decoded = decoder.decode(&packet)?;
-        let mut buf = SampleBuffer::<i16>::new(decoded.capacity() as u64, *decoded.spec());
-        buf.copy_interleaved_ref(decoded);
-        // Downmix to mono
-        if channels > 1 {
-            for frame in buf.samples().chunks_exact(channels) {
-                let sum: i32 = frame.iter().map(|&s| s as i32).sum();
-                pcm.push((sum / channels as i32) as i16);
-            }
-        } else {
-            pcm.extend_from_slice(buf.samples());
-        }
-    }
-
-    // Resample to 8kHz using simple linear interpolation
-    let dst_rate = 8000usize;
-    if src_rate == dst_rate {
-        return Ok(PcmFrames {
-            data: pcm,
-            sample_rate: 8000,
+/// Damaged packets in a row after which an entry is given up on.
+const MAX_BAD_RUN: u32 = 64;
+
+/// What this build decodes, for "unsupported" errors.
+const SUPPORTED: &str =
+    "WAV (PCM, A-law, µ-law, ADPCM), FLAC, Ogg/Vorbis, MKV, MP3 and AAC (ADTS, MP4/M4A)";
+
+/// An audio file decoded packet by packet to mono at a chosen rate. The container is
+/// probed by content, with the file extension as a hint. Sample rate and
+/// channel count are taken per packet, so streams that change either
+/// mid-way (e.g. chained Ogg) are downmixed and resampled piece by piece.
+pub struct Stream {
+    path: PathBuf,
+    format: Box<dyn FormatReader>,
+    track_id: u32,
+    decoder: Box<dyn Decoder>,
+    /// Output rate in Hz.
+    rate: u32,
+    quality: ResampleQuality,
+    resampler: Resampler,
+    mono: Vec<i16>,
+    /// Whether anything decoded since the last (re)start.
+    decoded: bool,
+    /// Damaged packets dropped since the last `take_damage`, the first
+    /// one's error, and how many failed in a row.
+    bad: u64,
+    first_bad: Option<String>,
+    bad_run: u32,
+}
+
+impl Stream {
+    pub fn open<P: AsRef<Path>>(path: P, rate: u32, quality: ResampleQuality) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("open audio file: {}", path.display()))?;
+        let mss = MediaSourceStream::new(Box::new(file), Default::default());
+        let mut hint = Hint::new();
+        if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+            hint.with_extension(ext);
+        }
+        // Trim encoder delay and padding so playlist entries join cleanly.
+        let fmt_opts = FormatOptions {
+            enable_gapless: true,
+            ..Default::default()
+        };
+        let meta_opts = MetadataOptions::default();
+        let probed = symphonia::default::get_probe()
+            .format(&hint, mss, &fmt_opts, &meta_opts)
+            .map_err(|e| match e {
+                Error::Unsupported(_) => unsupported_format(path),
+                e => anyhow!(e).context(format!("reading {}", path.display())),
+            })?;
+        let format = probed.format;
+        let (track_id, decoder) = open_track(path, format.as_ref())?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            format,
+            track_id,
+            decoder,
+            rate,
+            quality,
+            resampler: Resampler::new(rate, rate, quality),
+            mono: Vec::new(),
+            decoded: false,
+            bad: 0,
+            first_bad: None,
+            bad_run: 0,
+        })
+    }
+
+    /// Decode the next packet, appending its samples to `out`. Returns
+    /// false at the end of the stream, after appending the resampler's tail.
+    /// Damaged packets are dropped and counted (see `take_damage`); read
+    /// errors, or too many damaged packets in a row, fail the stream.
+    pub fn next_into(&mut self, out: &mut Vec<i16>) -> Result<bool> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(p) => p,
+                Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => {
+                    self.resampler.flush(out);
+                    return Ok(false);
+                }
+                // The track list changed (chained stream): go on with the
+                // new first audio track.
+                Err(Error::ResetRequired) => {
+                    (self.track_id, self.decoder) = open_track(&self.path, self.format.as_ref())?;
+                    continue;
+                }
+                Err(Error::DecodeError(e)) => {
+                    self.bad_packet(e)?;
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("reading {}", self.path.display()));
+                }
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(d) => d,
+                // A corrupt packet; the next one may decode. The decoder only
+                // reads the packet in memory, so a read error means a
+                // truncated packet rather than a failing file.
+                Err(Error::DecodeError(e)) => {
+                    self.bad_packet(e)?;
+                    continue;
+                }
+                Err(Error::IoError(e)) => {
+                    self.bad_packet(&e.to_string())?;
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("decoding {}", self.path.display()));
+                }
+            };
+            let spec = *decoded.spec();
+            if spec.rate != self.resampler.src_rate() {
+                self.resampler.flush(out);
+                self.resampler = Resampler::new(spec.rate, self.rate, self.quality);
+            }
+            let channels = spec.channels.count().max(1);
+            let mut buf = SampleBuffer::<i16>::new(decoded.capacity() as u64, spec);
+            buf.copy_interleaved_ref(decoded);
+            // Downmix to mono
+            self.mono.clear();
+            if channels > 1 {
+                for frame in buf.samples().chunks_exact(channels) {
+                    let sum: i32 = frame.iter().map(|&s| s as i32).sum();
+                    self.mono.push((sum / channels as i32) as i16);
+                }
+            } else {
+                self.mono.extend_from_slice(buf.samples());
+            }
+            self.resampler.process(&self.mono, out);
+            self.decoded = true;
+            self.bad_run = 0;
+            return Ok(true);
+        }
+    }
+
+    fn bad_packet(&mut self, error: &str) -> Result<()> {
+        self.bad += 1;
+        self.bad_run += 1;
+        self.first_bad.get_or_insert_with(|| error.to_string());
+        if self.bad_run >= MAX_BAD_RUN {
+            bail!(
+                "{}: {} damaged packets in a row ({error})",
+                self.path.display(),
+                self.bad_run
+            );
+        }
+        Ok(())
+    }
+
+    /// Damaged packets dropped since the last call, with the first error.
+    pub fn take_damage(&mut self) -> Option<(u64, String)> {
+        let bad = std::mem::take(&mut self.bad);
+        let first = self.first_bad.take()?;
+        Some((bad, first))
+    }
+
+    /// Go back to the start of the file. Seeks where the format allows,
+    /// otherwise reopens it.
+    pub fn rewind(&mut self) -> Result<()> {
+        let to = SeekTo::Time {
+            time: Time::default(),
+            track_id: Some(self.track_id),
+        };
+        if self.format.seek(SeekMode::Coarse, to).is_err() {
+            *self = Self::open(&self.path, self.rate, self.quality)?;
+            return Ok(());
+        }
+        self.decoder.reset();
+        self.resampler.reset();
+        self.decoded = false;
+        self.bad_run = 0;
+        Ok(())
+    }
+}
+
+/// First track with a known codec, and a decoder for it.
+fn open_track(path: &Path, format: &dyn FormatReader) -> Result<(u32, Box<dyn Decoder>)> {
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .with_context(|| format!("{}: no audio track", path.display()))?;
+    let decoder = symphonia::default::get_codecs()
+        .make(&track.codec_params, &DecoderOptions::default())
+        .map_err(|e| match e {
+            Error::Unsupported(_) => anyhow!(
+                "{}: {} audio is not supported (supported: {SUPPORTED})",
+                path.display(),
+                codec_name(track.codec_params.codec)
+            ),
+            e => anyhow!(e).context(format!("decoding {}", path.display())),
+        })?;
+    Ok((track.id, decoder))
+}
+
+fn unsupported_format(path: &Path) -> anyhow::Error {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+    let what = match ext.as_str() {
+        "" => "unrecognized audio format".to_string(),
+        _ => format!("unrecognized or unsupported audio format (.{ext})"),
+    };
+    anyhow!("{}: {what}; supported: {SUPPORTED}", path.display())
+}
+
+fn codec_name(codec: CodecType) -> String {
+    match codec {
+        CODEC_TYPE_AAC => "AAC".into(),
+        CODEC_TYPE_ALAC => "ALAC".into(),
+        CODEC_TYPE_OPUS => "Opus".into(),
+        c => format!("codec {c}"),
+    }
+}
+
+/// Zero crossings per side, Kaiser beta and cutoff (as a fraction of the
+/// lower Nyquist) for each quality level.
+fn filter_params(quality: ResampleQuality) -> (f64, f64, f64) {
+    match quality {
+        ResampleQuality::Fast => (8.0, 6.0, 0.90),
+        ResampleQuality::Medium => (16.0, 8.0, 0.94),
+        ResampleQuality::High => (32.0, 10.0, 0.97),
+    }
+}
+
+/// Most filter phases kept in a table. Rate pairs needing more (e.g.
+/// 44100 -> 7999) round the output position to the nearest of these.
+const MAX_PHASES: u64 = 1024;
+
+/// Band-limited streaming resampler between any two rates: a Kaiser-windowed
+/// sinc low-pass evaluated as a polyphase filter, so downsampling does not
+/// fold content above the new Nyquist into the band. State carries across
+/// `process` calls; `flush` emits the tail. Equal rates pass through.
+pub struct Resampler {
+    src: u32,
+    dst: u32,
+    /// `dst / src` in lowest terms: output `n` sits at input `n * down / up`.
+    up: u64,
+    down: u64,
+    /// Taps per side; each output reads `2 * half` inputs.
+    half: usize,
+    /// Phases in `table`, plus one row for a whole-sample offset.
+    rows: u64,
+    table: Vec<f32>,
+    hist: Vec<f32>,
+    /// Stream index of `hist[0]`; negative while the leading zeros last.
+    base: i64,
+    /// Input position of the next output: `idx + phase / up`.
+    idx: i64,
+    phase: u64,
+    /// Input samples taken since the last reset.
+    fed: i64,
+}
+
+impl Resampler {
+    pub fn new(src: u32, dst: u32, quality: ResampleQuality) -> Self {
+        let g = gcd(src.max(1) as u64, dst.max(1) as u64);
+        let (up, down) = (dst.max(1) as u64 / g, src.max(1) as u64 / g);
+        let (zero_crossings, beta, cutoff) = filter_params(quality);
+        // Cutoff relative to the input Nyquist.
+        let fc = cutoff * (up as f64 / down as f64).min(1.0);
+        let half = (zero_crossings / fc).ceil() as usize;
+        let rows = up.min(MAX_PHASES);
+        let taps = 2 * half;
+        let mut table = Vec::with_capacity((rows as usize + 1) * taps);
+        let i0_beta = bessel_i0(beta);
+        for row in 0..=rows {
+            let frac = row as f64 / rows as f64;
+            let start = table.len();
+            for j in 0..taps {
+                let x = j as f64 - (half as f64 - 1.0) - frac;
+                let w = (1.0 - (x / half as f64).powi(2)).max(0.0).sqrt();
+                table.push(fc * sinc(fc * x) * bessel_i0(beta * w) / i0_beta);
+            }
+            // Unity gain at DC for every phase.
+            let sum: f64 = table[start..].iter().sum();
+            for c in &mut table[start..] {
+                *c /= sum;
+            }
+        }
+        let mut r = Self {
+            src,
+            dst,
+            up,
+            down,
+            half,
+            rows,
+            table: table.into_iter().map(|c| c as f32).collect(),
+            hist: Vec::new(),
+            base: 0,
+            idx: 0,
+            phase: 0,
+            fed: 0,
+        };
+        r.reset();
+        r
+    }
+
+    pub fn src_rate(&self) -> u32 {
+        self.src
+    }
+
+    fn passthrough(&self) -> bool {
+        self.src == self.dst || self.src == 0
+    }
+
+    /// Forget all input, as if newly created.
+    pub fn reset(&mut self) {
+        self.hist.clear();
+        self.hist.resize(self.half - 1, 0.0);
+        self.base = 1 - self.half as i64;
+        self.idx = 0;
+        self.phase = 0;
+        self.fed = 0;
+    }
+
+    /// Resample `pcm`, appending what can be computed so far to `out`.
+    pub fn process(&mut self, pcm: &[i16], out: &mut Vec<i16>) {
+        if self.passthrough() {
+            out.extend_from_slice(pcm);
+            return;
+        }
+        self.hist.extend(pcm.iter().map(|&s| s as f32));
+        self.fed += pcm.len() as i64;
+        self.run(out, i64::MAX);
+    }
+
+    /// Emit the outputs still waiting for lookahead and reset.
+    pub fn flush(&mut self, out: &mut Vec<i16>) {
+        if self.passthrough() {
+            return;
+        }
+        self.hist.resize(self.hist.len() + self.half, 0.0);
+        self.run(out, self.fed);
+        self.reset();
+    }
+
+    /// Compute outputs whose taps are all buffered, up to input `limit`.
+    fn run(&mut self, out: &mut Vec<i16>, limit: i64) {
+        let taps = 2 * self.half;
+        let end = self.base + self.hist.len() as i64;
+        while self.idx + (self.half as i64) < end && self.idx < limit {
+            let start = (self.idx + 1 - self.half as i64 - self.base) as usize;
+            let row = ((self.phase * self.rows + self.up / 2) / self.up) as usize;
+            let coeffs = &self.table[row * taps..][..taps];
+            let v: f32 = coeffs
+                .iter()
+                .zip(&self.hist[start..start + taps])
+                .map(|(c, s)| c * s)
+                .sum();
+            out.push(v.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16);
+            self.phase += self.down;
+            self.idx += (self.phase / self.up) as i64;
+            self.phase %= self.up;
+        }
+        // Drop input no later output reads.
+        let used = (self.idx + 1 - self.half as i64 - self.base).clamp(0, self.hist.len() as i64);
+        self.hist.drain(..used as usize);
+        self.base += used;
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-9 {
+        1.0
+    } else {
+        let px = std::f64::consts::PI * x;
+        px.sin() / px
+    }
+}
+
+/// Modified Bessel function of the first kind, order zero (power series).
+fn bessel_i0(x: f64) -> f64 {
+    let (mut sum, mut term, q) = (1.0, 1.0, x * x / 4.0);
+    for k in 1..64 {
+        term *= q / (k * k) as f64;
+        sum += term;
+        if term < sum * 1e-12 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Something the send loop reports once playback reaches it.
+pub enum Notice {
+    /// Playlist entry `index` (1-based, in play order) of `total` started.
+    Started {
+        index: usize,
+        total: usize,
+        path: PathBuf,
+        title: Option<String>,
+        /// Passes through the playlist completed before this one.
+        pass: u64,
+    },
+    /// An entry could not be played and was left out.
+    Skipped { path: PathBuf, error: String },
+    /// An entry played with `packets` damaged packets dropped.
+    Damaged {
+        path: PathBuf,
+        packets: u64,
+        error: String,
+    },
+}
+
+/// A decoder thread filling a bounded ring of mono samples from a
+/// playlist, read a frame at a time by the send loop. Only `capacity_ms`
+/// of audio is ever held, however long the files.
+pub struct Feed {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+struct Shared {
+    ring: Mutex<Ring>,
+    cond: Condvar,
+    underruns: Arc<AtomicU64>,
+}
+
+impl Shared {
+    fn new(cap: usize) -> Self {
+        Self {
+            ring: Mutex::new(Ring {
+                buf: VecDeque::with_capacity(cap),
+                cap,
+                read: 0,
+                notices: VecDeque::new(),
+                ended: false,
+                error: None,
+                stop: false,
+            }),
+            cond: Condvar::new(),
+            underruns: Arc::default(),
+        }
+    }
+}
+
+struct Ring {
+    buf: VecDeque<i16>,
+    cap: usize,
+    /// Samples taken by `fill` so far.
+    read: u64,
+    /// Notices keyed by the stream position they apply from.
+    notices: VecDeque<(u64, Notice)>,
+    /// The decoder finished (not looping) or failed.
+    ended: bool,
+    error: Option<String>,
+    stop: bool,
+}
+
+/// How the decoder thread moves through the playlist.
+pub struct FeedOptions {
+    pub rate: u32,
+    pub capacity_ms: u32,
+    pub quality: ResampleQuality,
+    /// Equal-power overlap between entries; 0 plays them back to back.
+    pub crossfade_ms: u32,
+    /// Start the playlist over at its end.
+    pub looping: bool,
+}
+
+impl Feed {
+    /// Open the first entry (so a bad single file fails here) and start
+    /// decoding.
+    pub fn start(mut playlist: Playlist, opts: FeedOptions) -> Result<Self> {
+        let order = playlist.next_pass();
+        let first = match Stream::open(&playlist.items()[order[0]].path, opts.rate, opts.quality) {
+            Err(e) if playlist.items().len() == 1 => return Err(e),
+            first => first,
+        };
+        let cap = (opts.capacity_ms as usize * opts.rate as usize / 1000).max(1);
+        let shared = Arc::new(Shared::new(cap));
+        let thread = {
+            let shared = Arc::clone(&shared);
+            std::thread::Builder::new()
+                .name("decode".into())
+                .spawn(move || {
+                    let mut player = Player {
+                        shared: &shared,
+                        xfade: opts.crossfade_ms as usize * opts.rate as usize / 1000,
+                        tail: VecDeque::new(),
+                        written: 0,
+                        out: Vec::new(),
+                    };
+                    let res = player.run(playlist, order, first, &opts);
+                    let mut r = shared.ring.lock().unwrap();
+                    r.ended = true;
+                    r.error = res.err().map(|e| format!("{e:#}"));
+                    shared.cond.notify_all();
+                })
+                .context("spawn decoder thread")?
+        };
+        Ok(Self {
+            shared,
+            thread: Some(thread),
+        })
+    }
+
+    /// Wait until `samples` are buffered (or decoding ended), at most
+    /// `timeout`. Returns the number buffered.
+    pub fn wait_for(&self, samples: usize, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let mut r = self.shared.ring.lock().unwrap();
+        let want = samples.min(r.cap);
+        while r.buf.len() < want && !r.ended {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            r = self.shared.cond.wait_timeout(r, left).unwrap().0;
+        }
+        r.buf.len()
+    }
+
+    /// Fill `frame` from the ring. A short read while the decoder is still
+    /// running is padded with silence and counted as an underrun. Returns
+    /// false once decoding has ended and the ring is empty.
+    pub fn fill(&self, frame: &mut [i16]) -> bool {
+        let mut r = self.shared.ring.lock().unwrap();
+        let n = frame.len().min(r.buf.len());
+        if n == 0 && r.ended {
+            return false;
+        }
+        for (slot, s) in frame.iter_mut().zip(r.buf.drain(..n)) {
+            *slot = s;
+        }
+        frame[n..].fill(0);
+        r.read += n as u64;
+        if n < frame.len() && !r.ended {
+            self.shared.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+        self.shared.cond.notify_all();
+        true
+    }
+
+    /// Notices whose position `fill` has reached.
+    pub fn notices(&self) -> Vec<Notice> {
+        let mut r = self.shared.ring.lock().unwrap();
+        let mut due = Vec::new();
+        while r.notices.front().is_some_and(|(at, _)| *at <= r.read) {
+            due.extend(r.notices.pop_front().map(|(_, n)| n));
+        }
+        due
+    }
+
+    /// Count of frames padded with silence because the decoder fell
+    /// behind, readable from other threads.
+    pub fn underrun_counter(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.shared.underruns)
+    }
+
+    /// Why decoding stopped early, if it failed.
+    pub fn error(&self) -> Option<String> {
+        self.shared.ring.lock().unwrap().error.clone()
+    }
+}
+
+impl Drop for Feed {
+    fn drop(&mut self) {
+        self.shared.ring.lock().unwrap().stop = true;
+        self.shared.cond.notify_all();
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+/// Decoder-thread state: entries are decoded one after another into the
+/// ring, holding back the last `xfade` samples to overlap with the next.
+struct Player<'a> {
+    shared: &'a Shared,
+    xfade: usize,
+    tail: VecDeque<i16>,
+    /// Samples handed to the ring so far.
+    written: u64,
+    out: Vec<i16>,
+}
+
+impl Player<'_> {
+    fn run(
+        &mut self,
+        mut playlist: Playlist,
+        mut order: Vec<usize>,
+        first: Result<Stream>,
+        opts: &FeedOptions,
+    ) -> Result<()> {
+        let total = order.len();
+        let mut next = Some(first);
+        let mut pass = 0u64;
+        // Entries that failed once are left out of later passes.
+        let mut broken = vec![false; total];
+        loop {
+            let mut played = None;
+            let mut last_err = None;
+            for (k, &i) in order.iter().enumerate() {
+                if broken[i] {
+                    continue;
+                }
+                let item = &playlist.items()[i];
+                let opened = next
+                    .take()
+                    .unwrap_or_else(|| Stream::open(&item.path, opts.rate, opts.quality));
+                let res = opened.and_then(|mut s| {
+                    let alive = self.play(&mut s, k + 1, total, item, pass)?;
+                    Ok((s, alive))
+                });
+                match res {
+                    Ok((_, false)) => return Ok(()),
+                    Ok((s, true)) => played = Some(s),
+                    Err(e) => {
+                        let error = format!("{e:#}");
+                        let path = item.path.clone();
+                        self.notice(0, Notice::Skipped { path, error });
+                        broken[i] = true;
+                        last_err = Some(e);
+                    }
+                }
+            }
+            let Some(mut last) = played else {
+                return Err(last_err.unwrap_or_else(|| anyhow!("no playable entries left")));
+            };
+            if !opts.looping {
+                let tail: Vec<i16> = self.tail.drain(..).collect();
+                self.push(&tail);
+                return Ok(());
+            }
+            order = playlist.next_pass();
+            pass += 1;
+            // A single entry loops by seeking back rather than reopening.
+            if total == 1 {
+                last.rewind()?;
+                next = Some(Ok(last));
+            }
+        }
+    }
+
+    /// Decode one entry into the ring, crossfading its start with the held
+    /// back tail. An entry shorter than the tail shortens the fade to its
+    /// length. Returns false if the feed was dropped.
+    fn play(
+        &mut self,
+        stream: &mut Stream,
+        index: usize,
+        total: usize,
+        item: &Item,
+        pass: u64,
+    ) -> Result<bool> {
+        // Read ahead over the fade so its length is known before mixing.
+        let mut chunk = Vec::new();
+        let mut more = true;
+        while more && chunk.len() < self.tail.len() {
+            more = stream.next_into(&mut chunk)?;
+        }
+        // Too short for the whole fade: the old entry plays on until the
+        // shortened one starts.
+        let keep = self.tail.len().saturating_sub(chunk.len());
+        if !self.push_tail(keep) {
+            return Ok(false);
+        }
+        let started = Notice::Started {
+            index,
+            total,
+            path: item.path.clone(),
+            title: item.title.clone(),
+            pass,
+        };
+        self.notice(self.written, started);
+        let fade_len = self.tail.len();
+        let mut fade_pos = 0usize;
+        let mut decoded = 0usize;
+        loop {
+            decoded += chunk.len();
+            for &s in &chunk {
+                if fade_pos < fade_len {
+                    let t = (fade_pos as f32 + 0.5) / fade_len as f32 * std::f32::consts::FRAC_PI_2;
+                    let old = self.tail[fade_pos] as f32;
+                    let v = old * t.cos() + s as f32 * t.sin();
+                    self.tail[fade_pos] = v.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+                    fade_pos += 1;
+                } else {
+                    self.tail.push_back(s);
+                }
+            }
+            let over = self.tail.len().saturating_sub(self.xfade);
+            if !self.push_tail(over) {
+                return Ok(false);
+            }
+            if !more {
+                break;
+            }
+            chunk.clear();
+            more = stream.next_into(&mut chunk)?;
+        }
+        if let Some((packets, error)) = stream.take_damage() {
+            let path = item.path.clone();
+            self.notice(
+                self.written,
+                Notice::Damaged {
+                    path,
+                    packets,
+                    error,
+                },
+            );
+        }
+        if decoded == 0 {
+            bail!("{}: no audio decoded", item.path.display());
+        }
+        Ok(true)
+    }
+
+    /// Move the first `n` held back samples to the ring.
+    fn push_tail(&mut self, n: usize) -> bool {
+        if n == 0 {
+            return true;
+        }
+        let mut out = std::mem::take(&mut self.out);
+        out.clear();
+        out.extend(self.tail.drain(..n));
+        let alive = self.push(&out);
+        self.out = out;
+        alive
+    }
+
+    fn notice(&self, at: u64, notice: Notice) {
+        let mut r = self.shared.ring.lock().unwrap();
+        r.notices.push_back((at, notice));
+    }
+
+    /// Append to the ring, blocking while it is full. False once stopped.
+    fn push(&mut self, mut rest: &[i16]) -> bool {
+        while !rest.is_empty() {
+            let mut r = self.shared.ring.lock().unwrap();
+            while r.buf.len() >= r.cap && !r.stop {
+                r = self.shared.cond.wait(r).unwrap();
+            }
+            if r.stop {
+                return false;
+            }
+            let n = (r.cap - r.buf.len()).min(rest.len());
+            r.buf.extend(&rest[..n]);
+            rest = &rest[n..];
+            self.written += n as u64;
+            self.shared.cond.notify_all();
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const QUALITIES: [ResampleQuality; 3] = [
+        ResampleQuality::Fast,
+        ResampleQuality::Medium,
+        ResampleQuality::High,
+    ];
+
+    fn resample(pcm: &[i16], src: u32, dst: u32, quality: ResampleQuality) -> Vec<i16> {
+        let mut r = Resampler::new(src, dst, quality);
+        let mut out = Vec::new();
+        r.process(pcm, &mut out);
+        r.flush(&mut out);
+        out
+    }
+
+    fn tone(hz: f64, rate: u32, secs: f64, amp: f64) -> Vec<i16> {
+        let n = (rate as f64 * secs) as usize;
+        (0..n)
+            .map(|i| {
+                (amp * (2.0 * std::f64::consts::PI * hz * i as f64 / rate as f64).sin()) as i16
+            })
+            .collect()
+    }
+
+    /// RMS of `pcm` with the filter's settling time trimmed off both ends.
+    fn rms(pcm: &[i16]) -> f64 {
+        let body = &pcm[pcm.len() / 8..pcm.len() * 7 / 8];
+        (body.iter().map(|&s| (s as f64).powi(2)).sum::<f64>() / body.len() as f64).sqrt()
+    }
+
+    fn db(ratio: f64) -> f64 {
+        20.0 * ratio.max(1e-12).log10()
+    }
+
+    #[test]
+    fn equal_rates_pass_through() {
+        let pcm = tone(440.0, 8000, 0.1, 10000.0);
+        assert_eq!(resample(&pcm, 8000, 8000, ResampleQuality::High), pcm);
+    }
+
+    #[test]
+    fn output_length_follows_rate_ratio() {
+        for (src, dst) in [(48000, 8000), (44100, 8000), (8000, 44100), (44100, 7999)] {
+            let out = resample(&vec![0; src as usize], src, dst, ResampleQuality::Fast);
+            assert_eq!(out.len(), dst as usize, "{src} -> {dst}");
+        }
+    }
+
+    #[test]
+    fn impulse_is_centred_symmetric_and_unity_gain() {
+        for q in QUALITIES {
+            let mut pcm = vec![0i16; 9600];
+            pcm[4800] = 30000;
+            let out = resample(&pcm, 48000, 8000, q);
+            let peak = (0..out.len()).max_by_key(|&i| out[i].abs()).unwrap();
+            assert_eq!(peak, 800, "{q:?}");
+            for d in 1..50 {
+                assert!((out[800 - d] - out[800 + d]).abs() <= 1, "{q:?} at {d}");
+            }
+            // An impulse carries its area into 1/6 as many samples.
+            let area: i64 = out.iter().map(|&s| s as i64).sum();
+            assert!((area - 5000).abs() < 50, "{q:?}: area {area}");
+        }
+    }
+
+    #[test]
+    fn upsampled_impulse_is_centred_with_its_area() {
+        let mut pcm = vec![0i16; 800];
+        pcm[400] = 20000;
+        let out = resample(&pcm, 8000, 48000, ResampleQuality::Medium);
+        let peak = (0..out.len()).max_by_key(|&i| out[i].abs()).unwrap();
+        assert_eq!(peak, 2400);
+        for d in 1..100 {
+            assert!((out[2400 - d] - out[2400 + d]).abs() <= 1, "at {d}");
+        }
+        // One input sample spreads over six outputs at unity DC gain.
+        let area: i64 = out.iter().map(|&s| s as i64).sum();
+        assert!((area - 120_000).abs() < 600, "area {area}");
+    }
+
+    #[test]
+    fn step_settles_to_its_level() {
+        for q in QUALITIES {
+            let pcm: Vec<i16> = (0..16000)
+                .map(|i| if i < 8000 { 0 } else { 10000 })
+                .collect();
+            let out = resample(&pcm, 16000, 8000, q);
+            let r = Resampler::new(16000, 8000, q);
+            // Outputs further than the filter reach from the edge.
+            let settle = r.half / 2 + 1;
+            assert!(out[..4000 - settle].iter().all(|&s| s.abs() <= 1), "{q:?}");
+            assert!(
+                out[4000 + settle..7000]
+                    .iter()
+                    .all(|&s| (s - 10000).abs() <= 1),
+                "{q:?}"
+            );
+            // Gibbs ripple of a windowed sinc stays under 10%.
+            let max = *out.iter().max().unwrap();
+            assert!(max < 11000, "{q:?}: overshoot {max}");
+        }
+    }
+
+    #[test]
+    fn sweep_keeps_passband_and_rejects_aliases() {
+        // Top of the flat (±0.5 dB) band and minimum rejection above 4.8 kHz
+        // per level, at 44.1 kHz -> 8 kHz.
+        let limits = [(3000.0, 60.0), (3400.0, 80.0), (3600.0, 90.0)];
+        for (q, (flat_to, floor)) in QUALITIES.into_iter().zip(limits) {
+            let mut hz = 200.0;
+            while hz <= flat_to {
+                let pcm = tone(hz, 44100, 0.5, 16000.0);
+                let gain = db(rms(&resample(&pcm, 44100, 8000, q)) / rms(&pcm));
+                assert!(gain.abs() < 0.5, "{q:?} {hz} Hz: {gain:.2} dB");
+                hz += 200.0;
+            }
+            // Everything above 4 kHz would alias into the band.
+            let mut hz = 4800.0;
+            while hz < 22050.0 {
+                let pcm = tone(hz, 44100, 0.5, 16000.0);
+                let gain = db(rms(&resample(&pcm, 44100, 8000, q)) / rms(&pcm));
+                assert!(gain < -floor, "{q:?} {hz} Hz: {gain:.1} dB");
+                hz += 1100.0;
+            }
+        }
+    }
+
+    #[test]
+    fn chunked_input_matches_one_shot() {
+        let pcm = tone(1234.0, 44100, 0.3, 12000.0);
+        let whole = resample(&pcm, 44100, 8000, ResampleQuality::Medium);
+        let mut r = Resampler::new(44100, 8000, ResampleQuality::Medium);
+        let mut out = Vec::new();
+        for chunk in pcm.chunks(1152) {
+            r.process(chunk, &mut out);
+        }
+        r.flush(&mut out);
+        assert_eq!(out, whole);
+    }
+
+    /// One silent AAC-LC frame: a mono element with no scale factor bands.
+    const AAC_SILENCE: [u8; 4] = [0x00, 0x00, 0x00, 0x07];
+    /// AudioSpecificConfig for AAC-LC, 8 kHz, mono.
+    const AAC_CONFIG: [u8; 2] = [0x15, 0x88];
+
+    /// A frame claiming 63 scale factor bands, whose section data runs past
+    /// the end of the packet.
+    const AAC_DAMAGED: [u8; 4] = [0x00, 0x00, 0x1f, 0x80];
+
+    fn adts(frames: usize) -> Vec<u8> {
+        adts_frames(&vec![AAC_SILENCE; frames])
+    }
+
+    fn adts_frames(frames: &[[u8; 4]]) -> Vec<u8> {
+        let len = 7 + AAC_SILENCE.len();
+        let mut out = Vec::new();
+        for frame in frames {
+            // MPEG-4, no CRC, LC, 8 kHz, mono, full buffer.
+            out.extend_from_slice(&[0xff, 0xf1, 0x6c, 0x40]);
+            out.extend_from_slice(&[(len >> 3) as u8, ((len & 7) << 5) as u8 | 0x1f, 0xfc]);
+            out.extend_from_slice(frame);
+        }
+        out
+    }
+
+    fn mp4_box(kind: &[u8; 4], parts: &[&[u8]]) -> Vec<u8> {
+        let body = parts.concat();
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(&body);
+        out
+    }
+
+    /// Minimal M4A: one AAC track holding `frames` silent frames in one chunk.
+    fn m4a(frames: u32) -> Vec<u8> {
+        let be = |v: u32| v.to_be_bytes();
+        let zeros = [0u8; 24];
+        let matrix: Vec<u8> = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000]
+            .iter()
+            .flat_map(|v: &u32| v.to_be_bytes())
+            .collect();
+        let duration = frames * 1024;
+        let ftyp = mp4_box(b"ftyp", &[b"M4A ", &be(0), b"M4A mp42isom"]);
+        let esds = mp4_box(
+            b"esds",
+            &[
+                &be(0),
+                &[0x03, 25, 0, 0, 0],
+                &[0x04, 17, 0x40, 0x15, 0, 0, 0],
+                &be(0),
+                &be(0),
+                &[0x05, 2],
+                &AAC_CONFIG,
+                &[0x06, 1, 0x02],
+            ],
+        );
+        let mp4a = mp4_box(
+            b"mp4a",
+            &[
+                &zeros[..6],
+                &[0, 1],
+                &zeros[..8],
+                &[0, 1, 0, 16],
+                &zeros[..4],
+                &be(8000 << 16),
+                &esds,
+            ],
+        );
+        let moov = |offset: u32| {
+            let stbl = mp4_box(
+                b"stbl",
+                &[
+                    &mp4_box(b"stsd", &[&be(0), &be(1), &mp4a]),
+                    &mp4_box(b"stts", &[&be(0), &be(1), &be(frames), &be(1024)]),
+                    &mp4_box(b"stsc", &[&be(0), &be(1), &be(1), &be(frames), &be(1)]),
+                    &mp4_box(
+                        b"stsz",
+                        &[&be(0), &be(AAC_SILENCE.len() as u32), &be(frames)],
+                    ),
+                    &mp4_box(b"stco", &[&be(0), &be(1), &be(offset)]),
+                ],
+            );
+            let dinf = mp4_box(
+                b"dinf",
+                &[&mp4_box(
+                    b"dref",
+                    &[&be(0), &be(1), &mp4_box(b"url ", &[&be(1)])],
+                )],
+            );
+            let minf = mp4_box(
+                b"minf",
+                &[&mp4_box(b"smhd", &[&be(0), &be(0)]), &dinf, &stbl],
+            );
+            let mdhd = [be(0), be(0), be(0), be(8000), be(duration), be(0x55c4_0000)].concat();
+            let hdlr = [&be(0)[..], &be(0), b"soun", &zeros[..12], &[0]].concat();
+            let mdia = mp4_box(
+                b"mdia",
+                &[
+                    &mp4_box(b"mdhd", &[&mdhd]),
+                    &mp4_box(b"hdlr", &[&hdlr]),
+                    &minf,
+                ],
+            );
+            let tkhd = [
+                &be(7)[..],
+                &be(0),
+                &be(0),
+                &be(1),
+                &be(0),
+                &be(duration),
+                &zeros[..8],
+                &[0, 0, 0, 0, 1, 0, 0, 0],
+                &matrix,
+                &be(0),
+                &be(0),
+            ]
+            .concat();
+            let mvhd = [
+                &be(0)[..],
+                &be(0),
+                &be(0),
+                &be(8000),
+                &be(duration),
+                &be(0x10000),
+                &[1, 0],
+                &zeros[..10],
+                &matrix,
+                &zeros[..24],
+                &be(2),
+            ]
+            .concat();
+            let trak = mp4_box(b"trak", &[&mp4_box(b"tkhd", &[&tkhd]), &mdia]);
+            mp4_box(b"moov", &[&mp4_box(b"mvhd", &[&mvhd]), &trak])
+        };
+        let offset = (ftyp.len() + moov(0).len() + 8) as u32;
+        let data = AAC_SILENCE.repeat(frames as usize);
+        [ftyp, moov(offset), mp4_box(b"mdat", &[&data])].concat()
+    }
+
+    /// Decode `bytes` saved as `name` in a scratch file.
+    fn decode_file(name: &str, bytes: &[u8]) -> Result<Vec<i16>> {
+        decode_damaged(name, bytes).map(|(pcm, _)| pcm)
+    }
+
+    type Damage = Option<(u64, String)>;
+
+    fn decode_damaged(name: &str, bytes: &[u8]) -> Result<(Vec<i16>, Damage)> {
+        let path = std::env::temp_dir().join(format!("b2b-media-{}-{name}", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let decoded = Stream::open(&path, 8000, ResampleQuality::Fast).and_then(|mut s| {
+            let mut out = Vec::new();
+            while s.next_into(&mut out)? {}
+            Ok((out, s.take_damage()))
         });
+        let _ = std::fs::remove_file(&path);
+        decoded
+    }
+
+    #[test]
+    fn decodes_aac_in_adts_and_m4a() {
+        let pcm = decode_file("silence.aac", &adts(10)).unwrap();
+        assert_eq!(pcm.len(), 10 * 1024);
+        assert!(pcm.iter().all(|&s| s == 0));
+        // Probed by content: the extension is only a hint.
+        for name in ["silence.m4a", "silence.bin"] {
+            let pcm = decode_file(name, &m4a(10)).unwrap();
+            assert_eq!(pcm.len(), 10 * 1024, "{name}");
+            assert!(pcm.iter().all(|&s| s == 0), "{name}");
+        }
+    }
+
+    #[test]
+    fn unknown_content_names_supported_formats() {
+        let err = decode_file("noise.m4a", &[0x5a; 4096]).unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("unsupported audio format (.m4a)"), "{msg}");
+        assert!(msg.contains("AAC (ADTS, MP4/M4A)"), "{msg}");
+    }
+
+    #[test]
+    fn damaged_packets_are_dropped_and_counted() {
+        let mut frames = vec![AAC_SILENCE; 10];
+        frames[3] = AAC_DAMAGED;
+        frames[7] = AAC_DAMAGED;
+        let (pcm, damage) = decode_damaged("damaged.aac", &adts_frames(&frames)).unwrap();
+        assert_eq!(pcm.len(), 8 * 1024);
+        let (packets, error) = damage.unwrap();
+        assert_eq!(packets, 2);
+        assert!(!error.is_empty());
+
+        let (_, damage) = decode_damaged("clean.aac", &adts(4)).unwrap();
+        assert!(damage.is_none());
+    }
+
+    #[test]
+    fn a_run_of_damaged_packets_fails_the_stream() {
+        let mut frames = vec![AAC_SILENCE; 4];
+        frames.extend([AAC_DAMAGED; MAX_BAD_RUN as usize]);
+        frames.push(AAC_SILENCE);
+        let err = decode_file("ruined.aac", &adts_frames(&frames)).unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("64 damaged packets in a row"), "{msg}");
+    }
+
+    /// 16-bit mono PCM WAV at 8 kHz.
+    fn wav(pcm: &[i16]) -> Vec<u8> {
+        let data: Vec<u8> = pcm.iter().flat_map(|s| s.to_le_bytes()).collect();
+        riff_wav(1, 16, &data)
+    }
+
+    /// Mono WAV at 8 kHz holding `data` in `format` (1 PCM, 6 A-law,
+    /// 7 µ-law); formats other than PCM carry an empty extension.
+    fn riff_wav(format: u16, bits: u16, data: &[u8]) -> Vec<u8> {
+        let align = bits / 8;
+        let mut fmt = Vec::new();
+        for v in [format, 1] {
+            fmt.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [8000u32, 8000 * align as u32] {
+            fmt.extend_from_slice(&v.to_le_bytes());
+        }
+        for v in [align, bits] {
+            fmt.extend_from_slice(&v.to_le_bytes());
+        }
+        if format != 1 {
+            fmt.extend_from_slice(&0u16.to_le_bytes());
+        }
+        let mut out = Vec::new();
+        out.extend_from_slice(b"RIFF");
+        out.extend_from_slice(&(20 + fmt.len() as u32 + data.len() as u32).to_le_bytes());
+        out.extend_from_slice(b"WAVEfmt ");
+        out.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+        out.extend_from_slice(&fmt);
+        out.extend_from_slice(b"data");
+        out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        out.extend_from_slice(data);
+        out
+    }
+
+    /// MSB-first CRC with a zero start value: CRC-8 and CRC-16 of FLAC
+    /// frames, and the Ogg page checksum.
+    fn crc(bytes: &[u8], width: u32, poly: u64) -> u64 {
+        let mask = (1u64 << width) - 1;
+        let mut crc = 0u64;
+        for &b in bytes {
+            crc ^= (b as u64) << (width - 8);
+            for _ in 0..8 {
+                crc = if crc >> (width - 1) & 1 == 1 {
+                    (crc << 1) ^ poly
+                } else {
+                    crc << 1
+                } & mask;
+            }
+        }
+        crc
+    }
+
+    /// FLAC samples per frame in these fixtures.
+    const FLAC_BLOCK: usize = 256;
+
+    /// FLAC STREAMINFO block for 16-bit mono 8 kHz, `frames` blocks long.
+    fn flac_streaminfo(last: bool, frames: usize) -> Vec<u8> {
+        let mut b = vec![if last { 0x80 } else { 0x00 }, 0, 0, 34];
+        for _ in 0..2 {
+            b.extend_from_slice(&(FLAC_BLOCK as u16).to_be_bytes());
+        }
+        // Frame sizes unknown.
+        b.extend_from_slice(&[0; 6]);
+        let info = (8000u64 << 44) | (15 << 36) | (frames * FLAC_BLOCK) as u64;
+        b.extend_from_slice(&info.to_be_bytes());
+        // No MD5.
+        b.extend_from_slice(&[0; 16]);
+        b
+    }
+
+    /// One FLAC frame of `level` (a constant subframe). A damaged frame
+    /// sets the subframe's zero padding bit, which the decoder rejects.
+    fn flac_frame(n: u8, level: i16, damaged: bool) -> Vec<u8> {
+        // Fixed blocking, 256 samples, 8 kHz, mono, 16 bits, frame `n`.
+        let mut f = vec![0xff, 0xf8, 0x84, 0x08, n];
+        f.push(crc(&f, 8, 0x07) as u8);
+        f.push(if damaged { 0x80 } else { 0x00 });
+        f.extend_from_slice(&level.to_be_bytes());
+        let crc16 = crc(&f, 16, 0x8005) as u16;
+        f.extend_from_slice(&crc16.to_be_bytes());
+        f
+    }
+
+    fn flac(levels: &[i16]) -> Vec<u8> {
+        let mut out = b"fLaC".to_vec();
+        out.extend(flac_streaminfo(true, levels.len()));
+        for (n, &level) in levels.iter().enumerate() {
+            out.extend(flac_frame(n as u8, level, false));
+        }
+        out
+    }
+
+    /// One Ogg page of `packets` (each under 255 bytes).
+    fn ogg_page(flags: u8, granule: u64, seq: u32, packets: &[Vec<u8>]) -> Vec<u8> {
+        let mut page = b"OggS".to_vec();
+        page.extend_from_slice(&[0, flags]);
+        page.extend_from_slice(&granule.to_le_bytes());
+        page.extend_from_slice(&0x0b2b_u32.to_le_bytes());
+        page.extend_from_slice(&seq.to_le_bytes());
+        page.extend_from_slice(&[0; 4]);
+        page.push(packets.len() as u8);
+        page.extend(packets.iter().map(|p| p.len() as u8));
+        for p in packets {
+            page.extend_from_slice(p);
+        }
+        let sum = crc(&page, 32, 0x04c1_1db7) as u32;
+        page[22..26].copy_from_slice(&sum.to_le_bytes());
+        page
+    }
+
+    /// Ogg FLAC: the mapping header, a Vorbis comment and `frames` on the
+    /// last page.
+    fn ogg_flac(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut head = vec![0x7f];
+        head.extend_from_slice(b"FLAC");
+        // Mapping 1.0, one header packet after this one.
+        head.extend_from_slice(&[1, 0, 0, 1]);
+        head.extend_from_slice(b"fLaC");
+        head.extend(flac_streaminfo(false, frames.len()));
+        // Last block, VORBIS_COMMENT: no vendor string, no comments.
+        let comment = [0x84, 0, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0].to_vec();
+        let samples = (frames.len() * FLAC_BLOCK) as u64;
+        [
+            ogg_page(0x02, 0, 0, &[head]),
+            ogg_page(0x00, 0, 1, &[comment]),
+            ogg_page(0x04, samples, 2, frames),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn decodes_g711_wav() {
+        // µ-law: 0xff is zero, 0x80/0x00 the extremes; A-law has no zero
+        // (0xd5/0x55 are +-8) and 0xaa/0x2a are its extremes.
+        let pcm = decode_file("mulaw.wav", &riff_wav(7, 8, &[0xff, 0x80, 0x00])).unwrap();
+        assert_eq!(pcm, [0, 32124, -32124]);
+        let pcm = decode_file("alaw.wav", &riff_wav(6, 8, &[0xd5, 0x55, 0xaa, 0x2a])).unwrap();
+        assert_eq!(pcm, [8, -8, 32256, -32256]);
+    }
+
+    #[test]
+    fn decodes_flac_native_and_in_ogg() {
+        let levels = [1000, -2000, 3000];
+        let want: Vec<i16> = levels.iter().flat_map(|&l| [l; FLAC_BLOCK]).collect();
+        assert_eq!(decode_file("levels.flac", &flac(&levels)).unwrap(), want);
+        let frames: Vec<Vec<u8>> = (0..3)
+            .map(|n| flac_frame(n as u8, levels[n], false))
+            .collect();
+        assert_eq!(decode_file("levels.ogg", &ogg_flac(&frames)).unwrap(), want);
+    }
+
+    #[test]
+    fn damaged_ogg_packets_are_dropped_until_a_run_fails_the_stream() {
+        let frames: Vec<Vec<u8>> = (0..6)
+            .map(|n| flac_frame(n, 1000, n == 2 || n == 4))
+            .collect();
+        let (pcm, damage) = decode_damaged("damaged.ogg", &ogg_flac(&frames)).unwrap();
+        assert_eq!(pcm.len(), 4 * FLAC_BLOCK);
+        assert_eq!(damage.unwrap().0, 2);
+
+        let frames: Vec<Vec<u8>> = (0..MAX_BAD_RUN as u8 + 2)
+            .map(|n| flac_frame(n, 1000, n > 0 && n <= MAX_BAD_RUN as u8))
+            .collect();
+        let err = decode_file("ruined.ogg", &ogg_flac(&frames)).unwrap_err();
+        let msg = format!("{err:#}");
+        assert!(msg.contains("64 damaged packets in a row"), "{msg}");
+    }
+
+    /// Play `entries` once through a feed with a `crossfade_ms` overlap.
+    fn play_all(test: &str, entries: &[Vec<i16>], crossfade_ms: u32) -> Vec<i16> {
+        let dir = std::env::temp_dir().join(format!("b2b-media-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, pcm) in entries.iter().enumerate() {
+            std::fs::write(dir.join(format!("{i}.wav")), wav(pcm)).unwrap();
+        }
+        let list = Playlist::load(&dir.display().to_string()).unwrap();
+        let opts = FeedOptions {
+            rate: 8000,
+            capacity_ms: 10_000,
+            quality: ResampleQuality::Fast,
+            crossfade_ms,
+            looping: false,
+        };
+        let feed = Feed::start(list, opts).unwrap();
+        feed.wait_for(usize::MAX, Duration::from_secs(10));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut out = Vec::new();
+        let mut frame = [0i16; 160];
+        while feed.fill(&mut frame) {
+            out.extend_from_slice(&frame);
+        }
+        assert!(feed.error().is_none());
+        out.truncate(out.iter().rposition(|&s| s != 0).map_or(0, |i| i + 1));
+        out
+    }
+
+    #[test]
+    fn crossfade_overlaps_neighbouring_entries() {
+        let out = play_all("xfade", &[vec![1000; 8000], vec![3000; 8000]], 100);
+        assert_eq!(out.len(), 16000 - 800);
+        assert!(out[..7200].iter().all(|&s| s == 1000));
+        assert!(out[8000..].iter().all(|&s| s == 3000));
+        // Equal power: the midpoint carries both at cos/sin(45°).
+        let mid = out[7600] as f32;
+        assert!(
+            (mid - 4000.0 * std::f32::consts::FRAC_1_SQRT_2).abs() < 20.0,
+            "{mid}"
+        );
+    }
+
+    #[test]
+    fn entry_shorter_than_crossfade_shortens_it() {
+        let entries = [vec![1000; 8000], vec![-2000; 200], vec![3000; 8000]];
+        let out = play_all("xfade-short", &entries, 100);
+        // Both fades cover the short entry's 200 samples.
+        assert_eq!(out.len(), 8000 + 200 + 8000 - 200 - 200);
+        assert!(out[..7800].iter().all(|&s| s == 1000));
+        // The old entry is fully faded out by the end of the short one, and
+        // the short one hands over to the next within its own length.
+        assert!(out[7800..8000].iter().all(|&s| s != 1000 && s != 3000));
+        assert!(out[8000..].iter().all(|&s| s == 3000));
+    }
+
+    /// Removes a test's playlist directory when dropped.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A feed over one WAV entry of `pcm`.
+    fn feed(test: &str, pcm: &[i16], capacity_ms: u32, looping: bool) -> (TempDir, Feed) {
+        let dir = std::env::temp_dir().join(format!("b2b-media-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("0.wav"), wav(pcm)).unwrap();
+        let list = Playlist::load(&dir.display().to_string()).unwrap();
+        let opts = FeedOptions {
+            rate: 8000,
+            capacity_ms,
+            quality: ResampleQuality::Fast,
+            crossfade_ms: 0,
+            looping,
+        };
+        (TempDir(dir), Feed::start(list, opts).unwrap())
+    }
+
+    fn buffered(feed: &Feed) -> usize {
+        feed.shared.ring.lock().unwrap().buf.len()
+    }
+
+    #[test]
+    fn decoder_blocks_at_capacity_and_loses_nothing() {
+        let ramp: Vec<i16> = (0..8000).map(|i| i as i16).collect();
+        // 100 ms at 8 kHz: the ring holds 800 of the 8000 samples.
+        let (_dir, feed) = feed("capacity", &ramp, 100, false);
+        assert_eq!(feed.wait_for(usize::MAX, Duration::from_secs(5)), 800);
+        std::thread::sleep(Duration::from_millis(50));
+        assert_eq!(buffered(&feed), 800);
+        assert!(!feed.shared.ring.lock().unwrap().ended);
+
+        let mut out = Vec::new();
+        let mut frame = [0i16; 160];
+        while feed.wait_for(frame.len(), Duration::from_secs(5)) > 0 && feed.fill(&mut frame) {
+            out.extend_from_slice(&frame);
+            assert!(buffered(&feed) <= 800);
+        }
+        assert_eq!(out, ramp);
+        assert_eq!(feed.underrun_counter().load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn short_reads_are_padded_and_counted_until_the_end() {
+        // No decoder thread: samples arrive only when the test pushes them.
+        let feed = Feed {
+            shared: Arc::new(Shared::new(800)),
+            thread: None,
+        };
+        let mut player = Player {
+            shared: &feed.shared,
+            xfade: 0,
+            tail: VecDeque::new(),
+            written: 0,
+            out: Vec::new(),
+        };
+        let underruns = feed.underrun_counter();
+        let mut frame = [7i16; 160];
+        assert!(feed.fill(&mut frame));
+        assert!(frame.iter().all(|&s| s == 0));
+        assert_eq!(underruns.load(Ordering::Relaxed), 1);
+
+        assert!(player.push(&[5; 100]));
+        assert!(feed.fill(&mut frame));
+        assert!(frame[..100].iter().all(|&s| s == 5));
+        assert!(frame[100..].iter().all(|&s| s == 0));
+        assert_eq!(underruns.load(Ordering::Relaxed), 2);
+
+        // The end of the stream is not an underrun.
+        assert!(player.push(&[5; 100]));
+        feed.shared.ring.lock().unwrap().ended = true;
+        assert!(feed.fill(&mut frame));
+        assert!(!feed.fill(&mut frame));
+        assert_eq!(underruns.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn looping_starts_the_entry_over_at_its_end() {
+        let ramp: Vec<i16> = (1..=400).collect();
+        let (_dir, feed) = feed("loop", &ramp, 1000, true);
+        assert!(feed.wait_for(1300, Duration::from_secs(5)) >= 1300);
+        let mut out = Vec::new();
+        let mut frame = [0i16; 100];
+        for _ in 0..13 {
+            assert!(feed.fill(&mut frame));
+            out.extend_from_slice(&frame);
+        }
+        let want: Vec<i16> = ramp.iter().copied().cycle().take(1300).collect();
+        assert_eq!(out, want);
+        let passes: Vec<u64> = feed
+            .notices()
+            .into_iter()
+            .filter_map(|n| match n {
+                Notice::Started { pass, .. } => Some(pass),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(passes, [0, 1, 2, 3]);
+        assert!(feed.error().is_none());
     }
-    let factor = dst_rate as f64 / src_rate as f64;
-    let out_len = (pcm.len() as f64 * factor).floor() as usize;
-    let mut out = vec![0i16; out_len];
-    for (i, slot) in out.iter_mut().enumerate().take(out_len) {
-        let src_pos = i as f64 / factor;
-        let idx = src_pos.floor() as usize;
-        let frac = src_pos - idx as f64;
-        let a = pcm.get(idx).copied().unwrap_or(0) as f64;
-        let b = pcm.get(idx + 1).copied().unwrap_or(a as i16) as f64;
-        let v = a + (b - a) * frac;
-        *slot = v.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16;
-    }
-    Ok(PcmFrames {
-        data: out,
-        sample_rate: 8000,
-    })
-}
-
-pub fn split_into_20ms_frames(pcm: &[i16], sample_rate: u32) -> Vec<Vec<i16>> {
-    let samples_per_20ms = (sample_rate as usize / 50).max(1);
-    pcm.chunks(samples_per_20ms).map(|c| c.to_vec()).collect()
 }
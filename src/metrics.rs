@@ -1,6 +1,6 @@
 use serde::Serialize;
 
-#[derive(Debug, Default, Serialize)]
+#[derive(Debug, Default, Clone, Serialize)]
 pub struct Snapshot {
     pub ts: String,
     pub role: String,
@@ -11,9 +11,308 @@ pub struct Snapshot {
     pub jitter_ms_p50: f32,
     pub jitter_ms_p95: f32,
     pub cpu_pct: f32,
+    // Rollup of the role's 5s metrics lines; counters are cumulative for
+    // the current process, the rest is the latest window.
+    pub samples_tx: u64,
+    pub samples_rx: u64,
+    pub src_interval_min_ms: u32,
+    pub src_interval_max_ms: u32,
+    pub src_backlog_ms: u32,
+    pub mix_legs: u32,
+    pub mix_underrun: u64,
+    pub mix_silence_in: u64,
+    pub mix_bridge_ms: u32,
+    pub decode_underruns: u64,
+    /// Metrics lines folded in so far.
+    pub updates: u64,
 }
 
 impl Snapshot {
-    pub fn json_line(&self) -> String { serde_json::to_string(self).unwrap_or_default() }
+    pub fn new(role: &str, pid: u32) -> Self {
+        Self {
+            role: role.into(),
+            pid,
+            ..Default::default()
+        }
+    }
+
+    /// Fold one parsed metrics line into the rollup.
+    pub fn apply(&mut self, line: &Line, ts: String) {
+        match *line {
+            Line::Src {
+                pkts,
+                int_min_ms,
+                int_max_ms,
+                backlog_ms,
+            } => {
+                self.packets_tx += pkts;
+                self.src_interval_min_ms = int_min_ms;
+                self.src_interval_max_ms = int_max_ms;
+                self.src_backlog_ms = backlog_ms;
+            }
+            Line::Sink { rx_frames, drops } => {
+                self.packets_rx += rx_frames;
+                self.rtp_loss += drops;
+            }
+            Line::Mix {
+                legs,
+                in_frames,
+                out_frames,
+                silence_in,
+                underrun,
+                bridge_ms,
+                ..
+            } => {
+                self.mix_legs = legs;
+                self.packets_rx += in_frames;
+                self.packets_tx += out_frames;
+                self.mix_silence_in += silence_in;
+                self.mix_underrun += underrun;
+                self.mix_bridge_ms = bridge_ms;
+            }
+            Line::TxSamples {
+                total,
+                decode_underruns,
+                ..
+            } => {
+                self.samples_tx = total;
+                self.decode_underruns = decode_underruns;
+            }
+            Line::RxSamples { total, .. } => self.samples_rx = total,
+        }
+        self.ts = ts;
+        self.updates += 1;
+    }
+
+    /// Short per-role text for the periodic pipeline summary.
+    pub fn brief(&self) -> String {
+        let mut parts = Vec::new();
+        if self.packets_tx > 0 || self.samples_tx > 0 {
+            parts.push(format!("tx={}pk", self.packets_tx));
+        }
+        if self.packets_rx > 0 || self.samples_rx > 0 {
+            parts.push(format!("rx={}pk", self.packets_rx));
+        }
+        if self.rtp_loss > 0 {
+            parts.push(format!("drops={}", self.rtp_loss));
+        }
+        if self.src_backlog_ms > 0 {
+            parts.push(format!("backlog={}ms", self.src_backlog_ms));
+        }
+        if self.decode_underruns > 0 {
+            parts.push(format!("decode_underruns={}", self.decode_underruns));
+        }
+        if self.mix_legs > 0 {
+            parts.push(format!(
+                "legs={} underrun={} bridge={}ms",
+                self.mix_legs, self.mix_underrun, self.mix_bridge_ms
+            ));
+        }
+        if parts.is_empty() {
+            parts.push("no metrics".into());
+        }
+        parts.join(" ")
+    }
+}
+
+/// One metrics line as printed by a child: the C shim's `*_METRICS5s`
+/// lines or the Rust roles' `tx_samples=`/`rx_samples=` lines.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Line {
+    Src {
+        /// RTP packets the call's stream sent in the window.
+        pkts: u64,
+        int_min_ms: u32,
+        int_max_ms: u32,
+        backlog_ms: u32,
+    },
+    Sink {
+        rx_frames: u64,
+        drops: u64,
+    },
+    Mix {
+        legs: u32,
+        in_frames: u64,
+        out_frames: u64,
+        in_samples: u64,
+        out_samples: u64,
+        tone_on: u64,
+        silence_in: u64,
+        underrun: u64,
+        bridge_ms: u32,
+        bridge_min_ms: u32,
+        bridge_max_ms: u32,
+    },
+    TxSamples {
+        total: u64,
+        delta: u64,
+        decode_underruns: u64,
+    },
+    RxSamples {
+        total: u64,
+        delta: u64,
+    },
+}
+
+pub fn parse_line(line: &str) -> Option<Line> {
+    let mut toks = line.split_whitespace();
+    let head = toks.next()?;
+    let rest: Vec<&str> = toks.collect();
+    let get = |key: &str| -> Option<&str> {
+        rest.iter()
+            .find_map(|t| t.strip_prefix(key)?.strip_prefix('='))
+    };
+    let num = |key: &str| -> Option<u64> { get(key)?.trim_end_matches("ms").parse().ok() };
+    let num32 = |key: &str| -> Option<u32> { num(key)?.try_into().ok() };
+    match head {
+        "SRC_METRICS5s" => Some(Line::Src {
+            pkts: num("pkts")?,
+            int_min_ms: num32("int_min")?,
+            int_max_ms: num32("int_max")?,
+            backlog_ms: num32("backlog_ms")?,
+        }),
+        "SINK_METRICS5s" => Some(Line::Sink {
+            rx_frames: num("rx_frames")?,
+            drops: num("drops")?,
+        }),
+        "MIX_METRICS5s" => {
+            // bridge_ms=<cur>(<min>..<max>)
+            let bridge = get("bridge_ms")?;
+            let (cur, range) = bridge.split_once('(')?;
+            let (min, max) = range.trim_end_matches(')').split_once("..")?;
+            Some(Line::Mix {
+                legs: num32("legs")?,
+                in_frames: num("in_frames")?,
+                out_frames: num("out_frames")?,
+                in_samples: num("in_samples")?,
+                out_samples: num("out_samples")?,
+                tone_on: num("tone_on")?,
+                silence_in: num("silence_in")?,
+                underrun: num("underrun")?,
+                bridge_ms: cur.parse().ok()?,
+                bridge_min_ms: min.parse().ok()?,
+                bridge_max_ms: max.parse().ok()?,
+            })
+        }
+        _ => {
+            // tx_samples=<total> (+<delta>), tx_frames+<n>[, decode_underruns=<n>]
+            let (key, total) = head.split_once('=')?;
+            let total = total.parse().ok()?;
+            let delta = rest
+                .first()?
+                .trim_start_matches("(+")
+                .trim_end_matches("),")
+                .parse()
+                .ok()?;
+            match key {
+                "tx_samples" => Some(Line::TxSamples {
+                    total,
+                    delta,
+                    decode_underruns: num("decode_underruns").unwrap_or(0),
+                }),
+                "rx_samples" => Some(Line::RxSamples { total, delta }),
+                _ => None,
+            }
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_shim_lines() {
+        assert_eq!(
+            parse_line("SRC_METRICS5s pkts=248 int_min=19ms int_max=23ms backlog_ms=40"),
+            Some(Line::Src {
+                pkts: 248,
+                int_min_ms: 19,
+                int_max_ms: 23,
+                backlog_ms: 40,
+            })
+        );
+        assert_eq!(
+            parse_line("SINK_METRICS5s rx_frames=250 drops=3"),
+            Some(Line::Sink {
+                rx_frames: 250,
+                drops: 3
+            })
+        );
+        assert_eq!(
+            parse_line(
+                "MIX_METRICS5s legs=1 in_frames=250 out_frames=250 in_samples=40000 \
+                 out_samples=40000 tone_on=100 silence_in=2 underrun=1 bridge_ms=20(18..25)"
+            ),
+            Some(Line::Mix {
+                legs: 1,
+                in_frames: 250,
+                out_frames: 250,
+                in_samples: 40000,
+                out_samples: 40000,
+                tone_on: 100,
+                silence_in: 2,
+                underrun: 1,
+                bridge_ms: 20,
+                bridge_min_ms: 18,
+                bridge_max_ms: 25,
+            })
+        );
+    }
+
+    #[test]
+    fn parses_role_sample_lines() {
+        assert_eq!(
+            parse_line("tx_samples=80000 (+40000), tx_frames+250, decode_underruns=2"),
+            Some(Line::TxSamples {
+                total: 80000,
+                delta: 40000,
+                decode_underruns: 2
+            })
+        );
+        // Sources from before decode_underruns was reported.
+        assert_eq!(
+            parse_line("tx_samples=40000 (+40000), tx_frames+250"),
+            Some(Line::TxSamples {
+                total: 40000,
+                delta: 40000,
+                decode_underruns: 0
+            })
+        );
+        assert_eq!(
+            parse_line("rx_samples=40000 (+39840), rx_frames+249"),
+            Some(Line::RxSamples {
+                total: 40000,
+                delta: 39840
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_other_and_partial_lines() {
+        for line in [
+            "",
+            "call established",
+            "SRC_METRICS5s int_min=19ms int_max=23ms backlog_ms=40",
+            "SINK_METRICS5s rx_frames=many drops=0",
+            "MIX_METRICS5s legs=1 in_frames=1 out_frames=1 in_samples=1 out_samples=1 \
+             tone_on=0 silence_in=0 underrun=0 bridge_ms=20",
+            "tx_samples=80000",
+            "level=info (+1)",
+        ] {
+            assert_eq!(parse_line(line), None, "{line:?}");
+        }
+    }
+
+    #[test]
+    fn source_packets_come_from_the_line() {
+        let mut snap = Snapshot::new("source", 1);
+        for pkts in [250, 0] {
+            let line = format!("SRC_METRICS5s pkts={pkts} int_min=20ms int_max=20ms backlog_ms=0");
+            snap.apply(&parse_line(&line).unwrap(), String::new());
+        }
+        // A window with nothing sent leaves the counter flat.
+        assert_eq!(snap.packets_tx, 250);
+        assert_eq!(snap.updates, 2);
+    }
+}
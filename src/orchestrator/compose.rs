@@ -0,0 +1,470 @@
+use anyhow::{Context, Result, anyhow, bail};
+use std::path::{Path, PathBuf};
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, TableLike, Value};
+
+/// Names legacy `[topology]` plans give their roles.
+const LEGACY_ROLES: [&str; 3] = ["source", "mixer", "sink"];
+
+#[derive(Debug)]
+pub struct Composed {
+    pub doc: DocumentMut,
+    /// Whether `doc` differs from the plan file as written.
+    pub changed: bool,
+    /// The plan file and every file it includes.
+    pub files: Vec<PathBuf>,
+}
+
+/// Build the plan document from `path` and its `include`s, then apply the
+/// requested `[profile.*]` overlays and `--set` overrides, in that order.
+///
+/// Merging is by key: tables merge recursively, `[[...]]` entries with a
+/// `name` replace-merge the entry of the same name (others are appended),
+/// and any other value replaces the one below it. In overlays and `--set`
+/// keys, a role name addresses that role, e.g. `sink.jbuf_max_ms` is
+/// `[[role]] name = "sink"` or `[topology] sink.jbuf_max_ms`.
+pub fn compose(path: &Path, profiles: &[String], sets: &[String]) -> Result<Composed> {
+    let mut files = Vec::new();
+    let (mut doc, mut changed) = read(path, &mut Vec::new(), &mut files)?;
+    let defined = doc.remove("profile");
+    changed |= defined.is_some();
+    for name in profiles {
+        let overlay = defined
+            .as_ref()
+            .and_then(|p| p.get(name))
+            .and_then(Item::as_table_like)
+            .ok_or_else(|| {
+                let known: Vec<&str> = defined
+                    .iter()
+                    .filter_map(Item::as_table_like)
+                    .flat_map(|t| t.iter().map(|(k, _)| k))
+                    .collect();
+                let known = if known.is_empty() {
+                    "the plan defines none".to_string()
+                } else {
+                    format!("defined: {}", known.join(", "))
+                };
+                anyhow!("unknown profile {name:?} ({known})")
+            })?;
+        for (key, item) in overlay.iter() {
+            apply(&mut doc, key, item.clone()).with_context(|| format!("profile.{name}.{key}"))?;
+        }
+        changed = true;
+    }
+    for set in sets {
+        let (key, raw) = set
+            .split_once('=')
+            .ok_or_else(|| anyhow!("--set {set:?}: expected KEY=VALUE"))?;
+        let path: Vec<&str> = key.trim().split('.').collect();
+        if path.iter().any(|p| p.is_empty()) {
+            bail!("--set {set:?}: invalid key {key:?}");
+        }
+        override_value(&mut doc, &path, raw.trim()).with_context(|| format!("--set {key}"))?;
+        changed = true;
+    }
+    Ok(Composed {
+        doc,
+        changed,
+        files,
+    })
+}
+
+/// Parse one file and merge it over its includes (paths relative to the
+/// including file; a string or an array of strings).
+fn read(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<(DocumentMut, bool)> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("reading plan: {}", path.display()))?;
+    files.push(path.to_path_buf());
+    let mut doc: DocumentMut = s
+        .parse()
+        .map_err(|e| anyhow!("invalid plan {}:\n{e}", path.display()))?;
+    let Some(include) = doc.remove("include") else {
+        return Ok((doc, false));
+    };
+    let canon = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+    if stack.contains(&canon) {
+        let chain: Vec<String> = stack.iter().map(|p| p.display().to_string()).collect();
+        bail!(
+            "include cycle: {} -> {}",
+            chain.join(" -> "),
+            canon.display()
+        );
+    }
+    let includes: Vec<String> = match include.as_value() {
+        Some(Value::String(s)) => vec![s.value().clone()],
+        Some(Value::Array(a)) => a
+            .iter()
+            .map(|v| v.as_str().map(str::to_string))
+            .collect::<Option<_>>()
+            .ok_or_else(|| anyhow!("invalid plan {}: include: expected strings", path.display()))?,
+        _ => bail!(
+            "invalid plan {}: include: expected a path or an array of paths",
+            path.display()
+        ),
+    };
+    let dir = path.parent().unwrap_or(Path::new("."));
+    stack.push(canon);
+    let mut base: Option<DocumentMut> = None;
+    for f in includes {
+        let (inc, _) = read(&dir.join(&f), stack, files)
+            .with_context(|| format!("included from {}", path.display()))?;
+        match base.as_mut() {
+            Some(b) => merge_table(b.as_table_mut(), inc.as_table()),
+            None => base = Some(inc),
+        }
+    }
+    stack.pop();
+    let mut base = base.unwrap_or_default();
+    merge_table(base.as_table_mut(), doc.as_table());
+    Ok((base, true))
+}
+
+fn merge_table(base: &mut dyn TableLike, over: &dyn TableLike) {
+    for (key, item) in over.iter() {
+        match base.get_mut(key) {
+            Some(b) => merge_item(b, item),
+            None => {
+                base.insert(key, item.clone());
+            }
+        }
+    }
+}
+
+fn merge_item(base: &mut Item, over: &Item) {
+    if let (Some(b), Some(o)) = (base.as_table_like_mut(), over.as_table_like()) {
+        merge_table(b, o);
+        return;
+    }
+    if let (Item::ArrayOfTables(b), Item::ArrayOfTables(o)) = (&mut *base, over) {
+        merge_named(b, o);
+        return;
+    }
+    *base = over.clone();
+}
+
+fn merge_named(base: &mut ArrayOfTables, over: &ArrayOfTables) {
+    for t in over.iter() {
+        let name = t.get("name").and_then(Item::as_str);
+        let existing = name.and_then(|n| {
+            base.iter_mut()
+                .find(|b| b.get("name").and_then(Item::as_str) == Some(n))
+        });
+        match existing {
+            Some(b) => merge_table(b, t),
+            None => base.push(t.clone()),
+        }
+    }
+}
+
+/// Whether `name` addresses a role: a `[[role]]` entry, or a legacy role
+/// name in a `[topology]` plan.
+fn is_role(doc: &DocumentMut, name: &str) -> bool {
+    listed(doc, name)
+        || (LEGACY_ROLES.contains(&name) && doc.get("topology").is_some_and(Item::is_table_like))
+}
+
+fn listed(doc: &DocumentMut, name: &str) -> bool {
+    doc.get("role")
+        .and_then(Item::as_array_of_tables)
+        .is_some_and(|a| {
+            a.iter()
+                .any(|t| t.get("name").and_then(Item::as_str) == Some(name))
+        })
+}
+
+/// The table of role `name` to write to, from `[[role]]` or legacy
+/// `[topology]`; a legacy role without settings yet gets its table here.
+fn role_table<'a>(doc: &'a mut DocumentMut, name: &str) -> Option<&'a mut dyn TableLike> {
+    if listed(doc, name) {
+        return doc
+            .get_mut("role")?
+            .as_array_of_tables_mut()?
+            .iter_mut()
+            .find(|t| t.get("name").and_then(Item::as_str) == Some(name))
+            .map(|t| t as &mut dyn TableLike);
+    }
+    if !LEGACY_ROLES.contains(&name) {
+        return None;
+    }
+    let topology = doc.get_mut("topology")?.as_table_like_mut()?;
+    if topology.get(name).is_none() {
+        let mut t = Table::new();
+        t.set_dotted(true);
+        topology.insert(name, Item::Table(t));
+    }
+    topology.get_mut(name)?.as_table_like_mut()
+}
+
+/// Merge one top-level overlay entry, routing role names to their role.
+fn apply(doc: &mut DocumentMut, key: &str, item: Item) -> Result<()> {
+    if is_role(doc, key) {
+        let over = item
+            .as_table_like()
+            .ok_or_else(|| anyhow!("expected a table of {key}'s settings"))?;
+        let role = role_table(doc, key).expect("checked above");
+        merge_table(role, over);
+        return Ok(());
+    }
+    match doc.get_mut(key) {
+        Some(b) => merge_item(b, &item),
+        None => {
+            doc.insert(key, item);
+        }
+    }
+    Ok(())
+}
+
+/// Set one dotted key. `raw` is taken as a TOML value (`300`, `true`,
+/// `["a"]`) unless the current value is a string or it does not parse.
+fn override_value(doc: &mut DocumentMut, path: &[&str], raw: &str) -> Result<()> {
+    let (mut table, rest): (&mut dyn TableLike, &[&str]) = match path {
+        [role, rest @ ..] if !rest.is_empty() && is_role(doc, role) => {
+            (role_table(doc, role).expect("checked above"), rest)
+        }
+        _ => (doc.as_table_mut(), path),
+    };
+    let (last, parents) = rest.split_last().expect("non-empty key");
+    for p in parents {
+        if table.get(p).is_none() {
+            let mut t = Table::new();
+            t.set_implicit(true);
+            table.insert(p, Item::Table(t));
+        }
+        table = table
+            .get_mut(p)
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| anyhow!("{p} is not a table"))?;
+    }
+    let keep_string = table.get(last).is_some_and(|i| i.is_str());
+    let mut value = match raw.parse::<Value>() {
+        Ok(v) if !keep_string => v,
+        _ => Value::from(raw.trim_matches('"')),
+    };
+    match table.get(last).and_then(Item::as_value) {
+        Some(old) => *value.decor_mut() = old.decor().clone(),
+        None => value.decor_mut().clear(),
+    }
+    table.insert(last, Item::Value(value));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Removes a test's plan directory when dropped.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Write `files` into a fresh directory; the path is the first file's.
+    fn plan(test: &str, files: &[(&str, &str)]) -> (TempDir, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("b2b-compose-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, text) in files {
+            std::fs::write(dir.join(name), text).unwrap();
+        }
+        let path = dir.join(files[0].0);
+        (TempDir(dir), path)
+    }
+
+    fn sets(s: &[&str]) -> Vec<String> {
+        s.iter().map(|s| s.to_string()).collect()
+    }
+
+    fn role<'a>(doc: &'a DocumentMut, name: &str) -> &'a Table {
+        doc["role"]
+            .as_array_of_tables()
+            .unwrap()
+            .iter()
+            .find(|t| t.get("name").and_then(Item::as_str) == Some(name))
+            .unwrap()
+    }
+
+    const BASE: &str = "[[role]]\nname = \"sink\"\nkind = \"sink\"\nsip_bind = \"127.0.0.1:5062\"\n\
+         jbuf_max_ms = 100\naplay_cmd = \"aplay\"\n";
+
+    #[test]
+    fn later_layers_win_include_plan_profile_set() {
+        let (_dir, path) = plan(
+            "precedence",
+            &[
+                (
+                    "plan.toml",
+                    "include = \"base.toml\"\n\n[[role]]\nname = \"sink\"\njbuf_max_ms = 200\n\
+                     buffer_min_ms = 20\n\n[profile.lab]\nsink.jbuf_max_ms = 300\n\
+                     sink.buffer_min_ms = 30\n",
+                ),
+                ("base.toml", BASE),
+            ],
+        );
+        let sink = |profiles: &[String], s: &[&str]| {
+            let c = compose(&path, profiles, &sets(s)).unwrap();
+            let t = role(&c.doc, "sink");
+            (
+                t["jbuf_max_ms"].as_integer().unwrap(),
+                t["buffer_min_ms"].as_integer().unwrap(),
+                t["aplay_cmd"].as_str().unwrap().to_string(),
+            )
+        };
+        assert_eq!(sink(&[], &[]), (200, 20, "aplay".into()));
+        let lab = sets(&["lab"]);
+        assert_eq!(sink(&lab, &[]), (300, 30, "aplay".into()));
+        assert_eq!(
+            sink(&lab, &["sink.jbuf_max_ms=400"]),
+            (400, 30, "aplay".into())
+        );
+    }
+
+    #[test]
+    fn includes_merge_roles_by_name_and_append_new_ones() {
+        let (_dir, path) = plan(
+            "merge",
+            &[
+                (
+                    "plan.toml",
+                    "include = [\"base.toml\"]\n\n[[role]]\nname = \"sink\"\njbuf_max_ms = 60\n\n\
+                     [[role]]\nname = \"src\"\nkind = \"source\"\nsip_target = \"@sink\"\n",
+                ),
+                ("base.toml", BASE),
+            ],
+        );
+        let c = compose(&path, &[], &[]).unwrap();
+        assert!(c.changed);
+        assert_eq!(c.files.len(), 2);
+        let roles = c.doc["role"].as_array_of_tables().unwrap();
+        assert_eq!(roles.len(), 2);
+        let sink = role(&c.doc, "sink");
+        assert_eq!(sink["jbuf_max_ms"].as_integer(), Some(60));
+        assert_eq!(sink["sip_bind"].as_str(), Some("127.0.0.1:5062"));
+        assert!(c.doc.get("include").is_none());
+    }
+
+    #[test]
+    fn plain_plan_is_unchanged() {
+        let (_dir, path) = plan("plain", &[("plan.toml", BASE)]);
+        let c = compose(&path, &[], &[]).unwrap();
+        assert!(!c.changed);
+        assert_eq!(c.doc.to_string(), BASE);
+    }
+
+    #[test]
+    fn set_parses_values_unless_the_key_holds_a_string() {
+        let (_dir, path) = plan("set", &[("plan.toml", BASE)]);
+        let c = compose(
+            &path,
+            &[],
+            &sets(&[
+                "sink.buffer_max_ms = 80",
+                "sink.aplay_cmd=123",
+                "sink.sip_bind=0.0.0.0:5062",
+                "sink.jbuf_type=\"adaptive\"",
+                "duration=30s",
+                "logs.rotate_size_mb=8",
+            ]),
+        )
+        .unwrap();
+        let sink = role(&c.doc, "sink");
+        assert_eq!(sink["buffer_max_ms"].as_integer(), Some(80));
+        assert_eq!(sink["aplay_cmd"].as_str(), Some("123"));
+        assert_eq!(sink["sip_bind"].as_str(), Some("0.0.0.0:5062"));
+        assert_eq!(sink["jbuf_type"].as_str(), Some("adaptive"));
+        assert_eq!(c.doc["duration"].as_str(), Some("30s"));
+        assert_eq!(c.doc["logs"]["rotate_size_mb"].as_integer(), Some(8));
+    }
+
+    #[test]
+    fn set_reaches_legacy_topology_roles() {
+        let (_dir, path) = plan(
+            "legacy",
+            &[(
+                "plan.toml",
+                "[topology]\nsink.sip_bind = \"127.0.0.1:5062\"\n",
+            )],
+        );
+        let c = compose(
+            &path,
+            &[],
+            &sets(&[
+                "sink.jbuf_max_ms=300",
+                "mixer.sip_target=sip:127.0.0.1:5062",
+            ]),
+        )
+        .unwrap();
+        let topo = &c.doc["topology"];
+        assert_eq!(topo["sink"]["jbuf_max_ms"].as_integer(), Some(300));
+        assert_eq!(
+            topo["mixer"]["sip_target"].as_str(),
+            Some("sip:127.0.0.1:5062")
+        );
+    }
+
+    #[test]
+    fn bad_sets_profiles_and_include_cycles_fail() {
+        let (_dir, path) = plan(
+            "errors",
+            &[
+                (
+                    "plan.toml",
+                    "include = \"other.toml\"\n[profile.lab]\nduration = \"1s\"\n",
+                ),
+                ("other.toml", "include = \"plan.toml\"\n"),
+            ],
+        );
+        let e = compose(&path, &[], &[]).unwrap_err();
+        assert!(format!("{e:#}").contains("include cycle"), "{e:#}");
+
+        let (_dir, path) = plan(
+            "bad-set",
+            &[("plan.toml", "[profile.lab]\nduration = \"1s\"\n")],
+        );
+        let e = compose(&path, &[], &sets(&["duration"])).unwrap_err();
+        assert_eq!(e.to_string(), "--set \"duration\": expected KEY=VALUE");
+        let e = compose(&path, &[], &sets(&["sink..x=1"])).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "--set \"sink..x=1\": invalid key \"sink..x\""
+        );
+        let e = compose(&path, &sets(&["prod"]), &[]).unwrap_err();
+        assert_eq!(e.to_string(), "unknown profile \"prod\" (defined: lab)");
+    }
+
+    #[test]
+    fn looking_up_a_role_leaves_the_plan_alone() {
+        let (_dir, path) = plan(
+            "lookup",
+            &[(
+                "plan.toml",
+                "[topology]
+sink.sip_bind = \"127.0.0.1:5062\"\n\n\
+                 [profile.bad]\nmixer = 1\n[profile.short]\nduration = \"1s\"\n",
+            )],
+        );
+        let mut doc = compose(&path, &[], &[]).unwrap().doc;
+        let before = doc.to_string();
+        assert!(is_role(&doc, "mixer") && !is_role(&doc, "duration"));
+        let e = apply(&mut doc, "mixer", Item::Value(1.into())).unwrap_err();
+        assert_eq!(e.to_string(), "expected a table of mixer's settings");
+        assert_eq!(doc.to_string(), before);
+
+        // Nothing but the profile's own key shows up in the merged plan.
+        let c = compose(&path, &sets(&["short"]), &sets(&["duration=2s"])).unwrap();
+        assert_eq!(
+            c.doc.to_string(),
+            "duration = \"2s\"\n[topology]\nsink.sip_bind = \"127.0.0.1:5062\"\n"
+        );
+
+        // A [[role]] plan has no legacy roles to create.
+        let (_dir, path) = plan("lookup-roles", &[("plan.toml", BASE)]);
+        let c = compose(&path, &[], &sets(&["mixer.x=1"])).unwrap();
+        assert!(!c.doc.to_string().contains("topology"), "{}", c.doc);
+    }
+}
@@ -0,0 +1,133 @@
+use super::{plan::Expect, report::RunReport};
+use serde::Serialize;
+
+/// Result of one `[expect]` assertion.
+#[derive(Debug, Serialize)]
+pub struct Outcome {
+    pub name: String,
+    pub pass: bool,
+    pub detail: String,
+}
+
+/// Check the plan's expectations against the finished run.
+pub fn evaluate(expect: &Expect, report: &RunReport, ptime_ms: u32) -> Vec<Outcome> {
+    let mut out = Vec::new();
+    if let Some(limit) = expect.ready_within {
+        let limit_ms = limit.as_millis() as u64;
+        let late: Vec<String> = report
+            .roles
+            .iter()
+            .filter(|r| r.time_to_ready_ms.is_none_or(|ms| ms > limit_ms))
+            .map(|r| match r.time_to_ready_ms {
+                Some(ms) => format!("{} {ms} ms", r.name),
+                None => format!("{} never", r.name),
+            })
+            .collect();
+        let slowest = report.roles.iter().filter_map(|r| r.time_to_ready_ms).max();
+        out.push(Outcome {
+            name: format!("ready_within = {limit_ms} ms"),
+            pass: late.is_empty(),
+            detail: if late.is_empty() {
+                format!("slowest {} ms", slowest.unwrap_or(0))
+            } else {
+                format!("late: {}", late.join(", "))
+            },
+        });
+    }
+    if let Some(max) = expect.max_restarts {
+        let total: u32 = report
+            .roles
+            .iter()
+            .map(|r| r.restarts + r.stall_restarts)
+            .sum();
+        out.push(Outcome {
+            name: format!("max_restarts = {max}"),
+            pass: total <= max,
+            detail: format!("{total} restart(s)"),
+        });
+    }
+    if let Some(max) = expect.max_stalls {
+        let total: u32 = report.roles.iter().map(|r| r.stalls).sum();
+        out.push(Outcome {
+            name: format!("max_stalls = {max}"),
+            pass: total <= max,
+            detail: format!("{total} stall(s)"),
+        });
+    }
+    if let Some(min) = expect.min_sink_rx_ratio {
+        // Expected frames: one per ptime between call establishment and the
+        // sink's last metrics line, which is what `packets_rx` covers.
+        for r in report.roles.iter().filter(|r| r.kind == "sink") {
+            let rx = r.last_metrics.as_ref().map_or(0, |m| m.packets_rx);
+            let expected = r.call_ms.unwrap_or(0) / u64::from(ptime_ms.max(1));
+            let ratio = if expected == 0 {
+                0.0
+            } else {
+                rx as f64 / expected as f64
+            };
+            out.push(Outcome {
+                name: format!("min_sink_rx_ratio = {min} ({})", r.name),
+                pass: ratio >= min,
+                detail: format!("{rx}/{expected} frames ({:.1}%)", ratio * 100.0),
+            });
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::report::{RoleReport, tests::role};
+
+    fn run(roles: Vec<RoleReport>) -> RunReport {
+        RunReport {
+            plan: "p".into(),
+            started_at: String::new(),
+            ended_at: String::new(),
+            duration_ms: 0,
+            exit_code: 0,
+            error: None,
+            roles,
+            expectations: Vec::new(),
+            merged_log: None,
+        }
+    }
+
+    fn max_restarts(max: u32, roles: Vec<RoleReport>) -> Outcome {
+        let expect = Expect {
+            max_restarts: Some(max),
+            ..Default::default()
+        };
+        let mut out = evaluate(&expect, &run(roles), 20);
+        assert_eq!(out.len(), 1);
+        out.remove(0)
+    }
+
+    #[test]
+    fn planned_restarts_do_not_count_against_max_restarts() {
+        let sink = RoleReport {
+            scripted_restarts: 3,
+            ..role("sink", "sink")
+        };
+        let o = max_restarts(0, vec![sink, role("source", "source")]);
+        assert!(o.pass, "{o:?}");
+        assert_eq!(o.detail, "0 restart(s)");
+    }
+
+    #[test]
+    fn crash_and_stall_restarts_count_across_roles() {
+        let sink = RoleReport {
+            restarts: 1,
+            scripted_restarts: 5,
+            ..role("sink", "sink")
+        };
+        let source = RoleReport {
+            stall_restarts: 1,
+            ..role("source", "source")
+        };
+        let o = max_restarts(1, vec![sink, source]);
+        assert!(!o.pass, "{o:?}");
+        assert_eq!(o.detail, "2 restart(s)");
+    }
+}
@@ -0,0 +1,511 @@
+use super::{
+    CHILD_ENV, child_args, graph,
+    plan::{PlanTopology, RestartPolicy, RestartSpec, RoleSpec},
+    role_args, role_str,
+};
+use crate::cli::{Cli, ExportFormat};
+use anyhow::{Context, Result};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// One generated file.
+pub struct Artifact {
+    pub name: String,
+    pub content: String,
+}
+
+/// Launch artifacts that start the plan's roles with exactly the arguments
+/// and environment the orchestrator would use, in its startup order.
+pub fn export(
+    format: ExportFormat,
+    topo: &PlanTopology,
+    layers: &[Vec<usize>],
+    args: &Cli,
+    plan_path: &Path,
+) -> Result<Vec<Artifact>> {
+    let ctx = Ctx {
+        topo,
+        layers,
+        args,
+        plan: plan_path.display().to_string(),
+        stem: plan_stem(plan_path),
+        exe: std::env::current_exe().context("current_exe")?,
+        cwd: std::env::current_dir().context("current_dir")?,
+    };
+    Ok(match format {
+        ExportFormat::Shell => vec![ctx.shell()],
+        ExportFormat::Compose => vec![ctx.compose()],
+        ExportFormat::Systemd => ctx.systemd(),
+    })
+}
+
+struct Ctx<'a> {
+    topo: &'a PlanTopology,
+    layers: &'a [Vec<usize>],
+    args: &'a Cli,
+    plan: String,
+    /// Plan file name without `.plan.toml`, used to name units.
+    stem: String,
+    exe: PathBuf,
+    cwd: PathBuf,
+}
+
+impl Ctx<'_> {
+    fn argv(&self, r: &RoleSpec) -> Vec<String> {
+        child_args(r.params.kind(), &role_args(&r.params), self.args)
+    }
+
+    fn order(&self) -> String {
+        self.layers
+            .iter()
+            .map(|l| {
+                let names: Vec<&str> = l
+                    .iter()
+                    .map(|&i| self.topo.roles[i].name.as_str())
+                    .collect();
+                names.join(", ")
+            })
+            .collect::<Vec<_>>()
+            .join(" -> ")
+    }
+
+    /// Header comment shared by every format.
+    fn header(&self, format: &str) -> String {
+        let mut h = format!(
+            "# Generated by `b2b --plan {} --export {format}`.\n# Startup order: {}\n",
+            self.plan,
+            self.order()
+        );
+        let auto = self
+            .topo
+            .resolved
+            .substitutions
+            .iter()
+            .any(|s| s.from.ends_with(":auto"));
+        if auto {
+            h.push_str("# `auto` ports were picked at export time; they are fixed from here on.\n");
+        }
+        h
+    }
+
+    /// POSIX sh script: starts each layer, then waits for READY from the
+    /// roles the next layers dial, like the orchestrator does.
+    fn shell(&self) -> Artifact {
+        let gates = graph::has_dependents(self.topo);
+        let mut s = String::from("#!/bin/sh\n");
+        s.push_str(&self.header("shell"));
+        s.push_str(&format!(
+            "# Role stdout (READY and metrics lines) goes to $RUN_DIR/<role>.out;\n\
+             # logs go to stderr. Ctrl-C stops roles one by one in reverse startup order.\n\
+             set -u\n\
+             B2B=${{B2B:-{}}}\n\
+             READY_SECS=${{READY_SECS:-{}}}\n\
+             RUN_DIR=$(mktemp -d)\n",
+            sh_quote(&self.exe.to_string_lossy()),
+            self.args.ready_ms.div_ceil(1000).max(1),
+        ));
+        for (k, v) in CHILD_ENV {
+            s.push_str(&format!("export {k}={}\n", sh_quote(v)));
+        }
+        s.push_str(&format!(
+            "cd {} || exit 1\n",
+            sh_quote(&self.cwd.to_string_lossy())
+        ));
+        s.push_str(SHELL_FUNCS);
+        for layer in self.layers {
+            s.push('\n');
+            for &i in layer {
+                let r = &self.topo.roles[i];
+                let mut line = format!("start {}", sh_quote(&r.name));
+                for a in self.argv(r) {
+                    line.push(' ');
+                    line.push_str(&sh_quote(&a));
+                }
+                s.push_str(&format!("{line}\nr{i}=$pid\n"));
+            }
+            for &i in layer.iter().filter(|&&i| gates[i]) {
+                let name = sh_quote(&self.topo.roles[i].name);
+                s.push_str(&format!("wait_ready {name} \"$r{i}\"\n"));
+            }
+        }
+        s.push_str("\necho \"all roles started; Ctrl-C stops them\" >&2\nwait\n");
+        Artifact {
+            name: format!("{}.sh", self.stem),
+            content: s,
+        }
+    }
+
+    /// Compose file with one service per role on the host network. Compose
+    /// orders container starts but cannot wait for READY.
+    fn compose(&self) -> Artifact {
+        let deps = graph::dependencies(self.topo);
+        let cwd = self.cwd.to_string_lossy();
+        let mut s = self.header("compose");
+        s.push_str(
+            "# The image must contain the b2b binary; set B2B_IMAGE to use another.\n\
+             # The working directory is mounted so plan paths (audio files) resolve.\n",
+        );
+        let _ = writeln!(
+            s,
+            "name: {}",
+            yaml(&unit_name(&format!("b2b-{}", self.stem)))
+        );
+        s.push_str("services:\n");
+        for &i in self.layers.iter().flatten() {
+            let r = &self.topo.roles[i];
+            let _ = writeln!(s, "  {}:", unit_name(&r.name));
+            s.push_str("    image: ${B2B_IMAGE:-b2b}\n");
+            s.push_str("    entrypoint: [\"b2b\"]\n");
+            let _ = writeln!(s, "    command: {}", yaml_list(&self.argv(r)));
+            s.push_str("    environment:\n");
+            for (k, v) in CHILD_ENV {
+                let _ = writeln!(s, "      {k}: {}", yaml(v));
+            }
+            s.push_str("    network_mode: host\n");
+            let _ = writeln!(s, "    working_dir: {}", yaml(&cwd));
+            let _ = writeln!(s, "    volumes: [{}]", yaml(&format!("{cwd}:{cwd}")));
+            let restart = match r.restart.policy {
+                RestartPolicy::Never => "no".to_string(),
+                RestartPolicy::OnFailure => format!("on-failure:{}", r.restart.max_restarts),
+                RestartPolicy::Always => "always".to_string(),
+            };
+            let _ = writeln!(s, "    restart: {}", yaml(&restart));
+            let _ = writeln!(s, "    stop_grace_period: {}ms", self.grace_ms(r));
+            if !deps[i].is_empty() {
+                let names: Vec<String> = deps[i]
+                    .iter()
+                    .map(|&d| unit_name(&self.topo.roles[d].name))
+                    .collect();
+                let _ = writeln!(s, "    depends_on: {}", yaml_list(&names));
+            }
+        }
+        Artifact {
+            name: format!("{}.compose.yaml", self.stem),
+            content: s,
+        }
+    }
+
+    /// A `.service` per role, ordered with `After=`/`Requires=`, plus a
+    /// target that starts them all.
+    fn systemd(&self) -> Vec<Artifact> {
+        let deps = graph::dependencies(self.topo);
+        let prefix = unit_name(&format!("b2b-{}", self.stem));
+        let target = format!("{prefix}.target");
+        let service =
+            |i: usize| format!("{prefix}-{}.service", unit_name(&self.topo.roles[i].name));
+        let mut out = Vec::new();
+        for &i in self.layers.iter().flatten() {
+            let r = &self.topo.roles[i];
+            let mut s = self.header("systemd");
+            s.push_str(
+                "# Type=simple counts a role as started once it is spawned, so After=\n\
+                 # orders launches but does not wait for READY; a dependent that dials\n\
+                 # too early fails and relies on its own Restart= to retry.\n",
+            );
+            s.push_str("[Unit]\n");
+            let _ = writeln!(
+                s,
+                "Description=b2b {} role {} ({})",
+                role_str(r.params.kind()),
+                r.name,
+                self.plan.replace('%', "%%")
+            );
+            let _ = writeln!(s, "PartOf={target}");
+            if !deps[i].is_empty() {
+                let units: Vec<String> = deps[i].iter().map(|&d| service(d)).collect();
+                let _ = writeln!(s, "After={}", units.join(" "));
+                let _ = writeln!(s, "Requires={}", units.join(" "));
+            }
+            if r.restart.policy != RestartPolicy::Never {
+                // The first start counts towards the burst too.
+                let _ = writeln!(s, "StartLimitBurst={}", r.restart.max_restarts + 1);
+                let _ = writeln!(s, "StartLimitIntervalSec={}", start_limit_secs(&r.restart));
+            }
+            s.push_str("\n[Service]\nType=simple\n");
+            for (k, v) in CHILD_ENV {
+                let _ = writeln!(s, "Environment={}", systemd_quote(&format!("{k}={v}")));
+            }
+            let _ = writeln!(
+                s,
+                "WorkingDirectory={}",
+                systemd_quote(&self.cwd.to_string_lossy())
+            );
+            let mut exec = systemd_quote(&self.exe.to_string_lossy());
+            for a in self.argv(r) {
+                exec.push(' ');
+                exec.push_str(&systemd_quote(&a));
+            }
+            let _ = writeln!(s, "ExecStart={exec}");
+            let restart = match r.restart.policy {
+                RestartPolicy::Never => "no",
+                RestartPolicy::OnFailure => "on-failure",
+                RestartPolicy::Always => "always",
+            };
+            let _ = writeln!(s, "Restart={restart}");
+            if r.restart.policy != RestartPolicy::Never {
+                let spec = r.restart;
+                let _ = writeln!(s, "RestartSec={}ms", spec.backoff_ms);
+                // Doubling from RestartSec up to the cap (systemd >= 254).
+                let mut steps = 0;
+                while steps < 16
+                    && spec.backoff(steps + 1) < Duration::from_millis(spec.backoff_max_ms)
+                {
+                    steps += 1;
+                }
+                let _ = writeln!(s, "RestartSteps={steps}");
+                let _ = writeln!(s, "RestartMaxDelaySec={}ms", spec.backoff_max_ms);
+            }
+            let _ = writeln!(s, "TimeoutStopSec={}ms", self.grace_ms(r));
+            let _ = writeln!(s, "\n[Install]\nWantedBy={target}");
+            out.push(Artifact {
+                name: service(i),
+                content: s,
+            });
+        }
+        let wants: Vec<String> = self.layers.iter().flatten().map(|&i| service(i)).collect();
+        let mut s = self.header("systemd");
+        let _ = writeln!(
+            s,
+            "[Unit]\nDescription=b2b pipeline ({})\nWants={}\n\n[Install]\nWantedBy=multi-user.target",
+            self.plan.replace('%', "%%"),
+            wants.join(" ")
+        );
+        out.push(Artifact {
+            name: target,
+            content: s,
+        });
+        out
+    }
+
+    fn grace_ms(&self, r: &RoleSpec) -> u64 {
+        r.stop_grace_ms.unwrap_or(self.args.grace_ms)
+    }
+}
+
+const SHELL_FUNCS: &str = r#"
+pids=
+stop() {
+    trap '' INT TERM
+    trap - EXIT
+    for p in $pids; do
+        kill -TERM "$p" 2>/dev/null
+        wait "$p" 2>/dev/null
+    done
+    rm -rf "$RUN_DIR"
+}
+trap 'stop; exit 130' INT TERM
+trap stop EXIT
+
+# start NAME ARGS...: run one role in the background; sets $pid.
+start() {
+    name=$1
+    shift
+    "$B2B" "$@" >"$RUN_DIR/$name.out" &
+    pid=$!
+    pids="$pid $pids"
+}
+
+# wait_ready NAME PID: block until the role prints READY.
+wait_ready() {
+    n=0
+    until grep -q 'READY role=' "$RUN_DIR/$1.out" 2>/dev/null; do
+        if ! kill -0 "$2" 2>/dev/null; then
+            echo "$1 exited before READY" >&2
+            exit 1
+        fi
+        n=$((n + 1))
+        if [ "$n" -gt $((READY_SECS * 10)) ]; then
+            echo "$1 not READY after ${READY_SECS}s" >&2
+            exit 1
+        fi
+        sleep 0.1
+    done
+}
+"#;
+
+fn plan_stem(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "plan".into());
+    let stem = name
+        .strip_suffix(".plan.toml")
+        .or_else(|| name.strip_suffix(".toml"))
+        .unwrap_or(&name);
+    stem.to_string()
+}
+
+/// Window for `StartLimitBurst`: every backoff delay of the restart budget
+/// plus one capped delay of slack, so a role that crash-loops through its
+/// whole budget stays inside one window. Restarts spread wider than that
+/// (a role running a while between crashes) let systemd reset the count,
+/// where the orchestrator's budget lasts the whole run.
+fn start_limit_secs(spec: &RestartSpec) -> u64 {
+    let window: Duration = (1..=spec.max_restarts)
+        .map(|n| spec.backoff(n))
+        .sum::<Duration>()
+        + Duration::from_millis(spec.backoff_max_ms);
+    window.as_millis().div_ceil(1000) as u64
+}
+
+/// Lowercase letters, digits, `-` and `_` only (compose service and
+/// systemd unit names).
+fn unit_name(s: &str) -> String {
+    s.chars()
+        .map(|c| match c.to_ascii_lowercase() {
+            c @ ('a'..='z' | '0'..='9' | '-' | '_') => c,
+            _ => '-',
+        })
+        .collect()
+}
+
+/// Quote for POSIX sh; words that need no quoting are left alone.
+pub fn sh_quote(s: &str) -> String {
+    let plain = !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@%+=:,./-_".contains(c));
+    if plain {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', r"'\''"))
+    }
+}
+
+/// Quote one systemd command-line word; `%` and `$` are escaped so
+/// specifiers and variables are not expanded, and control characters
+/// become C escapes so the value stays on one line.
+fn systemd_quote(s: &str) -> String {
+    let s = s.replace('%', "%%").replace('$', "$$");
+    let plain = !s.is_empty()
+        && !s
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || "\"'\\;".contains(c));
+    if plain {
+        return s;
+    }
+    let mut q = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '\\' => q.push_str(r"\\"),
+            '"' => q.push_str("\\\""),
+            '\n' => q.push_str(r"\n"),
+            '\r' => q.push_str(r"\r"),
+            '\t' => q.push_str(r"\t"),
+            c if c.is_ascii_control() => {
+                let _ = write!(q, "\\x{:02x}", c as u32);
+            }
+            c if c.is_control() => {
+                let _ = write!(q, "\\u{:04x}", c as u32);
+            }
+            c => q.push(c),
+        }
+    }
+    q.push('"');
+    q
+}
+
+/// YAML scalar as a JSON string (valid YAML, no quoting surprises), with
+/// `$` doubled so compose does not interpolate it.
+fn yaml(s: &str) -> String {
+    serde_json::Value::from(s.replace('$', "$$")).to_string()
+}
+
+fn yaml_list(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|s| yaml(s)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+/// Write artifacts into `dir`, or to stdout with a name line before each
+/// when there are several.
+pub fn write(artifacts: &[Artifact], dir: Option<&Path>) -> Result<Vec<PathBuf>> {
+    let Some(dir) = dir else {
+        let many = artifacts.len() > 1;
+        for a in artifacts {
+            if many {
+                println!("### {}", a.name);
+            }
+            print!("{}", a.content);
+            if many {
+                println!();
+            }
+        }
+        return Ok(Vec::new());
+    };
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+    let mut written = Vec::new();
+    for a in artifacts {
+        let path = dir.join(&a.name);
+        std::fs::write(&path, &a.content).with_context(|| format!("writing {}", path.display()))?;
+        if a.name.ends_with(".sh") {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sh_quote_leaves_plain_words_and_quotes_the_rest() {
+        assert_eq!(
+            sh_quote("--sip-target=10.0.0.1:5060"),
+            "--sip-target=10.0.0.1:5060"
+        );
+        assert_eq!(sh_quote(""), "''");
+        assert_eq!(sh_quote("a b"), "'a b'");
+        assert_eq!(sh_quote("$HOME"), "'$HOME'");
+        assert_eq!(sh_quote("it's"), r"'it'\''s'");
+        assert_eq!(sh_quote("\"x\""), "'\"x\"'");
+        assert_eq!(sh_quote("a\nb"), "'a\nb'");
+        assert_eq!(sh_quote("*.wav"), "'*.wav'");
+    }
+
+    #[test]
+    fn systemd_quote_escapes_specifiers_variables_and_control_chars() {
+        assert_eq!(systemd_quote("/usr/bin/b2b"), "/usr/bin/b2b");
+        assert_eq!(systemd_quote("100%"), "100%%");
+        assert_eq!(systemd_quote("$HOME"), "$$HOME");
+        assert_eq!(systemd_quote(""), "\"\"");
+        assert_eq!(systemd_quote("a b"), "\"a b\"");
+        assert_eq!(systemd_quote("say \"hi\""), r#""say \"hi\"""#);
+        assert_eq!(systemd_quote("it's"), "\"it's\"");
+        assert_eq!(systemd_quote(r"C:\x;y"), r#""C:\\x;y""#);
+        assert_eq!(systemd_quote("a\nb\tc"), r#""a\nb\tc""#);
+        assert_eq!(systemd_quote("bell\x07"), r#""bell\x07""#);
+        assert!(!systemd_quote("x\ny\rz").contains(['\n', '\r']));
+    }
+
+    #[test]
+    fn yaml_is_a_json_string_without_interpolation() {
+        assert_eq!(yaml("b2b"), "\"b2b\"");
+        assert_eq!(yaml("a b: c"), "\"a b: c\"");
+        assert_eq!(yaml("${B2B_IMAGE}"), "\"$${B2B_IMAGE}\"");
+        assert_eq!(yaml("say \"hi\""), r#""say \"hi\"""#);
+        assert_eq!(yaml("it's"), "\"it's\"");
+        assert_eq!(yaml("a\nb"), r#""a\nb""#);
+        assert_eq!(yaml_list(&["-x".into(), "$y".into()]), r#"["-x", "$$y"]"#);
+    }
+
+    #[test]
+    fn start_limit_window_covers_the_whole_backoff_budget() {
+        let spec = |max_restarts, backoff_ms, backoff_max_ms| RestartSpec {
+            policy: RestartPolicy::OnFailure,
+            max_restarts,
+            backoff_ms,
+            backoff_max_ms,
+        };
+        // 0.5 + 1 + 2 + 3 + 3 delays, plus 3s of slack.
+        assert_eq!(start_limit_secs(&spec(5, 500, 3000)), 13);
+        assert_eq!(start_limit_secs(&spec(0, 500, 3000)), 3);
+        // Rounded up to whole seconds.
+        assert_eq!(start_limit_secs(&spec(1, 100, 100)), 1);
+        assert_eq!(start_limit_secs(&spec(2, 300, 1000)), 2);
+    }
+}
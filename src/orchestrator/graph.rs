@@ -0,0 +1,223 @@
+use super::plan::PlanTopology;
+use anyhow::{Result, bail};
+use std::net::{IpAddr, SocketAddr};
+
+/// For each role (by plan index), the indices of the roles it depends on:
+/// explicit `depends_on` entries plus any role whose `sip_bind` is the
+/// address this role dials, directly or as `@name`.
+pub fn dependencies(topo: &PlanTopology) -> Vec<Vec<usize>> {
+    topo.roles
+        .iter()
+        .enumerate()
+        .map(|(i, r)| {
+            let mut deps: Vec<usize> = r
+                .depends_on
+                .iter()
+                .filter_map(|d| topo.index_of(d))
+                .collect();
+            // `@name` targets depend on the named role.
+            if let Some(j) = r
+                .params
+                .sip_target()
+                .and_then(|t| t.strip_prefix('@'))
+                .and_then(|peer| topo.index_of(peer))
+            {
+                if !deps.contains(&j) {
+                    deps.push(j);
+                }
+            }
+            if let Some(target) = r.params.sip_target() {
+                for (j, other) in topo.roles.iter().enumerate() {
+                    let hit = other
+                        .params
+                        .sip_bind()
+                        .is_some_and(|b| target_matches_bind(target, b));
+                    if j != i && hit && !deps.contains(&j) {
+                        deps.push(j);
+                    }
+                }
+            }
+            deps
+        })
+        .collect()
+}
+
+/// Group roles into startup layers so every role's dependencies sit in an
+/// earlier layer. Fails with the offending path when the plan has a cycle.
+pub fn startup_layers(topo: &PlanTopology) -> Result<Vec<Vec<usize>>> {
+    let deps = dependencies(topo);
+    let mut placed = vec![false; deps.len()];
+    let mut layers: Vec<Vec<usize>> = Vec::new();
+    while placed.iter().any(|p| !p) {
+        let layer: Vec<usize> = (0..deps.len())
+            .filter(|&i| !placed[i] && deps[i].iter().all(|&d| placed[d]))
+            .collect();
+        if layer.is_empty() {
+            bail!("dependency cycle: {}", describe_cycle(topo, &deps, &placed));
+        }
+        for &i in &layer {
+            placed[i] = true;
+        }
+        layers.push(layer);
+    }
+    Ok(layers)
+}
+
+/// Roles that some other role depends on; only these gate startup.
+pub fn has_dependents(topo: &PlanTopology) -> Vec<bool> {
+    let mut v = vec![false; topo.roles.len()];
+    for deps in dependencies(topo) {
+        for d in deps {
+            v[d] = true;
+        }
+    }
+    v
+}
+
+// Every unplaced role has at least one unplaced dependency, so following
+// them from any unplaced role must revisit a node.
+fn describe_cycle(topo: &PlanTopology, deps: &[Vec<usize>], placed: &[bool]) -> String {
+    let Some(mut cur) = placed.iter().position(|p| !p) else {
+        return String::new();
+    };
+    let mut path: Vec<usize> = Vec::new();
+    loop {
+        if let Some(pos) = path.iter().position(|&n| n == cur) {
+            let mut names: Vec<&str> = path[pos..]
+                .iter()
+                .map(|&n| topo.roles[n].name.as_str())
+                .collect();
+            names.push(&topo.roles[cur].name);
+            return names.join(" -> ");
+        }
+        path.push(cur);
+        match deps[cur].iter().find(|&&d| !placed[d]) {
+            Some(&next) => cur = next,
+            None => return String::new(),
+        }
+    }
+}
+
+/// Host and port of a `sip:[user@]host:port[;params]` URI.
+fn target_addr(target: &str) -> Option<(&str, u16)> {
+    let rest = target.strip_prefix("sip:")?;
+    let rest = rest.split(';').next()?;
+    let host_port = rest.rsplit('@').next()?;
+    let (host, port) = host_port.rsplit_once(':')?;
+    Some((host.trim_matches(['[', ']']), port.parse().ok()?))
+}
+
+// A wildcard bind (0.0.0.0:5062) accepts a target on any host at that port.
+fn target_matches_bind(target: &str, bind: &str) -> bool {
+    let Some((host, port)) = target_addr(target) else {
+        return false;
+    };
+    let Ok(bind) = bind.parse::<SocketAddr>() else {
+        return false;
+    };
+    if bind.port() != port {
+        return false;
+    }
+    if bind.ip().is_unspecified() {
+        return true;
+    }
+    match host.parse::<IpAddr>() {
+        Ok(ip) => ip == bind.ip(),
+        Err(_) => host == "localhost" && bind.ip().is_loopback(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::{RestartSpec, RoleParams, RoleSpec};
+
+    fn role(name: &str, depends_on: &[&str], params: RoleParams) -> RoleSpec {
+        RoleSpec {
+            name: name.into(),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            restart: RestartSpec::default(),
+            stop_grace_ms: None,
+            params,
+        }
+    }
+
+    fn sink(bind: &str) -> RoleParams {
+        RoleParams::Sink(toml::from_str(&format!("sip_bind = {bind:?}")).unwrap())
+    }
+
+    fn mixer(bind: &str, target: &str) -> RoleParams {
+        RoleParams::Mixer(
+            toml::from_str(&format!("sip_bind = {bind:?}\nsip_target = {target:?}")).unwrap(),
+        )
+    }
+
+    fn source(target: &str) -> RoleParams {
+        RoleParams::Source(toml::from_str(&format!("sip_target = {target:?}")).unwrap())
+    }
+
+    fn topo(roles: Vec<RoleSpec>) -> PlanTopology {
+        PlanTopology {
+            roles,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pipeline_starts_downstream_first() {
+        let t = topo(vec![
+            role("src", &[], source("sip:b2b@127.0.0.1:5070")),
+            role("mix", &[], mixer("127.0.0.1:5070", "@snk")),
+            role("snk", &[], sink("127.0.0.1:5062")),
+        ]);
+        assert_eq!(startup_layers(&t).unwrap(), vec![vec![2], vec![1], vec![0]]);
+        assert_eq!(has_dependents(&t), vec![false, true, true]);
+    }
+
+    #[test]
+    fn independent_roles_share_a_layer() {
+        let t = topo(vec![
+            role("a", &[], sink("127.0.0.1:5062")),
+            role("b", &[], sink("127.0.0.1:5064")),
+            role("c", &["a", "b"], source("sip:127.0.0.1:5099")),
+            role("d", &[], source("sip:127.0.0.1:5064")),
+        ]);
+        assert_eq!(startup_layers(&t).unwrap(), vec![vec![0, 1], vec![2, 3]]);
+        assert_eq!(dependencies(&t)[3], vec![1]);
+    }
+
+    #[test]
+    fn targets_match_wildcard_and_loopback_binds() {
+        assert!(target_matches_bind("sip:10.1.2.3:5062", "0.0.0.0:5062"));
+        assert!(target_matches_bind(
+            "sip:b2b@localhost:5062;transport=udp",
+            "127.0.0.1:5062"
+        ));
+        assert!(target_matches_bind("sip:[::1]:5062", "[::1]:5062"));
+        assert!(!target_matches_bind("sip:127.0.0.1:5064", "127.0.0.1:5062"));
+        assert!(!target_matches_bind("sip:10.1.2.3:5062", "127.0.0.1:5062"));
+        assert!(!target_matches_bind("@sink", "127.0.0.1:5062"));
+    }
+
+    #[test]
+    fn cycle_is_reported_without_its_lead_in() {
+        let t = topo(vec![
+            role("tail", &["a"], source("sip:127.0.0.1:5099")),
+            role("a", &["b"], sink("127.0.0.1:5062")),
+            role("b", &[], mixer("127.0.0.1:5064", "@a")),
+            role("ok", &[], sink("127.0.0.1:5066")),
+        ]);
+        let e = startup_layers(&t).unwrap_err().to_string();
+        assert_eq!(e, "dependency cycle: a -> b -> a");
+    }
+
+    #[test]
+    fn self_dependency_via_own_bind_is_ignored() {
+        let t = topo(vec![role(
+            "m",
+            &[],
+            mixer("127.0.0.1:5070", "sip:127.0.0.1:5070"),
+        )]);
+        assert_eq!(startup_layers(&t).unwrap(), vec![vec![0]]);
+    }
+}
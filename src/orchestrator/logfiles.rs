@@ -0,0 +1,148 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+/// Rotated generations kept next to the live file (`x.log.1` .. `x.log.N`).
+const KEEP: u32 = 5;
+
+/// Append-only log file that rotates once it reaches `max_bytes` or has
+/// been open for `max_age`.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    opened: Instant,
+    max_bytes: u64,
+    max_age: Option<Duration>,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, max_bytes: u64, max_age: Option<Duration>) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening log file {}", path.display()))?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            written,
+            opened: Instant::now(),
+            max_bytes,
+            max_age,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write one line; logging must never take the run down, so errors
+    /// are dropped.
+    pub fn write_line(&mut self, line: &str) {
+        let age_due = self.max_age.is_some_and(|a| self.opened.elapsed() >= a);
+        if (self.written > 0 && self.written + line.len() as u64 >= self.max_bytes) || age_due {
+            let _ = self.rotate();
+        }
+        if writeln!(self.file, "{line}").is_ok() {
+            self.written += line.len() as u64 + 1;
+        }
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        let generation = |n: u32| {
+            let mut p = self.path.clone().into_os_string();
+            p.push(format!(".{n}"));
+            PathBuf::from(p)
+        };
+        for n in (1..KEEP).rev() {
+            let _ = std::fs::rename(generation(n), generation(n + 1));
+        }
+        std::fs::rename(&self.path, generation(1))?;
+        *self = Self::open(&self.path, self.max_bytes, self.max_age)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Removes a test's directory when dropped.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn tempdir(test: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("b2b-logfiles-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn read(dir: &TempDir, name: &str) -> Option<String> {
+        std::fs::read_to_string(dir.0.join(name)).ok()
+    }
+
+    #[test]
+    fn rotates_by_size_and_keeps_five_generations() {
+        let dir = tempdir("size");
+        let mut f = RotatingFile::open(&dir.0.join("sink.log"), 20, None).unwrap();
+        // 8 bytes a line: two fit under the 20-byte limit.
+        for n in 1..=20 {
+            f.write_line(&format!("line {n:02}"));
+        }
+        assert_eq!(f.path(), dir.0.join("sink.log"));
+        assert_eq!(read(&dir, "sink.log").unwrap(), "line 19\nline 20\n");
+        for (gen, first) in [(1, 17), (2, 15), (3, 13), (4, 11), (5, 9)] {
+            let want = format!("line {first:02}\nline {:02}\n", first + 1);
+            assert_eq!(read(&dir, &format!("sink.log.{gen}")).unwrap(), want);
+        }
+        assert_eq!(read(&dir, "sink.log.6"), None);
+        assert_eq!(std::fs::read_dir(&dir.0).unwrap().count(), 6);
+    }
+
+    #[test]
+    fn appends_to_an_existing_file_and_counts_its_size() {
+        let dir = tempdir("append");
+        let path = dir.0.join("merged.log");
+        std::fs::write(&path, "0123456789012345\n").unwrap();
+        let mut f = RotatingFile::open(&path, 20, None).unwrap();
+        f.write_line("next");
+        assert_eq!(read(&dir, "merged.log").unwrap(), "next\n");
+        assert_eq!(read(&dir, "merged.log.1").unwrap(), "0123456789012345\n");
+    }
+
+    #[test]
+    fn a_single_long_line_is_written_whole() {
+        let dir = tempdir("long");
+        let mut f = RotatingFile::open(&dir.0.join("x.log"), 4, None).unwrap();
+        f.write_line("much longer than the limit");
+        assert_eq!(read(&dir, "x.log").unwrap(), "much longer than the limit\n");
+        assert_eq!(read(&dir, "x.log.1"), None);
+    }
+
+    #[test]
+    fn rotates_by_age() {
+        let dir = tempdir("age");
+        let age = Duration::from_millis(50);
+        let mut f = RotatingFile::open(&dir.0.join("src.log"), u64::MAX, Some(age)).unwrap();
+        f.write_line("first");
+        f.write_line("still first");
+        std::thread::sleep(age);
+        f.write_line("second");
+        assert_eq!(read(&dir, "src.log").unwrap(), "second\n");
+        assert_eq!(read(&dir, "src.log.1").unwrap(), "first\nstill first\n");
+    }
+}
@@ -0,0 +1,508 @@
+mod compose;
+mod expect;
+mod export;
+mod graph;
+mod logfiles;
+mod pidfile;
+mod plan;
+mod ports;
+mod reload;
+mod report;
+mod supervisor;
+mod tui;
+mod vars;
+
+pub use tui::restore_terminal;
+
+use crate::{
+    cli::{Cli, RoleKind},
+    error::B2bError,
+    logging, util,
+};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use logfiles::RotatingFile;
+use plan::{LogSettings, PlanTopology, RoleParams};
+use report::ChildOutput;
+use std::{
+    os::unix::process::CommandExt,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicI32, Ordering},
+        mpsc,
+    },
+    time::Duration,
+};
+
+/// Process group shared by all children (0 until the first spawn), so a
+/// terminal Ctrl-C only reaches the orchestrator and shutdown can sweep
+/// grandchildren such as the sink's `aplay`.
+static CHILD_PGID: AtomicI32 = AtomicI32::new(0);
+
+pub fn run(args: &Cli) -> Result<()> {
+    let tag = logging::role_tag("orchestrator");
+    logging::println_tag(&tag, "starting");
+
+    if args.validate_plan && args.plan.is_none() {
+        anyhow::bail!(B2bError::Usage("--validate-plan requires --plan".into()));
+    }
+    if (!args.profile.is_empty() || !args.set.is_empty()) && args.plan.is_none() {
+        anyhow::bail!(B2bError::Usage("--profile and --set require --plan".into()));
+    }
+    crate::roles::check_codec(args.codec.as_deref(), args.sample_rate, args.ptime_ms)?;
+    if let Some(plan_path) = &args.plan {
+        let mut topo = plan::load(plan_path, &args.profile, &args.set)
+            .map_err(|e| B2bError::Usage(format!("{e:#}")))?;
+        apply_log_args(args, &mut topo).map_err(|e| B2bError::Usage(format!("{e:#}")))?;
+        let layers = graph::startup_layers(&topo)
+            .map_err(|e| B2bError::Usage(format!("invalid plan {}: {e}", plan_path.display())))?;
+        if args.validate_plan {
+            let order: Vec<String> = layers
+                .iter()
+                .map(|l| {
+                    let names: Vec<&str> = l.iter().map(|&i| topo.roles[i].name.as_str()).collect();
+                    names.join(", ")
+                })
+                .collect();
+            logging::println_tag(
+                &tag,
+                &format!(
+                    "plan OK: {} (startup order: {})",
+                    plan_path.display(),
+                    order.join(" -> ")
+                ),
+            );
+            return Ok(());
+        }
+        let reserved =
+            ports::resolve(&mut topo, &[]).map_err(|e| B2bError::Usage(format!("{e:#}")))?;
+        if let Some(format) = args.export {
+            let files = export::export(format, &topo, &layers, args, plan_path)
+                .and_then(|a| export::write(&a, args.export_dir.as_deref()))?;
+            for f in files {
+                logging::println_tag(&tag, &format!("exported {}", f.display()));
+            }
+            return Ok(());
+        }
+        if args.dry_run {
+            print_plan_cmds(&topo, &layers, args);
+            return Ok(());
+        }
+        for sub in &topo.resolved.substitutions {
+            logging::println_tag(
+                &tag,
+                &format!("resolved {} = {} (from {})", sub.key, sub.to, sub.from),
+            );
+        }
+        let merged_log = open_merged_log(&topo)?;
+        pidfile::sweep(&tag);
+        let pids = pidfile::PidFile::create()
+            .map_err(|e| logging::println_tag(&tag, &format!("warning: pid file: {e:#}")))
+            .ok();
+        let mut sup = supervisor::Supervisor::new(args, topo, reserved, pids, &tag);
+        if args.tui {
+            let tui = tui::Tui::start().map_err(|e| B2bError::Usage(format!("{e:#}")))?;
+            sup.attach_tui(tui);
+        }
+        // Supervise restarts until a child ends the run or Ctrl-C
+        let mut res = sup.start(&layers).and_then(|_| sup.run());
+        let mut rep = sup.report(plan_path);
+        rep.merged_log = merged_log;
+        if let Some(exp) = &sup.topo().expect {
+            rep.expectations = expect::evaluate(exp, &rep, args.ptime_ms);
+            for o in &rep.expectations {
+                let verdict = if o.pass { "PASS" } else { "FAIL" };
+                logging::println_tag(
+                    &tag,
+                    &format!("expect {}: {verdict} ({})", o.name, o.detail),
+                );
+            }
+            let failed = rep.expectations.iter().filter(|o| !o.pass).count();
+            if failed > 0 && res.is_ok() {
+                res = Err(B2bError::Expect(format!(
+                    "{failed} of {} expectation(s) failed",
+                    rep.expectations.len()
+                ))
+                .into());
+            }
+        }
+        rep.set_result(&res);
+        // A report that cannot be written is logged; the run's own result
+        // (and exit code) stands.
+        let written = [
+            args.report
+                .as_deref()
+                .map(|p| (p, report::write_json(p, &rep))),
+            args.report_md
+                .as_deref()
+                .map(|p| (p, report::write_markdown(p, &rep))),
+        ];
+        for (path, written) in written.into_iter().flatten() {
+            match written {
+                Ok(()) => logging::println_tag(&tag, &format!("report: {}", path.display())),
+                Err(e) => logging::println_tag(&tag, &format!("warning: {e:#}")),
+            }
+        }
+        return res;
+    }
+    // Wait for ctrl-c then exit children on future pass; skeleton exits immediately without children alive.
+    Ok(())
+}
+
+/// `--log-dir` and the rotation flags override the plan's `[logs]`.
+fn apply_log_args(args: &Cli, topo: &mut PlanTopology) -> Result<()> {
+    if let Some(dir) = &args.log_dir {
+        let logs = topo
+            .logs
+            .get_or_insert_with(|| LogSettings::new(dir.clone()));
+        logs.dir = dir.clone();
+    }
+    let Some(logs) = topo.logs.as_mut() else {
+        if args.log_rotate_mb.is_some() || args.log_rotate_every.is_some() {
+            anyhow::bail!("--log-rotate-mb/--log-rotate-every need --log-dir or a plan [logs] dir");
+        }
+        return Ok(());
+    };
+    if let Some(mb) = args.log_rotate_mb {
+        anyhow::ensure!(mb > 0, "--log-rotate-mb must be > 0");
+        logs.rotate_size_mb = mb;
+    }
+    if let Some(every) = &args.log_rotate_every {
+        let d =
+            plan::parse_duration(every).map_err(|e| anyhow::anyhow!("--log-rotate-every: {e}"))?;
+        anyhow::ensure!(
+            d >= Duration::from_secs(1),
+            "--log-rotate-every must be at least 1s"
+        );
+        logs.rotate_every = Some(d);
+    }
+    Ok(())
+}
+
+/// Load the plan again for a reload, with the same profiles, `--set` and
+/// log arguments. `auto` ports the running plan holds are kept.
+fn reload_plan(
+    args: &Cli,
+    running: &PlanTopology,
+) -> Result<(PlanTopology, Vec<Option<ports::Reservation>>)> {
+    let path = args.plan.as_deref().context("no plan to reload")?;
+    let mut topo = plan::load(path, &args.profile, &args.set)?;
+    apply_log_args(args, &mut topo)?;
+    graph::startup_layers(&topo).with_context(|| format!("invalid plan {}", path.display()))?;
+    let reserved = ports::resolve(&mut topo, &running.resolved.substitutions)?;
+    Ok((topo, reserved))
+}
+
+/// Create the log directory and mirror the console into `merged.log`.
+fn open_merged_log(topo: &PlanTopology) -> Result<Option<String>> {
+    let Some(logs) = &topo.logs else {
+        return Ok(None);
+    };
+    std::fs::create_dir_all(&logs.dir)
+        .with_context(|| format!("creating log dir {}", logs.dir.display()))?;
+    let merged = RotatingFile::open(
+        &logs.dir.join("merged.log"),
+        logs.rotate_size_mb << 20,
+        logs.rotate_every,
+    )?;
+    let path = merged.path().display().to_string();
+    let merged = Mutex::new(merged);
+    logging::set_mirror(move |line| {
+        if let Ok(mut f) = merged.lock() {
+            f.write_line(line);
+        }
+    });
+    Ok(Some(path))
+}
+
+/// Role-specific command line arguments for one plan instance.
+fn role_args(params: &RoleParams) -> Vec<String> {
+    let mut extra: Vec<String> = Vec::new();
+    match params {
+        RoleParams::Sink(sink) => {
+            extra.push("--sip-bind".into());
+            extra.push(sink.sip_bind.clone());
+            if let Some(ap) = sink.aplay_cmd.as_deref() {
+                extra.push("--aplay-cmd".into());
+                extra.push(ap.into());
+            }
+            if let (Some(min), Some(max)) = (sink.buffer_min_ms, sink.buffer_max_ms) {
+                extra.push("--sink-buffer-min-ms".into());
+                extra.push(min.to_string());
+                extra.push("--sink-buffer-max-ms".into());
+                extra.push(max.to_string());
+            }
+            if let Some(mode) = sink.buffer_mode {
+                extra.push("--sink-buffer-mode".into());
+                extra.push(value_name(&mode));
+            }
+            if let (Some(min), Some(max)) = (sink.jbuf_min_ms, sink.jbuf_max_ms) {
+                extra.push("--sink-jbuf-min-ms".into());
+                extra.push(min.to_string());
+                extra.push("--sink-jbuf-max-ms".into());
+                extra.push(max.to_string());
+            }
+            if let Some(jt) = sink.jbuf_type {
+                extra.push("--sink-jbuf-type".into());
+                extra.push(value_name(&jt));
+            }
+        }
+        RoleParams::Mixer(mixer) => {
+            extra.push("--sip-bind".into());
+            extra.push(mixer.sip_bind.clone());
+            extra.push("--target".into());
+            extra.push(mixer.sip_target.clone());
+            if let Some(seq) = mixer.dtmf_seq.as_deref() {
+                extra.push("--dtmf-seq".into());
+                extra.push(seq.into());
+            }
+            if let Some(p) = mixer.dtmf_period_ms {
+                extra.push("--dtmf-period-ms".into());
+                extra.push(p.to_string());
+            }
+            if let Some(g) = mixer.mix_gain_in {
+                extra.push("--mix-gain-in".into());
+                extra.push(g.to_string());
+            }
+            if let Some(g) = mixer.mix_gain_dtmf {
+                extra.push("--mix-gain-dtmf".into());
+                extra.push(g.to_string());
+            }
+        }
+        RoleParams::Source(source) => {
+            extra.push("--target".into());
+            extra.push(source.sip_target.clone());
+            if let Some(audio) = source.audio_file.as_deref() {
+                extra.push("--audio-file".into());
+                extra.push(audio.to_string_lossy().to_string());
+            }
+            if let Some(list) = source.playlist.as_deref() {
+                extra.push("--playlist".into());
+                extra.push(list.into());
+            }
+            if source.shuffle == Some(true) {
+                extra.push("--shuffle".into());
+            }
+            if let Some(seed) = source.seed {
+                extra.push("--seed".into());
+                extra.push(seed.to_string());
+            }
+            if let Some(ms) = source.crossfade_ms {
+                extra.push("--crossfade-ms".into());
+                extra.push(ms.to_string());
+            }
+            if let Some(q) = source.resample_quality {
+                extra.push("--resample-quality".into());
+                extra.push(value_name(&q));
+            }
+            if let Some(ms) = source.preroll_ms {
+                extra.push("--preroll-ms".into());
+                extra.push(ms.to_string());
+            }
+        }
+    }
+    extra
+}
+
+fn exe() -> Result<PathBuf> {
+    std::env::current_exe().context("current_exe")
+}
+
+/// Environment every role child runs with: raw lines, since the
+/// orchestrator adds timestamps and `[ROLE]` tags itself.
+const CHILD_ENV: [(&str, &str); 1] = [("B2B_CHILD_RAW", "1")];
+
+/// Full argument list (after the executable) for one role child.
+fn child_args(role: RoleKind, extra: &[String], args: &Cli) -> Vec<String> {
+    let mut argv: Vec<String> = vec!["--role".into(), role_str(role).into()];
+    // pass consistent output options
+    argv.push("--log-format".into());
+    argv.push(value_name(&args.log_format));
+    argv.push("--color".into());
+    argv.push(value_name(&args.color));
+    // every leg of the pipeline runs at the same rate and codec
+    argv.push("--sample-rate".into());
+    argv.push(value_name(&args.sample_rate));
+    if let Some(codec) = &args.codec {
+        argv.push("--codec".into());
+        argv.push(codec.clone());
+    }
+    argv.extend(extra.iter().cloned());
+    argv
+}
+
+fn spawn_role(role: RoleKind, extra: &[String], args: &Cli) -> Result<Child> {
+    let mut cmd = Command::new(exe()?);
+    cmd.args(child_args(role, extra, args));
+    cmd.envs(CHILD_ENV);
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    let parent = std::process::id() as libc::pid_t;
+    let pgid = CHILD_PGID.load(Ordering::Relaxed);
+    // Only async-signal-safe calls between fork and exec.
+    unsafe {
+        cmd.pre_exec(move || {
+            // Die with the orchestrator, even on SIGKILL. The signal follows
+            // the spawning thread, which is the supervisor's (main) thread.
+            libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGTERM);
+            if libc::getppid() != parent {
+                // Orchestrator already gone before prctl took effect.
+                libc::_exit(1);
+            }
+            // Join the children's group; start a new one if it is empty.
+            if pgid == 0 || libc::setpgid(0, pgid) != 0 {
+                libc::setpgid(0, 0);
+            }
+            Ok(())
+        });
+    }
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("spawning role {}", role_str(role)))?;
+    let pg = unsafe { libc::getpgid(child.id() as libc::pid_t) };
+    if pg > 0 {
+        CHILD_PGID.store(pg, Ordering::Relaxed);
+    }
+    Ok(child)
+}
+
+/// SIGKILL whatever is left in the children's process group.
+fn kill_child_group() {
+    let pg = CHILD_PGID.load(Ordering::Relaxed);
+    if pg > 0 {
+        unsafe {
+            libc::killpg(pg, libc::SIGKILL);
+        }
+    }
+}
+
+/// CLI spelling of a clap value enum, e.g. `BufferMode::Adaptive` -> "adaptive".
+fn value_name<T: ValueEnum>(v: &T) -> String {
+    v.to_possible_value()
+        .map(|p| p.get_name().to_string())
+        .unwrap_or_default()
+}
+
+fn role_str(r: RoleKind) -> &'static str {
+    match r {
+        RoleKind::Orchestrator => "orchestrator",
+        RoleKind::Source => "source",
+        RoleKind::Sink => "sink",
+        RoleKind::Mixer => "mixer",
+    }
+}
+
+fn print_plan_cmds(topo: &PlanTopology, layers: &[Vec<usize>], args: &Cli) {
+    let exe = std::env::current_exe()
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+        .unwrap_or_else(|| "b2b".into());
+    let orch = logging::role_tag("orchestrator");
+    logging::println_tag(&orch, "dry-run: merged plan:");
+    for line in topo.merged.lines() {
+        logging::println_tag(&orch, &format!("dry-run: | {line}"));
+    }
+    for (k, v) in &topo.resolved.vars {
+        logging::println_tag(&orch, &format!("dry-run: var {k} = {v:?}"));
+    }
+    for sub in &topo.resolved.substitutions {
+        logging::println_tag(
+            &orch,
+            &format!("dry-run: {} = {:?} (from {:?})", sub.key, sub.to, sub.from),
+        );
+    }
+    for &i in layers.iter().flatten() {
+        let spec = &topo.roles[i];
+        let kind = spec.params.kind();
+        let mut cmd = format!("dry-run: [{}]", spec.name);
+        for (k, v) in CHILD_ENV {
+            cmd.push_str(&format!(" {k}={}", export::sh_quote(v)));
+        }
+        cmd.push(' ');
+        cmd.push_str(&export::sh_quote(&exe));
+        for a in child_args(kind, &role_args(&spec.params), args) {
+            cmd.push(' ');
+            cmd.push_str(&export::sh_quote(&a));
+        }
+        logging::println_tag(&orch, &cmd);
+    }
+    if let Some(d) = topo.duration {
+        logging::println_tag(&orch, &format!("dry-run: duration {:.1}s", d.as_secs_f64()));
+    }
+    for step in &topo.timeline {
+        let line = format!(
+            "dry-run: timeline t+{:.1}s: {} {}",
+            step.at.as_secs_f64(),
+            step.action.name(),
+            step.role.as_deref().unwrap_or_default()
+        );
+        logging::println_tag(&orch, line.trim_end());
+    }
+}
+
+fn pipe_child_output(
+    name: &str,
+    kind: RoleKind,
+    child: &mut Child,
+    tx: mpsc::Sender<String>,
+    output: Arc<Mutex<ChildOutput>>,
+) {
+    let (out, err) = util::child_pipes(child);
+    let pid = child.id();
+    let tag = logging::instance_tag(role_str(kind), name);
+    // READY lines carry the role kind; report the instance name instead so
+    // several instances of one kind are tracked separately.
+    let txo = tx.clone();
+    let name_stdout = name.to_string();
+    let tag_stdout = tag.clone();
+    if let Some(o) = out {
+        let output = output.clone();
+        util::spawn_reader_thread(o, tag.clone(), move |_tag, line| {
+            // The C shim prints its metrics lines on stdout.
+            on_child_line(&tag_stdout, kind, &name_stdout, pid, line, &txo, &output);
+        });
+    }
+    if let Some(e) = err {
+        let name_stderr = name.to_string();
+        let tag_stderr = tag.clone();
+        util::spawn_reader_thread(e, tag.clone(), move |_tag, line| {
+            on_child_line(&tag_stderr, kind, &name_stderr, pid, line, &tx, &output);
+        });
+    }
+}
+
+/// Pick READY, errors and metrics out of one child line (text or JSON
+/// record) and print it.
+fn on_child_line(
+    tag: &str,
+    kind: RoleKind,
+    name: &str,
+    pid: u32,
+    line: &str,
+    tx: &mpsc::Sender<String>,
+    output: &Mutex<ChildOutput>,
+) {
+    let rec = logging::parse_record(line);
+    let msg = rec.as_ref().map_or(line, |r| r.msg.as_str());
+    if parse_ready_role(msg).is_some() {
+        let _ = tx.send(name.to_string());
+    }
+    if let Ok(mut o) = output.lock() {
+        o.observe(line, msg);
+    }
+    logging::forward(tag, role_str(kind), name, pid, line);
+}
+
+fn parse_ready_role(line: &str) -> Option<&str> {
+    if !line.starts_with("READY ") {
+        return None;
+    }
+    // Expect fields like: READY role=<role> ...
+    for tok in line.split_whitespace() {
+        if let Some(val) = tok.strip_prefix("role=") {
+            return Some(val);
+        }
+    }
+    None
+}
@@ -0,0 +1,272 @@
+use crate::logging;
+use anyhow::{Context, Result};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// `orch-<pid>.pids` in the per-user runtime dir: the orchestrator's pid
+/// and every child it spawned, held under `flock` for the whole run. A
+/// file nobody holds the lock on belongs to a dead orchestrator.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    file: File,
+}
+
+impl PidFile {
+    pub fn create() -> Result<Self> {
+        let dir = runtime_dir();
+        std::fs::create_dir_all(&dir).with_context(|| format!("creating {}", dir.display()))?;
+        let path = dir.join(format!("orch-{}.pids", std::process::id()));
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            anyhow::bail!(
+                "locking {}: {}",
+                path.display(),
+                std::io::Error::last_os_error()
+            );
+        }
+        writeln!(file, "orchestrator {}", std::process::id())?;
+        Ok(Self { path, file })
+    }
+
+    /// Record a spawned child; failures only cost the next run's sweep.
+    pub fn add(&mut self, name: &str, pid: u32) {
+        let _ = writeln!(self.file, "child {pid} {name}");
+        let _ = self.file.flush();
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn runtime_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir);
+    base.join(format!("b2b-{}", unsafe { libc::getuid() }))
+}
+
+/// Find pid files left by orchestrators that died without cleaning up and
+/// stop any of their children that are still running (they hold SIP
+/// ports). Pids are only signalled if they still look like a b2b role.
+pub fn sweep(tag: &str) {
+    let Ok(entries) = std::fs::read_dir(runtime_dir()) else {
+        return;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        let is_pids = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with("orch-") && n.ends_with(".pids"));
+        if !is_pids {
+            continue;
+        }
+        let Ok(file) = File::open(&path) else {
+            continue;
+        };
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            // Held: that orchestrator is still running.
+            continue;
+        }
+        if sweep_file(tag, &path) {
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
+
+/// Returns false if the file's orchestrator is alive after all (it may
+/// have created the file but not locked it yet).
+fn sweep_file(tag: &str, path: &Path) -> bool {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return false;
+    };
+    let mut orch = "?";
+    for line in text.lines() {
+        let mut toks = line.split_whitespace();
+        match (toks.next(), toks.next(), toks.next()) {
+            (Some("orchestrator"), Some(pid), _) => {
+                let alive = pid
+                    .parse::<i32>()
+                    .is_ok_and(|p| unsafe { libc::kill(p, 0) } == 0);
+                if alive {
+                    return false;
+                }
+                orch = pid;
+            }
+            (Some("child"), Some(pid), Some(name)) => {
+                let Ok(pid) = pid.parse::<i32>() else {
+                    continue;
+                };
+                if !is_b2b_role(pid) {
+                    continue;
+                }
+                logging::println_tag(
+                    tag,
+                    &format!(
+                        "stale role {name} pid={pid} left by orchestrator pid={orch}; sending SIGTERM"
+                    ),
+                );
+                unsafe {
+                    libc::kill(pid, libc::SIGTERM);
+                }
+            }
+            _ => {}
+        }
+    }
+    true
+}
+
+/// Whether `pid` is alive and runs `b2b ... --role ...` (guards against
+/// pid reuse).
+fn is_b2b_role(pid: i32) -> bool {
+    let Ok(cmdline) = std::fs::read(format!("/proc/{pid}/cmdline")) else {
+        return false;
+    };
+    let mut args = cmdline
+        .split(|&b| b == 0)
+        .map(|a| String::from_utf8_lossy(a).into_owned());
+    let exe_ok = args.next().is_some_and(|a| {
+        Path::new(&a)
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with("b2b"))
+    });
+    exe_ok && args.any(|a| a == "--role")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        os::unix::process::ExitStatusExt,
+        process::{Child, Command},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn tempdir(test: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("b2b-pidfile-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Killed when dropped, so a failed test leaves nothing running.
+    struct Proc(Child);
+
+    impl Drop for Proc {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    impl Proc {
+        fn pid(&self) -> i32 {
+            self.0.id() as i32
+        }
+
+        fn wait_exit(&mut self) -> Option<std::process::ExitStatus> {
+            let until = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < until {
+                if let Some(status) = self.0.try_wait().unwrap() {
+                    return Some(status);
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            None
+        }
+    }
+
+    fn dead_pid() -> i32 {
+        let mut ch = Command::new("true").spawn().unwrap();
+        ch.wait().unwrap();
+        ch.id() as i32
+    }
+
+    fn pids_file(dir: &TempDir, text: &str) -> PathBuf {
+        let path = dir.0.join("orch-1.pids");
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn stale_role_is_stopped_and_others_are_left_alone() {
+        let dir = tempdir("stale");
+        // A shell run under a b2b name with a --role argument looks like a
+        // role; a plain sleep stands for a pid reused by something else.
+        let b2b = dir.0.join("b2b");
+        std::fs::copy("/bin/sh", &b2b).unwrap();
+        let mut role = Proc(
+            Command::new(&b2b)
+                .args(["-c", "sleep 30; :", "--role", "sink"])
+                .spawn()
+                .unwrap(),
+        );
+        let mut other = Proc(Command::new("sleep").arg("30").spawn().unwrap());
+        // /proc/<pid>/cmdline is filled in once exec is done.
+        let until = Instant::now() + Duration::from_secs(5);
+        while !is_b2b_role(role.pid()) && Instant::now() < until {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(is_b2b_role(role.pid()));
+        assert!(!is_b2b_role(other.pid()));
+        assert!(!is_b2b_role(dead_pid()));
+
+        let path = pids_file(
+            &dir,
+            &format!(
+                "orchestrator {}\nchild {} sink\nchild {} mixer\nchild {} source\n",
+                dead_pid(),
+                role.pid(),
+                other.pid(),
+                dead_pid()
+            ),
+        );
+        assert!(sweep_file("[test]", &path));
+        let status = role.wait_exit().expect("stale role stopped");
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+        assert!(other.0.try_wait().unwrap().is_none());
+    }
+
+    #[test]
+    fn live_orchestrator_keeps_its_file() {
+        let dir = tempdir("live");
+        let path = pids_file(&dir, &format!("orchestrator {}\n", std::process::id()));
+        assert!(!sweep_file("[test]", &path));
+        assert!(!sweep_file("[test]", &dir.0.join("missing.pids")));
+    }
+
+    #[test]
+    fn malformed_lines_are_skipped() {
+        let dir = tempdir("malformed");
+        let mut other = Proc(Command::new("sleep").arg("30").spawn().unwrap());
+        let text = format!(
+            "orchestrator\norchestrator x\nchild\nchild nan sink\n\
+             child {} \ngarbage 1 2\n\u{fffd}\n",
+            other.pid()
+        );
+        let path = pids_file(&dir, &text);
+        assert!(sweep_file("[test]", &path));
+        assert!(other.0.try_wait().unwrap().is_none());
+    }
+}
@@ -0,0 +1,1163 @@
+use super::{
+    compose,
+    vars::{self, Resolved},
+};
+use crate::cli::{BufferMode, JbufType, ResampleQuality, RoleKind};
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{
+    Deserialize, Deserializer,
+    de::{
+        DeserializeSeed, Error as _, IntoDeserializer, MapAccess, Visitor,
+        value::MapAccessDeserializer,
+    },
+};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Top-level plan file. Unknown keys are rejected at every level so typos
+/// surface as errors (with line/column) instead of being silently ignored.
+///
+/// Roles are either listed as named instances (`[[role]]`) or, for the
+/// original single-pipeline layout, as `[topology]` source/mixer/sink tables.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlanFile {
+    topology: Option<LegacyTopology>,
+    #[serde(default)]
+    role: Vec<RoleSpec>,
+    #[serde(default)]
+    timeline: Vec<TimelineStep>,
+    #[serde(default, deserialize_with = "de_opt_duration")]
+    duration: Option<Duration>,
+    expect: Option<Checked<Expect>>,
+    logs: Option<Checked<LogSettings>>,
+    /// `${NAME}` values; already expanded by `vars::interpolate`.
+    #[serde(rename = "vars")]
+    _vars: Option<serde::de::IgnoredAny>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LegacyTopology {
+    source: Option<Checked<SourcePlan>>,
+    mixer: Option<Checked<MixerPlan>>,
+    sink: Option<Checked<SinkPlan>>,
+}
+
+#[derive(Debug, Default)]
+pub struct PlanTopology {
+    pub roles: Vec<RoleSpec>,
+    /// Scenario steps, sorted by `at`.
+    pub timeline: Vec<TimelineStep>,
+    /// Stop cleanly this long after startup completes.
+    pub duration: Option<Duration>,
+    pub expect: Option<Expect>,
+    pub logs: Option<LogSettings>,
+    /// `[vars]` and `${...}` substitutions, shown by `--dry-run`.
+    pub resolved: Resolved,
+    /// The plan after includes, profiles, `--set` and interpolation.
+    pub merged: String,
+    /// The plan file and its includes, watched by `--watch`.
+    pub sources: Vec<PathBuf>,
+}
+
+/// `[logs]`: per-role raw output and a merged file under `dir`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogSettings {
+    pub dir: PathBuf,
+    #[serde(default = "default_rotate_size_mb")]
+    pub rotate_size_mb: u64,
+    #[serde(default, deserialize_with = "de_opt_duration")]
+    pub rotate_every: Option<Duration>,
+}
+
+fn default_rotate_size_mb() -> u64 {
+    64
+}
+
+impl LogSettings {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            rotate_size_mb: default_rotate_size_mb(),
+            rotate_every: None,
+        }
+    }
+}
+
+impl Validate for LogSettings {
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.rotate_size_mb == 0 {
+            return Err("logs.rotate_size_mb must be > 0".into());
+        }
+        if self
+            .rotate_every
+            .is_some_and(|d| d < Duration::from_secs(1))
+        {
+            return Err("logs.rotate_every must be at least 1s".into());
+        }
+        Ok(())
+    }
+}
+
+/// `[expect]` assertions, checked once the run has stopped. Every key is
+/// optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Expect {
+    /// Every role reports READY within this long of being spawned.
+    #[serde(default, deserialize_with = "de_opt_duration")]
+    pub ready_within: Option<Duration>,
+    /// Unplanned child restarts (crashes, missed READY, stalls) across all
+    /// roles; timeline and dashboard restarts do not count.
+    pub max_restarts: Option<u32>,
+    /// STALL events across all roles.
+    pub max_stalls: Option<u32>,
+    /// Fraction of the frames each sink should have received (one per
+    /// ptime) since its call was established.
+    pub min_sink_rx_ratio: Option<f64>,
+}
+
+impl Validate for Expect {
+    fn validate(&self) -> std::result::Result<(), String> {
+        match self.min_sink_rx_ratio {
+            Some(r) if !(0.0..=1.0).contains(&r) => Err(format!(
+                "expect.min_sink_rx_ratio must be within 0.0..=1.0 (got {r})"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// One scheduled `[[timeline]]` action. `at` counts from the moment
+/// startup completes.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TimelineStep {
+    #[serde(deserialize_with = "de_duration")]
+    pub at: Duration,
+    pub action: TimelineAction,
+    pub role: Option<String>,
+    #[serde(default, deserialize_with = "de_signal")]
+    pub signal: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimelineAction {
+    /// SIGTERM the role and leave it down.
+    Stop,
+    /// SIGKILL the role; its restart policy decides what happens next.
+    Kill,
+    /// Stop and respawn the role; dependents redial once it is READY.
+    Restart,
+    /// Send `signal` to the role.
+    Signal,
+    /// End the run as if Ctrl-C was pressed.
+    End,
+}
+
+impl TimelineAction {
+    pub fn name(self) -> &'static str {
+        match self {
+            TimelineAction::Stop => "stop",
+            TimelineAction::Kill => "kill",
+            TimelineAction::Restart => "restart",
+            TimelineAction::Signal => "signal",
+            TimelineAction::End => "end",
+        }
+    }
+}
+
+/// `"15s"`, `"500ms"`, `"2m"` or `"1.5s"`.
+pub fn parse_duration(s: &str) -> std::result::Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let n: f64 = num
+        .parse()
+        .map_err(|_| format!("invalid duration {s:?} (expected e.g. \"15s\" or \"500ms\")"))?;
+    let secs = match unit {
+        "ms" => n / 1000.0,
+        "s" => n,
+        "m" => n * 60.0,
+        _ => {
+            return Err(format!("invalid duration {s:?}: unit must be ms, s or m"));
+        }
+    };
+    if !secs.is_finite() || secs < 0.0 {
+        return Err(format!("invalid duration {s:?}: out of range"));
+    }
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid duration {s:?}: too long"))
+}
+
+fn de_duration<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Duration, D::Error> {
+    parse_duration(&String::deserialize(d)?).map_err(serde::de::Error::custom)
+}
+
+fn de_opt_duration<'de, D: Deserializer<'de>>(
+    d: D,
+) -> std::result::Result<Option<Duration>, D::Error> {
+    de_duration(d).map(Some)
+}
+
+/// Signal by name (`"HUP"`, `"SIGUSR1"`) or number (1 to SIGRTMAX).
+fn de_signal<'de, D: Deserializer<'de>>(d: D) -> std::result::Result<Option<i32>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Num(i32),
+        Name(String),
+    }
+    let sig = match Raw::deserialize(d)? {
+        Raw::Num(n) if (1..=libc::SIGRTMAX()).contains(&n) => n,
+        Raw::Num(n) => {
+            return Err(serde::de::Error::custom(format!(
+                "invalid signal {n} (expected 1..={})",
+                libc::SIGRTMAX()
+            )));
+        }
+        Raw::Name(name) => {
+            let upper = name.to_ascii_uppercase();
+            match upper.strip_prefix("SIG").unwrap_or(&upper) {
+                "HUP" => libc::SIGHUP,
+                "INT" => libc::SIGINT,
+                "QUIT" => libc::SIGQUIT,
+                "KILL" => libc::SIGKILL,
+                "USR1" => libc::SIGUSR1,
+                "USR2" => libc::SIGUSR2,
+                "TERM" => libc::SIGTERM,
+                "CONT" => libc::SIGCONT,
+                "STOP" => libc::SIGSTOP,
+                _ => {
+                    return Err(serde::de::Error::custom(format!("unknown signal {name:?}")));
+                }
+            }
+        }
+    };
+    Ok(Some(sig))
+}
+
+/// One named role instance.
+#[derive(Debug)]
+pub struct RoleSpec {
+    pub name: String,
+    /// Roles that must be READY before this one starts, in addition to the
+    /// ones implied by `sip_target`.
+    pub depends_on: Vec<String>,
+    pub restart: RestartSpec,
+    /// SIGTERM-to-SIGKILL window when stopping; `--grace-ms` if unset.
+    pub stop_grace_ms: Option<u64>,
+    pub params: RoleParams,
+}
+
+/// When the supervisor respawns an exited role.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// Restart policy plus its budget and exponential backoff.
+#[derive(Debug, Clone, Copy)]
+pub struct RestartSpec {
+    pub policy: RestartPolicy,
+    pub max_restarts: u32,
+    pub backoff_ms: u64,
+    pub backoff_max_ms: u64,
+}
+
+impl Default for RestartSpec {
+    fn default() -> Self {
+        Self {
+            policy: RestartPolicy::Never,
+            max_restarts: 5,
+            backoff_ms: 500,
+            backoff_max_ms: 30_000,
+        }
+    }
+}
+
+impl RestartSpec {
+    /// Delay before restart number `attempt` (1-based): doubles each time,
+    /// capped at `backoff_max_ms`.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 1u64 << attempt.saturating_sub(1).min(16);
+        Duration::from_millis(
+            self.backoff_ms
+                .saturating_mul(factor)
+                .min(self.backoff_max_ms),
+        )
+    }
+}
+
+/// Keys every `[[role]]` entry accepts regardless of its kind.
+const ROLE_COMMON_KEYS: [&str; 6] = [
+    "depends_on",
+    "restart",
+    "max_restarts",
+    "restart_backoff_ms",
+    "restart_backoff_max_ms",
+    "stop_grace_ms",
+];
+
+const SOURCE_KEYS: [&str; 8] = [
+    "sip_target",
+    "audio_file",
+    "playlist",
+    "shuffle",
+    "seed",
+    "crossfade_ms",
+    "resample_quality",
+    "preroll_ms",
+];
+
+const MIXER_KEYS: [&str; 6] = [
+    "sip_bind",
+    "sip_target",
+    "dtmf_seq",
+    "dtmf_period_ms",
+    "mix_gain_in",
+    "mix_gain_dtmf",
+];
+
+const SINK_KEYS: [&str; 8] = [
+    "sip_bind",
+    "aplay_cmd",
+    "buffer_min_ms",
+    "buffer_max_ms",
+    "buffer_mode",
+    "jbuf_min_ms",
+    "jbuf_max_ms",
+    "jbuf_type",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EntryKind {
+    Source,
+    Mixer,
+    Sink,
+}
+
+impl EntryKind {
+    const ALL: [EntryKind; 3] = [EntryKind::Source, EntryKind::Mixer, EntryKind::Sink];
+
+    fn name(self) -> &'static str {
+        match self {
+            EntryKind::Source => "source",
+            EntryKind::Mixer => "mixer",
+            EntryKind::Sink => "sink",
+        }
+    }
+
+    /// Keys specific to this kind.
+    fn keys(self) -> &'static [&'static str] {
+        match self {
+            EntryKind::Source => &SOURCE_KEYS,
+            EntryKind::Mixer => &MIXER_KEYS,
+            EntryKind::Sink => &SINK_KEYS,
+        }
+    }
+}
+
+/// A `[[role]]` table: every key any kind accepts, typed as in the
+/// kind-specific plans. It is read through `KindKeys`, which rejects keys
+/// the entry's `kind` does not take, so every error keeps the line and
+/// column of the key or value at fault.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoleEntry {
+    name: String,
+    kind: EntryKind,
+    #[serde(default)]
+    depends_on: Vec<String>,
+    #[serde(default)]
+    restart: RestartPolicy,
+    max_restarts: Option<u32>,
+    restart_backoff_ms: Option<u64>,
+    restart_backoff_max_ms: Option<u64>,
+    stop_grace_ms: Option<u64>,
+    sip_target: Option<String>,
+    sip_bind: Option<String>,
+    audio_file: Option<PathBuf>,
+    playlist: Option<String>,
+    shuffle: Option<bool>,
+    seed: Option<u64>,
+    crossfade_ms: Option<u32>,
+    resample_quality: Option<ResampleQuality>,
+    preroll_ms: Option<u32>,
+    dtmf_seq: Option<String>,
+    dtmf_period_ms: Option<u32>,
+    mix_gain_in: Option<f32>,
+    mix_gain_dtmf: Option<f32>,
+    aplay_cmd: Option<String>,
+    buffer_min_ms: Option<u32>,
+    buffer_max_ms: Option<u32>,
+    buffer_mode: Option<BufferMode>,
+    jbuf_min_ms: Option<u32>,
+    jbuf_max_ms: Option<u32>,
+    jbuf_type: Option<JbufType>,
+}
+
+impl RoleEntry {
+    fn restart_spec(&self) -> std::result::Result<RestartSpec, String> {
+        let d = RestartSpec::default();
+        let spec = RestartSpec {
+            policy: self.restart,
+            max_restarts: self.max_restarts.unwrap_or(d.max_restarts),
+            backoff_ms: self.restart_backoff_ms.unwrap_or(d.backoff_ms),
+            backoff_max_ms: self.restart_backoff_max_ms.unwrap_or(d.backoff_max_ms),
+        };
+        if spec.backoff_ms == 0 || spec.backoff_ms > spec.backoff_max_ms {
+            return Err(format!(
+                "restart_backoff_ms ({}) must be within 1..=restart_backoff_max_ms ({})",
+                spec.backoff_ms, spec.backoff_max_ms
+            ));
+        }
+        Ok(spec)
+    }
+
+    // Keys of other kinds were rejected while reading, so only the ones for
+    // `kind` can be set here.
+    fn into_spec(self) -> std::result::Result<RoleSpec, String> {
+        let restart = self.restart_spec()?;
+        let required = |v: Option<String>, key: &str| v.ok_or(format!("missing field `{key}`"));
+        let params = match self.kind {
+            EntryKind::Source => RoleParams::Source(checked(SourcePlan {
+                sip_target: required(self.sip_target, "sip_target")?,
+                audio_file: self.audio_file,
+                playlist: self.playlist,
+                shuffle: self.shuffle,
+                seed: self.seed,
+                crossfade_ms: self.crossfade_ms,
+                resample_quality: self.resample_quality,
+                preroll_ms: self.preroll_ms,
+            })?),
+            EntryKind::Mixer => RoleParams::Mixer(checked(MixerPlan {
+                sip_bind: required(self.sip_bind, "sip_bind")?,
+                sip_target: required(self.sip_target, "sip_target")?,
+                dtmf_seq: self.dtmf_seq,
+                dtmf_period_ms: self.dtmf_period_ms,
+                mix_gain_in: self.mix_gain_in,
+                mix_gain_dtmf: self.mix_gain_dtmf,
+            })?),
+            EntryKind::Sink => RoleParams::Sink(checked(SinkPlan {
+                sip_bind: required(self.sip_bind, "sip_bind")?,
+                aplay_cmd: self.aplay_cmd,
+                buffer_min_ms: self.buffer_min_ms,
+                buffer_max_ms: self.buffer_max_ms,
+                buffer_mode: self.buffer_mode,
+                jbuf_min_ms: self.jbuf_min_ms,
+                jbuf_max_ms: self.jbuf_max_ms,
+                jbuf_type: self.jbuf_type,
+            })?),
+        };
+        Ok(RoleSpec {
+            name: self.name,
+            depends_on: self.depends_on,
+            restart,
+            stop_grace_ms: self.stop_grace_ms,
+            params,
+        })
+    }
+}
+
+fn checked<T: Validate>(plan: T) -> std::result::Result<T, String> {
+    plan.validate()?;
+    Ok(plan)
+}
+
+#[derive(Debug)]
+pub enum RoleParams {
+    Source(SourcePlan),
+    Mixer(MixerPlan),
+    Sink(SinkPlan),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SourcePlan {
+    pub sip_target: String,
+    pub audio_file: Option<PathBuf>,
+    /// M3U file, directory or glob; see `--playlist`.
+    pub playlist: Option<String>,
+    pub shuffle: Option<bool>,
+    pub seed: Option<u64>,
+    pub crossfade_ms: Option<u32>,
+    pub resample_quality: Option<ResampleQuality>,
+    pub preroll_ms: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MixerPlan {
+    pub sip_bind: String,
+    pub sip_target: String,
+    pub dtmf_seq: Option<String>,
+    pub dtmf_period_ms: Option<u32>,
+    pub mix_gain_in: Option<f32>,
+    pub mix_gain_dtmf: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SinkPlan {
+    pub sip_bind: String,
+    pub aplay_cmd: Option<String>,
+    pub buffer_min_ms: Option<u32>,
+    pub buffer_max_ms: Option<u32>,
+    pub buffer_mode: Option<BufferMode>,
+    pub jbuf_min_ms: Option<u32>,
+    pub jbuf_max_ms: Option<u32>,
+    pub jbuf_type: Option<JbufType>,
+}
+
+/// Range checks that only need the table itself. Errors raised here are
+/// reported by the TOML deserializer against the table's position.
+trait Validate {
+    fn validate(&self) -> std::result::Result<(), String>;
+}
+
+/// A plan table that passed `Validate` while being deserialized.
+#[derive(Debug)]
+struct Checked<T>(T);
+
+impl<'de, T: Deserialize<'de> + Validate> Deserialize<'de> for Checked<T> {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        let inner = T::deserialize(d)?;
+        inner.validate().map_err(serde::de::Error::custom)?;
+        Ok(Checked(inner))
+    }
+}
+
+impl<'de> Deserialize<'de> for RoleSpec {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> std::result::Result<Self, D::Error> {
+        struct Table;
+        impl<'de> Visitor<'de> for Table {
+            type Value = RoleEntry;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a role table")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(
+                self,
+                map: A,
+            ) -> std::result::Result<RoleEntry, A::Error> {
+                RoleEntry::deserialize(MapAccessDeserializer::new(KindKeys {
+                    map,
+                    state: KindState::default(),
+                }))
+            }
+        }
+        let entry = d.deserialize_map(Table)?;
+        let name = entry.name.clone();
+        entry
+            .into_spec()
+            .map_err(|e| D::Error::custom(format!("role {name:?}: {e}")))
+    }
+}
+
+/// Hands a `[[role]]` table to `RoleEntry` key by key, checking each key
+/// against `kind` while the TOML deserializer still knows where it is.
+struct KindKeys<A> {
+    map: A,
+    state: KindState,
+}
+
+#[derive(Default)]
+struct KindState {
+    kind: Option<EntryKind>,
+    /// Kind-specific keys read before `kind`; checked once it is known.
+    early: Vec<String>,
+    /// The next value is `kind`.
+    at_kind: bool,
+}
+
+impl KindState {
+    fn check_key(&mut self, key: &str) -> std::result::Result<(), String> {
+        self.at_kind = key == "kind";
+        if key == "name" || key == "kind" || ROLE_COMMON_KEYS.contains(&key) {
+            return Ok(());
+        }
+        match self.kind {
+            Some(kind) if kind.keys().contains(&key) => Ok(()),
+            Some(kind) => Err(format!(
+                "unknown field `{key}` for a {} role, expected one of {}",
+                kind.name(),
+                role_keys(Some(kind))
+            )),
+            None if EntryKind::ALL.iter().any(|k| k.keys().contains(&key)) => {
+                self.early.push(key.to_string());
+                Ok(())
+            }
+            None => Err(format!(
+                "unknown field `{key}`, expected one of {}",
+                role_keys(None)
+            )),
+        }
+    }
+}
+
+/// The keys a role of `kind` (or of any kind) accepts, for error messages.
+fn role_keys(kind: Option<EntryKind>) -> String {
+    let mut keys = vec!["name", "kind"];
+    keys.extend(ROLE_COMMON_KEYS);
+    for k in EntryKind::ALL {
+        if kind.is_none_or(|kind| kind == k) {
+            for key in k.keys() {
+                if !keys.contains(key) {
+                    keys.push(key);
+                }
+            }
+        }
+    }
+    keys.iter()
+        .map(|k| format!("`{k}`"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl<'de, A: MapAccess<'de>> MapAccess<'de> for KindKeys<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> std::result::Result<Option<K::Value>, A::Error> {
+        self.map.next_key_seed(KeySeed {
+            seed,
+            state: &mut self.state,
+        })
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> std::result::Result<V::Value, A::Error> {
+        if std::mem::take(&mut self.state.at_kind) {
+            self.map.next_value_seed(KindSeed {
+                seed,
+                state: &mut self.state,
+            })
+        } else {
+            self.map.next_value_seed(seed)
+        }
+    }
+}
+
+struct KeySeed<'a, K> {
+    seed: K,
+    state: &'a mut KindState,
+}
+
+impl<'de, K: DeserializeSeed<'de>> DeserializeSeed<'de> for KeySeed<'_, K> {
+    type Value = K::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> std::result::Result<K::Value, D::Error> {
+        let key = String::deserialize(d)?;
+        self.state.check_key(&key).map_err(D::Error::custom)?;
+        self.seed.deserialize(key.into_deserializer())
+    }
+}
+
+struct KindSeed<'a, V> {
+    seed: V,
+    state: &'a mut KindState,
+}
+
+impl<'de, V: DeserializeSeed<'de>> DeserializeSeed<'de> for KindSeed<'_, V> {
+    type Value = V::Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, d: D) -> std::result::Result<V::Value, D::Error> {
+        let kind = EntryKind::deserialize(d)?;
+        if let Some(key) = self
+            .state
+            .early
+            .iter()
+            .find(|key| !kind.keys().contains(&key.as_str()))
+        {
+            return Err(D::Error::custom(format!(
+                "a {} role does not take `{key}`, expected one of {}",
+                kind.name(),
+                role_keys(Some(kind))
+            )));
+        }
+        self.state.kind = Some(kind);
+        self.seed.deserialize(kind.name().into_deserializer())
+    }
+}
+
+impl RoleParams {
+    pub fn kind(&self) -> RoleKind {
+        match self {
+            RoleParams::Source(_) => RoleKind::Source,
+            RoleParams::Mixer(_) => RoleKind::Mixer,
+            RoleParams::Sink(_) => RoleKind::Sink,
+        }
+    }
+
+    pub fn sip_target(&self) -> Option<&str> {
+        match self {
+            RoleParams::Source(s) => Some(&s.sip_target),
+            RoleParams::Mixer(m) => Some(&m.sip_target),
+            RoleParams::Sink(_) => None,
+        }
+    }
+
+    pub fn sip_bind(&self) -> Option<&str> {
+        match self {
+            RoleParams::Source(_) => None,
+            RoleParams::Mixer(m) => Some(&m.sip_bind),
+            RoleParams::Sink(k) => Some(&k.sip_bind),
+        }
+    }
+}
+
+impl Validate for SourcePlan {
+    fn validate(&self) -> std::result::Result<(), String> {
+        if self.audio_file.is_some() && self.playlist.is_some() {
+            return Err("source: set audio_file or playlist, not both".into());
+        }
+        if self.shuffle == Some(true) && self.playlist.is_none() {
+            return Err("source.shuffle needs a playlist".into());
+        }
+        if self.seed.is_some() && self.shuffle != Some(true) {
+            return Err("source.seed needs shuffle = true".into());
+        }
+        Ok(())
+    }
+}
+
+impl Validate for MixerPlan {
+    fn validate(&self) -> std::result::Result<(), String> {
+        if let Some(seq) = self.dtmf_seq.as_deref() {
+            if seq.is_empty() || seq.len() > 127 {
+                return Err("mixer.dtmf_seq must be 1..=127 characters".into());
+            }
+            if let Some(c) = seq
+                .chars()
+                .find(|c| !matches!(c, '0'..='9' | 'A'..='D' | '*' | '#' | '+'))
+            {
+                return Err(format!(
+                    "mixer.dtmf_seq: invalid digit {c:?} (expected 0-9, A-D, *, #, +)"
+                ));
+            }
+        }
+        if self.dtmf_period_ms == Some(0) {
+            return Err("mixer.dtmf_period_ms must be > 0".into());
+        }
+        for (key, gain) in [
+            ("mix_gain_in", self.mix_gain_in),
+            ("mix_gain_dtmf", self.mix_gain_dtmf),
+        ] {
+            if let Some(g) = gain {
+                if !(0.0..=1.0).contains(&g) {
+                    return Err(format!("mixer.{key} must be within 0.0..=1.0 (got {g})"));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Validate for SinkPlan {
+    fn validate(&self) -> std::result::Result<(), String> {
+        check_range("sink.buffer", self.buffer_min_ms, self.buffer_max_ms)?;
+        check_range("sink.jbuf", self.jbuf_min_ms, self.jbuf_max_ms)
+    }
+}
+
+fn check_range(
+    prefix: &str,
+    min: Option<u32>,
+    max: Option<u32>,
+) -> std::result::Result<(), String> {
+    match (min, max) {
+        (None, None) => Ok(()),
+        (Some(min), Some(max)) => {
+            if max == 0 {
+                Err(format!("{prefix}_max_ms must be > 0"))
+            } else if min > max {
+                Err(format!(
+                    "{prefix}_min_ms ({min}) must be <= {prefix}_max_ms ({max})"
+                ))
+            } else {
+                Ok(())
+            }
+        }
+        _ => Err(format!(
+            "{prefix}_min_ms and {prefix}_max_ms must be set together"
+        )),
+    }
+}
+
+/// Read, compose (includes, `profiles`, `--set` overrides), parse and
+/// validate a plan file.
+pub fn load(path: &Path, profiles: &[String], sets: &[String]) -> Result<PlanTopology> {
+    let compose::Composed {
+        mut doc,
+        changed,
+        files,
+    } = compose::compose(path, profiles, sets)?;
+    let resolved =
+        vars::interpolate(&mut doc).with_context(|| format!("invalid plan {}", path.display()))?;
+    // Unchanged documents print back byte for byte, so error positions
+    // still match the file; composed ones are shown by --dry-run.
+    let merged = doc.to_string();
+    let what = if changed { " (as merged)" } else { "" };
+    let file: PlanFile = toml::from_str(&merged)
+        .map_err(|e| anyhow!("invalid plan {}{what}:\n{e}", path.display()))?;
+    let mut topo = match (file.topology, file.role.is_empty()) {
+        (Some(_), false) => bail!(
+            "invalid plan {}: use either [topology] or [[role]], not both",
+            path.display()
+        ),
+        (Some(legacy), true) => PlanTopology::from_legacy(legacy),
+        (None, _) => PlanTopology {
+            roles: file.role,
+            ..Default::default()
+        },
+    };
+    topo.timeline = file.timeline;
+    topo.duration = file.duration;
+    topo.expect = file.expect.map(|c| c.0);
+    topo.logs = file.logs.map(|c| c.0);
+    topo.resolved = resolved;
+    topo.merged = merged;
+    topo.sources = files;
+    topo.timeline.sort_by_key(|s| s.at);
+    substitute_host_ip(&mut topo);
+    topo.validate()
+        .with_context(|| format!("invalid plan {}", path.display()))?;
+    Ok(topo)
+}
+
+impl PlanTopology {
+    // Legacy plans name each instance after its kind and keep the historical
+    // sink -> mixer -> source startup order through explicit dependencies.
+    fn from_legacy(t: LegacyTopology) -> Self {
+        let mut roles = Vec::new();
+        let mut upstream: Vec<String> = Vec::new();
+        if let Some(k) = t.sink {
+            roles.push(RoleSpec {
+                name: "sink".into(),
+                depends_on: Vec::new(),
+                restart: RestartSpec::default(),
+                stop_grace_ms: None,
+                params: RoleParams::Sink(k.0),
+            });
+            upstream = vec!["sink".into()];
+        }
+        if let Some(m) = t.mixer {
+            roles.push(RoleSpec {
+                name: "mixer".into(),
+                depends_on: upstream,
+                restart: RestartSpec::default(),
+                stop_grace_ms: None,
+                params: RoleParams::Mixer(m.0),
+            });
+            upstream = vec!["mixer".into()];
+        }
+        if let Some(s) = t.source {
+            roles.push(RoleSpec {
+                name: "source".into(),
+                depends_on: upstream,
+                restart: RestartSpec::default(),
+                stop_grace_ms: None,
+                params: RoleParams::Source(s.0),
+            });
+        }
+        PlanTopology {
+            roles,
+            ..Default::default()
+        }
+    }
+
+    /// Plan index of the role called `name`.
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.roles.iter().position(|r| r.name == name)
+    }
+
+    // Cross-role checks and address checks run after placeholder
+    // substitution, so they cannot be part of `Validate`.
+    fn validate(&self) -> Result<()> {
+        if self.roles.is_empty() {
+            bail!("plan defines no roles");
+        }
+        let mut names = HashSet::new();
+        let mut binds = HashMap::new();
+        for r in &self.roles {
+            if r.name.is_empty()
+                || !r
+                    .name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+            {
+                bail!("role {:?}: name must be non-empty [A-Za-z0-9_-]", r.name);
+            }
+            if !names.insert(r.name.as_str()) {
+                bail!("role {:?}: duplicate name", r.name);
+            }
+            match &r.params {
+                RoleParams::Source(s) => self.check_target(&r.name, "sip_target", &s.sip_target)?,
+                RoleParams::Mixer(m) => {
+                    check_bind(&r.name, &m.sip_bind)?;
+                    self.check_target(&r.name, "sip_target", &m.sip_target)?;
+                }
+                RoleParams::Sink(k) => check_bind(&r.name, &k.sip_bind)?,
+            }
+            for dep in &r.depends_on {
+                if dep == &r.name {
+                    bail!("role {:?}: depends_on lists itself", r.name);
+                }
+                if self.index_of(dep).is_none() {
+                    bail!("role {:?}: depends_on names unknown role {dep:?}", r.name);
+                }
+            }
+            // `auto` binds get distinct ports at startup.
+            if let Some(bind) = r.params.sip_bind().filter(|b| !b.ends_with(":auto")) {
+                if let Some(other) = binds.insert(bind, r.name.as_str()) {
+                    bail!(
+                        "role {:?}: sip_bind {bind} already used by role {other:?}",
+                        r.name
+                    );
+                }
+            }
+        }
+        for step in &self.timeline {
+            let at = format!("timeline at {:?}", step.at);
+            match (step.action, step.role.as_deref()) {
+                (TimelineAction::End, Some(_)) => bail!("{at}: `end` takes no role"),
+                (TimelineAction::End, None) => {}
+                (a, None) => bail!("{at}: `{}` needs a role", a.name()),
+                (_, Some(role)) if self.index_of(role).is_none() => {
+                    bail!("{at}: unknown role {role:?}")
+                }
+                _ => {}
+            }
+            match (step.action, step.signal) {
+                (TimelineAction::Signal, None) => bail!("{at}: `signal` needs signal = \"...\""),
+                (TimelineAction::Signal, Some(_)) | (_, None) => {}
+                (a, Some(_)) => bail!(
+                    "{at}: signal is only valid with action = \"signal\", not `{}`",
+                    a.name()
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// A `sip:` URI, or `@name` for a role that binds a SIP address.
+    fn check_target(&self, role: &str, key: &str, v: &str) -> Result<()> {
+        if let Some(peer) = v.strip_prefix('@') {
+            let Some(j) = self.index_of(peer) else {
+                bail!("role {role:?}: {key}: {v:?} names unknown role {peer:?}");
+            };
+            if peer == role || self.roles[j].params.sip_bind().is_none() {
+                bail!("role {role:?}: {key}: {v:?} must name another sink or mixer");
+            }
+            return Ok(());
+        }
+        match v.strip_prefix("sip:") {
+            Some(rest) if !rest.is_empty() => Ok(()),
+            _ => bail!("role {role:?}: {key}: expected a sip: URI or @role, got {v:?}"),
+        }
+    }
+}
+
+/// `IP:PORT`, or `IP:auto` for a port reserved at startup.
+fn check_bind(role: &str, v: &str) -> Result<()> {
+    let ok = match v.strip_suffix(":auto") {
+        Some(ip) => auto_bind_ip(ip).is_some(),
+        None => v.parse::<SocketAddr>().is_ok(),
+    };
+    if !ok {
+        bail!("role {role:?}: sip_bind: expected IP:PORT or IP:auto, got {v:?}");
+    }
+    Ok(())
+}
+
+/// The IP of an `IP:auto` bind; IPv6 is bracketed as in `[::1]:auto`.
+pub fn auto_bind_ip(ip: &str) -> Option<IpAddr> {
+    match ip.strip_prefix('[').and_then(|i| i.strip_suffix(']')) {
+        Some(v6) => v6.parse::<Ipv6Addr>().ok().map(IpAddr::V6),
+        None => ip.parse::<Ipv4Addr>().ok().map(IpAddr::V4),
+    }
+}
+
+// Legacy placeholders YOUR_HOST_IP / YOUR_IP; same address as `${host}`.
+fn substitute_host_ip(topo: &mut PlanTopology) {
+    let Some(ip) = vars::host_ipv4().map(|ip| ip.to_string()) else {
+        return;
+    };
+    let replace = |v: &mut String| {
+        if v.contains("YOUR_HOST_IP") || v.contains("YOUR_IP") {
+            *v = v.replace("YOUR_HOST_IP", &ip).replace("YOUR_IP", &ip);
+        }
+    };
+    for r in topo.roles.iter_mut() {
+        match &mut r.params {
+            RoleParams::Source(s) => replace(&mut s.sip_target),
+            RoleParams::Mixer(m) => {
+                replace(&mut m.sip_bind);
+                replace(&mut m.sip_target);
+            }
+            RoleParams::Sink(k) => replace(&mut k.sip_bind),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_err(plan: &str) -> String {
+        match toml::from_str::<PlanFile>(plan) {
+            Ok(_) => panic!("plan parsed:\n{plan}"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    const SINK: &str =
+        "[[role]]\nname = \"sink\"\nkind = \"sink\"\nsip_bind = \"127.0.0.1:5062\"\n";
+
+    #[test]
+    fn role_entry_reads_kind_and_common_keys() {
+        let plan = format!(
+            "{SINK}\n[[role]]\nname = \"src\"\nsip_target = \"@sink\"\nkind = \"source\"\n\
+             restart = \"on-failure\"\nmax_restarts = 2\nshuffle = false\n"
+        );
+        let file: PlanFile = toml::from_str(&plan).unwrap();
+        let src = &file.role[1];
+        assert_eq!(src.name, "src");
+        assert_eq!(src.restart.policy, RestartPolicy::OnFailure);
+        assert_eq!(src.restart.max_restarts, 2);
+        match &src.params {
+            RoleParams::Source(s) => assert_eq!(s.sip_target, "@sink"),
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn unknown_role_key_points_at_the_key() {
+        let e = parse_err(&format!("{SINK}bogus = 1\n"));
+        assert!(e.contains("line 5, column 1"), "{e}");
+        assert!(e.contains("unknown field `bogus`"), "{e}");
+    }
+
+    #[test]
+    fn key_of_another_kind_points_at_the_key_and_lists_common_keys() {
+        let e = parse_err(&format!("{SINK}jbuf_min_ms = 20\naudio_file = \"a.wav\"\n"));
+        assert!(e.contains("line 6, column 1"), "{e}");
+        assert!(
+            e.contains("unknown field `audio_file` for a sink role"),
+            "{e}"
+        );
+        assert!(
+            e.contains("`depends_on`") && e.contains("`stop_grace_ms`"),
+            "{e}"
+        );
+        assert!(
+            e.contains("`aplay_cmd`") && !e.contains("`dtmf_seq`"),
+            "{e}"
+        );
+    }
+
+    #[test]
+    fn key_before_kind_is_checked_at_kind() {
+        let e = parse_err("[[role]]\nname = \"m\"\naplay_cmd = \"aplay\"\nkind = \"mixer\"\n");
+        assert!(e.contains("line 4, column 8"), "{e}");
+        assert!(e.contains("a mixer role does not take `aplay_cmd`"), "{e}");
+    }
+
+    #[test]
+    fn out_of_range_role_value_points_at_the_value() {
+        let e = parse_err(&format!("{SINK}max_restarts = -1\n"));
+        assert!(e.contains("line 5, column 16"), "{e}");
+        let e = parse_err(&format!("{SINK}buffer_mode = \"huge\"\n"));
+        assert!(e.contains("line 5, column 15"), "{e}");
+    }
+
+    #[test]
+    fn table_checks_name_the_role() {
+        let e = parse_err("[[role]]\nname = \"s\"\nkind = \"source\"\n");
+        assert!(e.contains("role \"s\": missing field `sip_target`"), "{e}");
+        let e = parse_err(&format!("{SINK}buffer_min_ms = 40\n"));
+        assert!(e.contains("role \"sink\": sink.buffer_min_ms and"), "{e}");
+    }
+
+    #[test]
+    fn durations_take_ms_s_and_m() {
+        for (text, ms) in [
+            ("500ms", 500),
+            ("15s", 15_000),
+            (" 1.5s ", 1_500),
+            ("2m", 120_000),
+            ("0s", 0),
+            (".25s", 250),
+        ] {
+            assert_eq!(
+                parse_duration(text),
+                Ok(Duration::from_millis(ms)),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn bad_durations_are_errors_not_panics() {
+        for text in ["15", "s", "-5s", "1.2.3s", "5h", "inf s", "NaN", "1e3s", ""] {
+            assert!(parse_duration(text).is_err(), "{text}");
+        }
+        let e = parse_duration("99999999999999999999m").unwrap_err();
+        assert!(e.contains("too long"), "{e}");
+        let huge = format!("{}s", "9".repeat(400));
+        let e = parse_duration(&huge).unwrap_err();
+        assert!(e.contains("out of range"), "{e}");
+    }
+
+    #[test]
+    fn overflowing_plan_duration_is_a_parse_error() {
+        let e = parse_err(&format!("duration = \"99999999999999999999m\"\n{SINK}"));
+        assert!(
+            e.contains("line 1, column 12") && e.contains("too long"),
+            "{e}"
+        );
+    }
+
+    fn signal(value: &str) -> std::result::Result<Option<i32>, String> {
+        toml::from_str::<TimelineStep>(&format!(
+            "at = \"1s\"\naction = \"signal\"\nsignal = {value}"
+        ))
+        .map(|step| step.signal)
+        .map_err(|e| e.message().to_string())
+    }
+
+    #[test]
+    fn signals_by_name_or_number() {
+        assert_eq!(signal("\"HUP\""), Ok(Some(libc::SIGHUP)));
+        assert_eq!(signal("\"sigusr1\""), Ok(Some(libc::SIGUSR1)));
+        assert_eq!(signal("\"SIGTERM\""), Ok(Some(libc::SIGTERM)));
+        assert_eq!(signal("9"), Ok(Some(9)));
+        let max = libc::SIGRTMAX();
+        assert_eq!(signal(&max.to_string()), Ok(Some(max)));
+        for n in [0, -2, max + 1, 999] {
+            let want = format!("invalid signal {n} (expected 1..={max})");
+            assert_eq!(signal(&n.to_string()), Err(want));
+        }
+        assert_eq!(signal("\"WINCH\""), Err("unknown signal \"WINCH\"".into()));
+    }
+}
@@ -0,0 +1,226 @@
+use super::{
+    plan::{self, PlanTopology, RoleParams},
+    vars::Substitution,
+};
+use anyhow::{Context, Result, bail};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, UdpSocket};
+
+/// Holds an `auto` port (SIP listens on UDP and TCP) until the role that
+/// binds it is spawned.
+#[derive(Debug)]
+pub struct Reservation {
+    _udp: UdpSocket,
+    _tcp: TcpListener,
+}
+
+/// Replace `IP:auto` binds with reserved ports, then `@role` targets with
+/// that role's `sip:IP:PORT`. Both are recorded in `topo.resolved`. Returns
+/// the reservations by role index.
+///
+/// `keep` holds the substitutions of a running plan (on reload): a role
+/// whose bind is still the same `IP:auto` keeps the port it already has.
+pub fn resolve(topo: &mut PlanTopology, keep: &[Substitution]) -> Result<Vec<Option<Reservation>>> {
+    let mut reserved = Vec::with_capacity(topo.roles.len());
+    for r in topo.roles.iter_mut() {
+        let bind = match &mut r.params {
+            RoleParams::Mixer(m) => &mut m.sip_bind,
+            RoleParams::Sink(k) => &mut k.sip_bind,
+            RoleParams::Source(_) => {
+                reserved.push(None);
+                continue;
+            }
+        };
+        let Some(ip) = bind.strip_suffix(":auto") else {
+            reserved.push(None);
+            continue;
+        };
+        let ip = plan::auto_bind_ip(ip)
+            .with_context(|| format!("role {:?}: sip_bind {bind:?}", r.name))?;
+        let key = format!("{}.sip_bind", r.name);
+        let (addr, res) = match keep.iter().find(|k| k.key == key && k.from == *bind) {
+            Some(k) => (k.to.clone(), None),
+            None => {
+                let (addr, res) =
+                    reserve(ip).with_context(|| format!("role {:?}: sip_bind", r.name))?;
+                (addr.to_string(), Some(res))
+            }
+        };
+        topo.resolved.substitutions.push(Substitution {
+            key,
+            from: std::mem::replace(bind, addr.clone()),
+            to: addr,
+        });
+        reserved.push(res);
+    }
+    for i in 0..topo.roles.len() {
+        let Some(peer) = topo.roles[i]
+            .params
+            .sip_target()
+            .and_then(|t| t.strip_prefix('@'))
+        else {
+            continue;
+        };
+        let uri = topo
+            .index_of(peer)
+            .and_then(|j| topo.roles[j].params.sip_bind())
+            .and_then(|b| b.parse::<SocketAddr>().ok())
+            .map(|a| format!("sip:{}", dialable(a)))
+            .with_context(|| format!("role {:?}: cannot resolve @{peer}", topo.roles[i].name))?;
+        let r = &mut topo.roles[i];
+        let target = match &mut r.params {
+            RoleParams::Source(s) => &mut s.sip_target,
+            RoleParams::Mixer(m) => &mut m.sip_target,
+            RoleParams::Sink(_) => continue,
+        };
+        topo.resolved.substitutions.push(Substitution {
+            key: format!("{}.sip_target", r.name),
+            from: std::mem::replace(target, uri.clone()),
+            to: uri,
+        });
+    }
+    Ok(reserved)
+}
+
+/// A free port on `ip` for both UDP and TCP.
+fn reserve(ip: IpAddr) -> Result<(SocketAddr, Reservation)> {
+    for _ in 0..32 {
+        let tcp = TcpListener::bind((ip, 0))?;
+        let addr = tcp.local_addr()?;
+        // The TCP port may be taken on UDP; try another.
+        if let Ok(udp) = UdpSocket::bind(addr) {
+            return Ok((
+                addr,
+                Reservation {
+                    _udp: udp,
+                    _tcp: tcp,
+                },
+            ));
+        }
+    }
+    bail!("no free UDP+TCP port on {ip}")
+}
+
+/// Address a peer dials for a bind: wildcard binds are reached on loopback.
+fn dialable(a: SocketAddr) -> SocketAddr {
+    match a.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => (Ipv4Addr::LOCALHOST, a.port()).into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => {
+            (IpAddr::from([0u16, 0, 0, 0, 0, 0, 0, 1]), a.port()).into()
+        }
+        _ => a,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use plan::RoleSpec;
+    use serde::Deserialize;
+
+    fn topo(plan: &str) -> PlanTopology {
+        #[derive(Deserialize)]
+        struct Roles {
+            role: Vec<RoleSpec>,
+        }
+        let roles: Roles = toml::from_str(plan).unwrap();
+        PlanTopology {
+            roles: roles.role,
+            ..Default::default()
+        }
+    }
+
+    fn target(topo: &PlanTopology, name: &str) -> String {
+        let i = topo.index_of(name).unwrap();
+        topo.roles[i].params.sip_target().unwrap().into()
+    }
+
+    #[test]
+    fn auto_binds_get_a_reserved_port_and_targets_follow() {
+        let mut topo = topo(
+            r#"
+            [[role]]
+            name = "sink"
+            kind = "sink"
+            sip_bind = "127.0.0.1:auto"
+            [[role]]
+            name = "mix"
+            kind = "mixer"
+            sip_bind = "0.0.0.0:auto"
+            sip_target = "@sink"
+            [[role]]
+            name = "src"
+            kind = "source"
+            sip_target = "@mix"
+            "#,
+        );
+        let reserved = resolve(&mut topo, &[]).unwrap();
+        assert!(reserved[0].is_some() && reserved[1].is_some() && reserved[2].is_none());
+
+        let sink: SocketAddr = topo.roles[0].params.sip_bind().unwrap().parse().unwrap();
+        assert_eq!(sink.ip(), IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_ne!(sink.port(), 0);
+        assert_eq!(target(&topo, "mix"), format!("sip:{sink}"));
+
+        // A wildcard bind is dialed on loopback.
+        let mix: SocketAddr = topo.roles[1].params.sip_bind().unwrap().parse().unwrap();
+        assert!(mix.ip().is_unspecified());
+        assert_eq!(
+            target(&topo, "src"),
+            format!("sip:127.0.0.1:{}", mix.port())
+        );
+
+        let keys: Vec<&str> = topo
+            .resolved
+            .substitutions
+            .iter()
+            .map(|s| s.key.as_str())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "sink.sip_bind",
+                "mix.sip_bind",
+                "mix.sip_target",
+                "src.sip_target"
+            ]
+        );
+    }
+
+    #[test]
+    fn fixed_binds_are_kept_and_reload_keeps_auto_ports() {
+        let plan = r#"
+            [[role]]
+            name = "sink"
+            kind = "sink"
+            sip_bind = "127.0.0.1:5062"
+            [[role]]
+            name = "sink2"
+            kind = "sink"
+            sip_bind = "127.0.0.1:auto"
+            "#;
+        let keep = [Substitution {
+            key: "sink2.sip_bind".into(),
+            from: "127.0.0.1:auto".into(),
+            to: "127.0.0.1:40123".into(),
+        }];
+        let mut topo = topo(plan);
+        let reserved = resolve(&mut topo, &keep).unwrap();
+        assert!(reserved.iter().all(Option::is_none));
+        assert_eq!(topo.roles[0].params.sip_bind(), Some("127.0.0.1:5062"));
+        assert_eq!(topo.roles[1].params.sip_bind(), Some("127.0.0.1:40123"));
+    }
+
+    #[test]
+    fn unknown_role_target_is_an_error() {
+        let mut topo = topo(
+            r#"
+            [[role]]
+            name = "src"
+            kind = "source"
+            sip_target = "@nobody"
+            "#,
+        );
+        let e = format!("{:#}", resolve(&mut topo, &[]).unwrap_err());
+        assert!(e.contains(r#"role "src": cannot resolve @nobody"#), "{e}");
+    }
+}
@@ -0,0 +1,289 @@
+use super::{plan::PlanTopology, role_args, role_str};
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant, SystemTime},
+};
+
+/// How often `--watch` looks at the plan files. A change is applied once
+/// the files have stayed the same for one more poll, so a half-written
+/// plan is not loaded.
+const POLL_EVERY: Duration = Duration::from_millis(500);
+
+static HUP: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_hup(_: libc::c_int) {
+    HUP.store(true, Ordering::Relaxed);
+}
+
+/// Make SIGHUP request a reload instead of ending the run.
+pub fn catch_sighup() {
+    unsafe {
+        let mut sa: libc::sigaction = std::mem::zeroed();
+        sa.sa_sigaction = on_hup as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        sa.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut sa.sa_mask);
+        libc::sigaction(libc::SIGHUP, &sa, std::ptr::null_mut());
+    }
+}
+
+/// Whether SIGHUP arrived since the last call.
+pub fn sighup() -> bool {
+    HUP.swap(false, Ordering::Relaxed)
+}
+
+/// Polls the modification times of the plan file and its includes.
+pub struct Watch {
+    files: Vec<PathBuf>,
+    seen: Vec<Option<SystemTime>>,
+    /// Stamps that differ from `seen`, waiting to settle.
+    pending: Option<Vec<Option<SystemTime>>>,
+    last_poll: Instant,
+}
+
+impl Watch {
+    pub fn new(files: &[PathBuf]) -> Self {
+        Self {
+            seen: stamps(files),
+            files: files.to_vec(),
+            pending: None,
+            last_poll: Instant::now(),
+        }
+    }
+
+    /// True once per settled change.
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_EVERY {
+            return false;
+        }
+        self.last_poll = Instant::now();
+        let now = stamps(&self.files);
+        if now == self.seen {
+            self.pending = None;
+            return false;
+        }
+        if self.pending.as_ref() != Some(&now) {
+            self.pending = Some(now);
+            return false;
+        }
+        self.seen = now;
+        self.pending = None;
+        true
+    }
+
+    /// Follow a new include list after a reload.
+    pub fn set_files(&mut self, files: &[PathBuf]) {
+        if files != self.files {
+            *self = Self::new(files);
+        }
+    }
+}
+
+fn stamps(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|f| std::fs::metadata(f).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Roles of a reloaded plan compared with the running one, by name.
+#[derive(Debug, Default)]
+pub struct Diff {
+    /// Roles whose command line changed, with what changed.
+    pub changed: Vec<(String, String)>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub unchanged: Vec<String>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// A role needs a restart only if the arguments it would be spawned with
+/// differ; restart policy, grace and `depends_on` apply without one.
+pub fn diff(old: &PlanTopology, new: &PlanTopology) -> Diff {
+    let mut d = Diff::default();
+    for r in &new.roles {
+        let Some(i) = old.index_of(&r.name) else {
+            d.added.push(r.name.clone());
+            continue;
+        };
+        let (ok, nk) = (old.roles[i].params.kind(), r.params.kind());
+        let (oa, na) = (role_args(&old.roles[i].params), role_args(&r.params));
+        if ok != nk {
+            let what = format!("kind {} -> {}", role_str(ok), role_str(nk));
+            d.changed.push((r.name.clone(), what));
+        } else if oa != na {
+            d.changed.push((r.name.clone(), describe(&oa, &na)));
+        } else {
+            d.unchanged.push(r.name.clone());
+        }
+    }
+    for r in &old.roles {
+        if new.index_of(&r.name).is_none() {
+            d.removed.push(r.name.clone());
+        }
+    }
+    d
+}
+
+/// `--flag old -> new` for each option that differs.
+fn describe(old: &[String], new: &[String]) -> String {
+    let (old, new) = (options(old), options(new));
+    let mut parts = Vec::new();
+    for (flag, v) in &new {
+        match old.iter().find(|(f, _)| f == flag) {
+            Some((_, o)) if o == v => {}
+            Some((_, o)) => parts.push(format!("{flag} {o:?} -> {v:?}")),
+            None => parts.push(format!("{flag} {v:?} added")),
+        }
+    }
+    for (flag, _) in &old {
+        if !new.iter().any(|(f, _)| f == flag) {
+            parts.push(format!("{flag} removed"));
+        }
+    }
+    parts.join(", ")
+}
+
+/// Pair each `--flag` with the value after it (empty for bare flags).
+fn options(args: &[String]) -> Vec<(String, String)> {
+    let mut out = Vec::new();
+    let mut it = args.iter().peekable();
+    while let Some(flag) = it.next() {
+        let value = it
+            .next_if(|v| !v.starts_with("--"))
+            .cloned()
+            .unwrap_or_default();
+        out.push((flag.clone(), value));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orchestrator::plan::RoleSpec;
+
+    fn topo(plan: &str) -> PlanTopology {
+        #[derive(serde::Deserialize)]
+        struct Roles {
+            role: Vec<RoleSpec>,
+        }
+        let roles: Roles = toml::from_str(plan).unwrap();
+        PlanTopology {
+            roles: roles.role,
+            ..Default::default()
+        }
+    }
+
+    const BASE: &str = r#"
+        [[role]]
+        name = "snk"
+        kind = "sink"
+        sip_bind = "127.0.0.1:5062"
+
+        [[role]]
+        name = "src"
+        kind = "source"
+        sip_target = "sip:b2b@127.0.0.1:5062"
+    "#;
+
+    #[test]
+    fn same_plan_changes_nothing() {
+        let d = diff(&topo(BASE), &topo(BASE));
+        assert!(d.is_empty());
+        assert_eq!(d.unchanged, ["snk", "src"]);
+    }
+
+    #[test]
+    fn supervision_settings_apply_without_restart() {
+        let new = BASE.replace(
+            r#"kind = "source""#,
+            r#"kind = "source"
+            depends_on = ["snk"]
+            restart = "always"
+            max_restarts = 9
+            stop_grace_ms = 100"#,
+        );
+        let d = diff(&topo(BASE), &topo(&new));
+        assert!(d.is_empty(), "{d:?}");
+        assert_eq!(d.unchanged, ["snk", "src"]);
+    }
+
+    #[test]
+    fn changed_arguments_are_described() {
+        let new = BASE
+            .replace(
+                r#"sip_bind = "127.0.0.1:5062""#,
+                r#"sip_bind = "127.0.0.1:5064"
+                aplay_cmd = "cat""#,
+            )
+            .replace(
+                r#"sip_target = "sip:b2b@127.0.0.1:5062""#,
+                r#"sip_target = "sip:b2b@127.0.0.1:5064""#,
+            );
+        let d = diff(&topo(BASE), &topo(&new));
+        assert_eq!(
+            d.changed,
+            [
+                (
+                    "snk".to_string(),
+                    r#"--sip-bind "127.0.0.1:5062" -> "127.0.0.1:5064", --aplay-cmd "cat" added"#
+                        .to_string()
+                ),
+                (
+                    "src".to_string(),
+                    r#"--target "sip:b2b@127.0.0.1:5062" -> "sip:b2b@127.0.0.1:5064""#.to_string()
+                ),
+            ]
+        );
+        let back = diff(&topo(&new), &topo(BASE));
+        assert!(
+            back.changed[0].1.ends_with("--aplay-cmd removed"),
+            "{back:?}"
+        );
+    }
+
+    #[test]
+    fn kind_change_added_and_removed_roles() {
+        let new = r#"
+            [[role]]
+            name = "snk"
+            kind = "mixer"
+            sip_bind = "127.0.0.1:5062"
+            sip_target = "sip:b2b@127.0.0.1:5064"
+
+            [[role]]
+            name = "rec"
+            kind = "sink"
+            sip_bind = "127.0.0.1:5064"
+        "#;
+        let d = diff(&topo(BASE), &topo(new));
+        assert_eq!(
+            d.changed,
+            [("snk".to_string(), "kind sink -> mixer".to_string())]
+        );
+        assert_eq!(d.added, ["rec"]);
+        assert_eq!(d.removed, ["src"]);
+        assert!(d.unchanged.is_empty());
+        assert!(!d.is_empty());
+    }
+
+    #[test]
+    fn options_pair_flags_with_values() {
+        let args: Vec<String> = ["--a", "1", "--b", "--c", "-x"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let pairs = options(&args);
+        let pairs: Vec<(&str, &str)> = pairs
+            .iter()
+            .map(|(f, v)| (f.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(pairs, [("--a", "1"), ("--b", ""), ("--c", "-x")]);
+    }
+}
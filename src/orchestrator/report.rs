@@ -0,0 +1,306 @@
+use super::{expect::Outcome, logfiles::RotatingFile};
+use crate::{error, logging, metrics};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::{fmt::Write as _, path::Path, time::Instant};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+
+/// What the reader threads pick out of one child's output.
+#[derive(Debug, Default)]
+pub struct ChildOutput {
+    /// Last `error:`/`panic:` line; children log their terminal error there.
+    pub last_error: Option<String>,
+    /// Last line of any kind, for liveness.
+    pub last_line: Option<Instant>,
+    /// First call-established event of the current instance.
+    pub established: Option<Instant>,
+    /// When the last metrics line arrived.
+    pub metrics_at: Option<Instant>,
+    /// Rollup of the instance's metrics lines.
+    pub metrics: metrics::Snapshot,
+    /// Raw copy of the role's output under `--log-dir`.
+    pub raw_log: Option<RotatingFile>,
+}
+
+impl ChildOutput {
+    /// `raw` is the line as the child printed it, `line` its text (the
+    /// `msg` of a JSON record).
+    pub fn observe(&mut self, raw: &str, line: &str) {
+        if let Some(f) = self.raw_log.as_mut() {
+            f.write_line(raw);
+        }
+        self.last_line = Some(Instant::now());
+        if line.starts_with("error:") || line.starts_with("panic:") {
+            self.last_error = Some(line.to_string());
+        }
+        if self.established.is_none() && is_call_established(line) {
+            self.established = Some(Instant::now());
+        }
+        if let Some(m) = metrics::parse_line(line) {
+            self.metrics.apply(&m, logging::ts());
+            self.metrics_at = Some(Instant::now());
+        }
+    }
+}
+
+// Source logs "SIP: Call established", the mixer "<leg>: established ..."
+// and the sink the raw baresip event.
+fn is_call_established(line: &str) -> bool {
+    line.contains("SIP: Call established")
+        || line.contains(": established ")
+        || line.contains("CALL_ESTABLISHED")
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunReport {
+    pub plan: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub duration_ms: u64,
+    pub exit_code: u8,
+    /// Error that ended the run, if it failed.
+    pub error: Option<String>,
+    pub roles: Vec<RoleReport>,
+    /// `[expect]` results, if the plan has any.
+    pub expectations: Vec<Outcome>,
+    /// Merged, timestamped log of the whole run (`--log-dir`).
+    pub merged_log: Option<String>,
+}
+
+impl RunReport {
+    pub fn set_result(&mut self, res: &Result<()>) {
+        self.exit_code = res.as_ref().err().map_or(0, error::exit_code);
+        self.error = res.as_ref().err().map(|e| format!("{e:#}"));
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RoleReport {
+    pub name: String,
+    pub kind: String,
+    /// Latest instance; earlier ones are counted in the `*restarts` fields.
+    pub pid: Option<u32>,
+    pub spawned_at: Option<String>,
+    pub time_to_ready_ms: Option<u64>,
+    pub call_established_ms: Option<u64>,
+    /// Call time covered by the last metrics line.
+    pub call_ms: Option<u64>,
+    pub exit_code: Option<i32>,
+    pub exit_signal: Option<i32>,
+    /// Restarts by the role's restart policy (crashes, missed READY).
+    pub restarts: u32,
+    pub stalls: u32,
+    /// Restarts by `--stall-action restart`.
+    pub stall_restarts: u32,
+    /// Restarts by `[[timeline]]` steps or the dashboard.
+    pub scripted_restarts: u32,
+    /// Raw output of the role (`--log-dir`); rotated files get `.1`, `.2`...
+    pub log_file: Option<String>,
+    pub last_error: Option<String>,
+    pub last_metrics: Option<metrics::Snapshot>,
+}
+
+pub fn rfc3339(t: OffsetDateTime) -> String {
+    t.format(&Rfc3339).unwrap_or_default()
+}
+
+pub fn write_json(path: &Path, report: &RunReport) -> Result<()> {
+    std::fs::write(path, json(report)?)
+        .with_context(|| format!("writing report {}", path.display()))
+}
+
+pub fn write_markdown(path: &Path, report: &RunReport) -> Result<()> {
+    std::fs::write(path, markdown(report))
+        .with_context(|| format!("writing report {}", path.display()))
+}
+
+fn json(report: &RunReport) -> Result<String> {
+    Ok(serde_json::to_string_pretty(report)? + "\n")
+}
+
+/// Text safe inside one Markdown table cell (or list item): `|` escaped,
+/// line breaks as `<br>`.
+fn cell(s: &str) -> String {
+    s.replace('|', "\\|")
+        .replace("\r\n", "<br>")
+        .replace(['\n', '\r'], "<br>")
+}
+
+fn markdown(report: &RunReport) -> String {
+    let mut md = String::new();
+    let _ = writeln!(md, "# b2b run report\n");
+    let _ = writeln!(md, "- plan: `{}`", report.plan);
+    let _ = writeln!(
+        md,
+        "- started: {} ({} ms)",
+        report.started_at, report.duration_ms
+    );
+    let _ = writeln!(md, "- exit code: {}", report.exit_code);
+    if let Some(e) = &report.error {
+        let _ = writeln!(md, "- error: {}", cell(e));
+    }
+    if let Some(p) = &report.merged_log {
+        let _ = writeln!(md, "- merged log: `{p}`");
+    }
+    let _ = writeln!(
+        md,
+        "\n| role | kind | pid | ready ms | established ms | exit | restarts | stall restarts | scripted restarts |"
+    );
+    let _ = writeln!(md, "|---|---|---|---|---|---|---|---|---|");
+    let opt = |v: Option<u64>| v.map_or("-".to_string(), |v| v.to_string());
+    for r in &report.roles {
+        let exit = match (r.exit_code, r.exit_signal) {
+            (Some(c), _) => format!("code {c}"),
+            (None, Some(s)) => format!("signal {s}"),
+            (None, None) => "-".into(),
+        };
+        let _ = writeln!(
+            md,
+            "| {} | {} | {} | {} | {} | {exit} | {} | {} | {} |",
+            cell(&r.name),
+            cell(&r.kind),
+            opt(r.pid.map(u64::from)),
+            opt(r.time_to_ready_ms),
+            opt(r.call_established_ms),
+            r.restarts,
+            r.stall_restarts,
+            r.scripted_restarts
+        );
+    }
+    if !report.expectations.is_empty() {
+        let _ = writeln!(md, "\n| expectation | result | detail |");
+        let _ = writeln!(md, "|---|---|---|");
+        for o in &report.expectations {
+            let res = if o.pass { "pass" } else { "FAIL" };
+            let _ = writeln!(md, "| {} | {res} | {} |", cell(&o.name), cell(&o.detail));
+        }
+    }
+    for r in &report.roles {
+        if r.last_metrics.is_none() && r.last_error.is_none() && r.log_file.is_none() {
+            continue;
+        }
+        let _ = writeln!(md, "\n## {}\n", r.name);
+        if let Some(p) = &r.log_file {
+            let _ = writeln!(md, "Log: `{p}`\n");
+        }
+        if let Some(e) = &r.last_error {
+            let _ = writeln!(md, "Last error: `{e}`\n");
+        }
+        if let Some(m) = &r.last_metrics {
+            let _ = writeln!(md, "Last metrics ({}): {}", m.ts, m.brief());
+        }
+    }
+    md
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::error::B2bError;
+
+    pub(crate) fn role(name: &str, kind: &str) -> RoleReport {
+        RoleReport {
+            name: name.into(),
+            kind: kind.into(),
+            pid: None,
+            spawned_at: None,
+            time_to_ready_ms: None,
+            call_established_ms: None,
+            call_ms: None,
+            exit_code: None,
+            exit_signal: None,
+            restarts: 0,
+            stalls: 0,
+            stall_restarts: 0,
+            scripted_restarts: 0,
+            log_file: None,
+            last_error: None,
+            last_metrics: None,
+        }
+    }
+
+    fn report() -> RunReport {
+        let sink = RoleReport {
+            pid: Some(4242),
+            time_to_ready_ms: Some(120),
+            exit_code: Some(0),
+            restarts: 2,
+            scripted_restarts: 1,
+            last_error: Some("error: aplay | gone".into()),
+            last_metrics: Some(metrics::Snapshot {
+                packets_rx: 250,
+                updates: 1,
+                ..metrics::Snapshot::new("sink", 4242)
+            }),
+            ..role("sink", "sink")
+        };
+        let source = RoleReport {
+            exit_signal: Some(9),
+            ..role("src|a", "source")
+        };
+        let mut rep = RunReport {
+            plan: "plans/x.plan.toml".into(),
+            started_at: "2026-01-02T03:04:05Z".into(),
+            ended_at: "2026-01-02T03:04:15Z".into(),
+            duration_ms: 10_000,
+            exit_code: 0,
+            error: None,
+            roles: vec![sink, source],
+            expectations: vec![Outcome {
+                name: "max_restarts = 1".into(),
+                pass: false,
+                detail: "2 restart(s)\nsink | 2".into(),
+            }],
+            merged_log: None,
+        };
+        let res: Result<()> = Err(B2bError::Expect("1 of 1 expectation(s) failed".into()).into());
+        rep.set_result(&res);
+        rep
+    }
+
+    #[test]
+    fn json_report_round_trips() {
+        let v: serde_json::Value = serde_json::from_str(&json(&report()).unwrap()).unwrap();
+        assert_eq!(v["exit_code"], 1);
+        assert_eq!(v["error"], "1 of 1 expectation(s) failed");
+        assert_eq!(v["roles"][0]["name"], "sink");
+        assert_eq!(v["roles"][0]["pid"], 4242);
+        assert_eq!(v["roles"][0]["restarts"], 2);
+        assert_eq!(v["roles"][0]["scripted_restarts"], 1);
+        assert_eq!(v["roles"][0]["last_metrics"]["packets_rx"], 250);
+        assert_eq!(v["roles"][1]["pid"], serde_json::Value::Null);
+        assert_eq!(v["roles"][1]["exit_signal"], 9);
+        assert_eq!(v["expectations"][0]["pass"], false);
+        assert_eq!(v["expectations"][0]["detail"], "2 restart(s)\nsink | 2");
+    }
+
+    #[test]
+    fn markdown_cells_cannot_break_tables() {
+        assert_eq!(cell("a|b"), r"a\|b");
+        assert_eq!(cell("one\ntwo\r\nthree"), "one<br>two<br>three");
+        let md = markdown(&report());
+        assert!(md.contains("- exit code: 1\n"), "{md}");
+        assert!(
+            md.contains("| sink | sink | 4242 | 120 | - | code 0 | 2 | 0 | 1 |\n"),
+            "{md}"
+        );
+        assert!(
+            md.contains(r"| src\|a | source | - | - | - | signal 9 | 0 | 0 | 0 |"),
+            "{md}"
+        );
+        assert!(
+            md.contains(r"| max_restarts = 1 | FAIL | 2 restart(s)<br>sink \| 2 |"),
+            "{md}"
+        );
+        // Every table row has its table's column count.
+        for line in md.lines().filter(|l| l.starts_with('|')) {
+            let cols = line.replace(r"\|", "").matches('|').count() - 1;
+            assert!(cols == 9 || cols == 3, "{line}");
+        }
+        assert!(
+            md.contains("## sink\n\nLast error: `error: aplay | gone`\n"),
+            "{md}"
+        );
+        assert!(!md.contains("## src|a"), "{md}");
+    }
+}
@@ -0,0 +1,1513 @@
+use super::{
+    graph, kill_child_group,
+    logfiles::RotatingFile,
+    pidfile::PidFile,
+    pipe_child_output,
+    plan::{PlanTopology, RestartPolicy, RestartSpec, TimelineAction},
+    ports::Reservation,
+    reload, reload_plan,
+    report::{self, ChildOutput, RoleReport, RunReport},
+    role_args, role_str, spawn_role,
+    tui::{self, RoleView, Tui},
+};
+use crate::{
+    cli::{Cli, StallAction},
+    error::{self, B2bError},
+    logging,
+    metrics::Snapshot,
+};
+use anyhow::Result;
+use std::{
+    path::Path,
+    process::{Child, ExitStatus},
+    sync::{Arc, Mutex, mpsc},
+    thread,
+    time::{Duration, Instant},
+};
+use time::OffsetDateTime;
+
+/// Children print metrics every 5s; roll them up at the same cadence.
+const ROLLUP_EVERY: Duration = Duration::from_secs(5);
+
+/// Lifecycle of one plan instance as seen by the supervisor.
+enum RoleState {
+    /// Not running; spawn once `not_before` has passed and every dependency
+    /// is READY.
+    Waiting {
+        not_before: Instant,
+    },
+    /// Spawned, READY not seen yet. The deadline only applies to roles that
+    /// other roles depend on.
+    Starting {
+        deadline: Instant,
+    },
+    Ready,
+    /// SIGTERM sent on purpose (dependency restart, stall, timeline);
+    /// SIGKILL at `kill_at`. The exit is not a failure: the role is
+    /// respawned or left down.
+    Stopping {
+        kill_at: Instant,
+        respawn: bool,
+    },
+    /// Stopped by the timeline; not respawned.
+    Stopped,
+}
+
+/// What a role's restart policy asks for when it exits.
+#[derive(Debug, PartialEq)]
+enum Restart {
+    No,
+    /// Wanted, but `max_restarts` have already been used.
+    Exhausted,
+    After(Duration),
+}
+
+/// Decide on a restart for a role that has already been restarted
+/// `restarts` times; `failed` is a non-zero exit or a missed READY.
+fn next_restart(spec: &RestartSpec, restarts: u32, failed: bool) -> Restart {
+    let wanted = match spec.policy {
+        RestartPolicy::Never => false,
+        RestartPolicy::OnFailure => failed,
+        RestartPolicy::Always => true,
+    };
+    if !wanted {
+        Restart::No
+    } else if restarts >= spec.max_restarts {
+        Restart::Exhausted
+    } else {
+        Restart::After(spec.backoff(restarts + 1))
+    }
+}
+
+struct RoleProc {
+    name: String,
+    child: Option<Child>,
+    state: RoleState,
+    restarts: u32,
+    /// Set when respawned after initial startup: reaching READY again
+    /// restarts running dependents so they redial.
+    respawned: bool,
+    /// Killed for missing its READY deadline; the exit counts as a timeout.
+    ready_timeout: bool,
+    /// Error, call and metrics lines seen by the output readers.
+    output: Arc<Mutex<ChildOutput>>,
+    pid: Option<u32>,
+    spawned: Option<(Instant, OffsetDateTime)>,
+    ready_at: Option<Instant>,
+    exit: Option<ExitStatus>,
+    activity: Activity,
+    stalls: u32,
+    /// Restarts after a stall, budgeted apart from `restarts` (crashes).
+    stall_restarts: u32,
+    /// Restarts asked for by the timeline or the dashboard; no budget.
+    scripted_restarts: u32,
+    /// `auto` port held until the first spawn binds it.
+    reserved: Option<Reservation>,
+}
+
+impl RoleProc {
+    /// Times the role was started again, for whatever reason.
+    fn respawns(&self) -> u32 {
+        self.restarts + self.stall_restarts + self.scripted_restarts
+    }
+
+    fn new(name: &str, reserved: Option<Reservation>) -> Self {
+        Self {
+            name: name.to_string(),
+            child: None,
+            state: RoleState::Waiting {
+                not_before: Instant::now(),
+            },
+            restarts: 0,
+            respawned: false,
+            ready_timeout: false,
+            output: Arc::default(),
+            pid: None,
+            spawned: None,
+            ready_at: None,
+            exit: None,
+            activity: Activity::new(),
+            stalls: 0,
+            stall_restarts: 0,
+            scripted_restarts: 0,
+            reserved,
+        }
+    }
+}
+
+/// Packet flow of the current instance, for stall detection.
+struct Activity {
+    /// Sum of the role's packet and sample counters.
+    flow: u64,
+    /// When `flow` last changed (or the instance spawned).
+    since: Instant,
+    stalled: bool,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            flow: 0,
+            since: Instant::now(),
+            stalled: false,
+        }
+    }
+
+    /// Whether the flow changed within the last `window`.
+    fn active(&self, now: Instant, window: Duration) -> bool {
+        now.saturating_duration_since(self.since) < window
+    }
+}
+
+/// Why a READY role counts as stalled at `now`, if it does: no output line
+/// for `window` ("silent"), or metrics arriving with flat packet counters
+/// ("flat"). A flat role is only blamed when nothing feeds it (a source)
+/// or something feeding it is still active. `updates` is its metrics line
+/// count, `upstream` the activity of the running roles that dial it.
+fn stall_reason(
+    activity: &Activity,
+    updates: u64,
+    last_line: Option<Instant>,
+    upstream: &[&Activity],
+    now: Instant,
+    window: Duration,
+) -> Option<&'static str> {
+    let silent = last_line.is_none_or(|t| now.saturating_duration_since(t) >= window);
+    let flat = updates > 0
+        && !activity.active(now, window)
+        && (upstream.is_empty() || upstream.iter().any(|a| a.active(now, window)));
+    match (silent, flat) {
+        (true, _) => Some("silent"),
+        (false, true) => Some("flat"),
+        _ => None,
+    }
+}
+
+/// Whether `ch` has exited (or cannot be waited for). Unlike `try_wait`
+/// this leaves the zombie, and with it the process group id, in place.
+fn has_exited(ch: &Child) -> bool {
+    let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+    let flags = libc::WEXITED | libc::WNOHANG | libc::WNOWAIT;
+    let r = unsafe { libc::waitid(libc::P_PID, ch.id() as libc::id_t, &mut info, flags) };
+    r != 0 || unsafe { info.si_pid() } != 0
+}
+
+/// Shutdown order: startup layers reversed (dependants stop before what
+/// they dial), each role with its SIGTERM-to-SIGKILL window, `stop_grace_ms`
+/// or `default_grace_ms`. A plan with a cycle cannot have started; its roles
+/// all stop together.
+fn stop_order(topo: &PlanTopology, default_grace_ms: u64) -> Vec<Vec<(usize, Duration)>> {
+    let layers =
+        graph::startup_layers(topo).unwrap_or_else(|_| vec![(0..topo.roles.len()).collect()]);
+    layers
+        .into_iter()
+        .rev()
+        .map(|layer| {
+            layer
+                .into_iter()
+                .map(|i| {
+                    let ms = topo.roles[i].stop_grace_ms.unwrap_or(default_grace_ms);
+                    (i, Duration::from_millis(ms))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The exit that ended the run.
+struct Failure {
+    role: usize,
+    status: String,
+    code: u8,
+}
+
+/// Owns the children of one plan run: startup in dependency order, READY
+/// tracking, restart policies and shutdown.
+pub struct Supervisor<'a> {
+    args: &'a Cli,
+    topo: PlanTopology,
+    tag: String,
+    deps: Vec<Vec<usize>>,
+    gates: Vec<bool>,
+    tx: mpsc::Sender<String>,
+    rx: mpsc::Receiver<String>,
+    /// Ctrl-C (or SIGTERM), from construction on so startup can be stopped.
+    interrupt: mpsc::Receiver<()>,
+    /// Ctrl-C seen; stays set once the message is taken.
+    interrupted: bool,
+    roles: Vec<RoleProc>,
+    failure: Option<Failure>,
+    started: (Instant, OffsetDateTime),
+    /// When the last pipeline summary was printed, and the metrics update
+    /// count it covered.
+    last_rollup: (Instant, u64),
+    /// Timeline clock (end of startup) and the next step to run.
+    timeline: (Instant, usize),
+    /// Children of this run, for the next run's stale-process sweep.
+    pids: Option<PidFile>,
+    /// `--tui` dashboard; dropped (terminal restored) before shutdown.
+    tui: Option<Tui>,
+    /// `--watch` on the plan files.
+    watch: Option<reload::Watch>,
+    /// Roles dropped from the plan by a reload, with their SIGKILL time.
+    retired: Vec<(RoleProc, Instant)>,
+}
+
+impl<'a> Supervisor<'a> {
+    pub fn new(
+        args: &'a Cli,
+        topo: PlanTopology,
+        reserved: Vec<Option<Reservation>>,
+        pids: Option<PidFile>,
+        tag: &str,
+    ) -> Self {
+        let (tx, rx) = mpsc::channel::<String>();
+        // Installed before `--tui` takes the terminal and before the first
+        // spawn, so Ctrl-C never falls back to the default action.
+        let (ctx, interrupt) = mpsc::channel::<()>();
+        let _ = ctrlc::set_handler(move || {
+            let _ = ctx.send(());
+        });
+        reload::catch_sighup();
+        let now = Instant::now();
+        let (deps, gates) = (graph::dependencies(&topo), graph::has_dependents(&topo));
+        let roles = topo
+            .roles
+            .iter()
+            .zip(reserved)
+            .map(|(r, reserved)| RoleProc::new(&r.name, reserved))
+            .collect();
+        Self {
+            args,
+            topo,
+            tag: tag.to_string(),
+            deps,
+            gates,
+            tx,
+            rx,
+            interrupt,
+            interrupted: false,
+            roles,
+            failure: None,
+            started: (now, OffsetDateTime::now_utc()),
+            last_rollup: (now, 0),
+            timeline: (now, 0),
+            pids,
+            tui: None,
+            watch: None,
+            retired: Vec::new(),
+        }
+    }
+
+    pub fn attach_tui(&mut self, tui: Tui) {
+        self.tui = Some(tui);
+    }
+
+    /// The running plan (the latest one after reloads).
+    pub fn topo(&self) -> &PlanTopology {
+        &self.topo
+    }
+
+    /// Start the plan layer by layer. Only roles that something depends on
+    /// gate the next layer; a missed READY deadline fails the run. Ctrl-C
+    /// stops the startup; `run` then shuts down what was started.
+    pub fn start(&mut self, layers: &[Vec<usize>]) -> Result<()> {
+        for layer in layers {
+            if self.interrupted() {
+                break;
+            }
+            let res = layer
+                .iter()
+                .try_for_each(|&i| self.spawn(i))
+                .and_then(|_| self.wait_for_ready(layer));
+            if let Err(e) = res {
+                logging::println_tag(&self.tag, &format!("startup failed: {e}"));
+                self.shutdown();
+                self.summary();
+                return Err(self.result().err().unwrap_or(e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Supervise until a child exits without being restarted or Ctrl-C is
+    /// received, then shut everything down. Fails with the exit code of the
+    /// role that ended the run, if it failed.
+    pub fn run(&mut self) -> Result<()> {
+        if self.args.watch {
+            self.watch = Some(reload::Watch::new(&self.topo.sources));
+        }
+
+        self.timeline = (Instant::now(), 0);
+        loop {
+            if self.interrupted() {
+                logging::println_tag(&self.tag, "Ctrl+C received; shutting down children");
+                return self.finish(Ok(()));
+            }
+            self.drain_ready();
+            // Check for child exit
+            for i in 0..self.roles.len() {
+                let status = match self.roles[i].child.as_mut().map(|c| c.try_wait()) {
+                    Some(Ok(Some(status))) => status,
+                    _ => continue,
+                };
+                self.roles[i].child = None;
+                self.roles[i].exit = Some(status);
+                if !self.on_exit(i, status) {
+                    return self.finish(Ok(()));
+                }
+            }
+            if let Err(e) = self.tick() {
+                return self.finish(Err(e));
+            }
+            self.rollup();
+            if !self.check_stalls() || !self.run_timeline() {
+                return self.finish(Ok(()));
+            }
+            if let Some(d) = self.topo.duration {
+                if self.timeline.0.elapsed() >= d {
+                    logging::println_tag(
+                        &self.tag,
+                        &format!("duration {:.1}s reached; stopping", d.as_secs_f64()),
+                    );
+                    return self.finish(Ok(()));
+                }
+            }
+            if !self.ui(true) {
+                logging::println_tag(&self.tag, "quit from dashboard; shutting down children");
+                return self.finish(Ok(()));
+            }
+            if reload::sighup() {
+                self.reload("SIGHUP");
+            } else if self.watch.as_mut().is_some_and(|w| w.changed()) {
+                self.reload("plan changed");
+            }
+            self.reap_retired();
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    fn interrupted(&mut self) -> bool {
+        self.interrupted |= self.interrupt.try_recv().is_ok();
+        self.interrupted
+    }
+
+    fn finish(&mut self, res: Result<()>) -> Result<()> {
+        self.shutdown();
+        self.summary();
+        res.and_then(|_| self.result())
+    }
+
+    fn result(&self) -> Result<()> {
+        match &self.failure {
+            Some(f) => Err(B2bError::Role {
+                role: self.roles[f.role].name.clone(),
+                status: f.status.clone(),
+                code: f.code,
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Final line naming the failing role, its exit code and last error.
+    fn summary(&self) {
+        let line = match &self.failure {
+            None => "summary: all roles stopped cleanly".to_string(),
+            Some(f) => {
+                // Read after shutdown so the reader thread has seen the
+                // child's final lines.
+                let p = &self.roles[f.role];
+                let last = p.output.lock().ok().and_then(|o| o.last_error.clone());
+                format!(
+                    "summary: {} failed ({}; exit {}); last error: {}",
+                    p.name,
+                    f.status,
+                    f.code,
+                    last.as_deref().unwrap_or("none")
+                )
+            }
+        };
+        logging::println_tag(&self.tag, &line);
+    }
+
+    fn fail(&mut self, i: usize, status: String, code: u8) {
+        self.failure.get_or_insert(Failure {
+            role: i,
+            status,
+            code: code.max(1),
+        });
+    }
+
+    /// Latest metrics rollup of every role, in plan order.
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.roles
+            .iter()
+            .map(|p| {
+                p.output
+                    .lock()
+                    .map(|o| o.metrics.clone())
+                    .unwrap_or_default()
+            })
+            .collect()
+    }
+
+    /// Periodic one-line pipeline summary, printed when new metrics arrived.
+    fn rollup(&mut self) {
+        if self.last_rollup.0.elapsed() < ROLLUP_EVERY {
+            return;
+        }
+        let snaps = self.snapshots();
+        let updates: u64 = snaps.iter().map(|s| s.updates).sum();
+        let fresh = updates != self.last_rollup.1;
+        self.last_rollup = (Instant::now(), updates);
+        if !fresh {
+            return;
+        }
+        let parts: Vec<String> = snaps
+            .iter()
+            .zip(&self.roles)
+            .filter(|(_, p)| p.child.is_some())
+            .map(|(s, p)| format!("{} {}", p.name, s.brief()))
+            .collect();
+        // JSON mode carries the full snapshots alongside the summary text.
+        let fresh: Vec<_> = snaps.iter().filter(|s| s.updates > 0).collect();
+        logging::event(
+            &self.tag,
+            "metrics",
+            &format!("pipeline: {}", parts.join(" | ")),
+            serde_json::json!({ "roles": fresh }),
+        );
+    }
+
+    fn spawn(&mut self, i: usize) -> Result<()> {
+        let spec = &self.topo.roles[i];
+        let kind = spec.params.kind();
+        if let (Some(logs), Ok(mut o)) = (&self.topo.logs, self.roles[i].output.lock()) {
+            if o.raw_log.is_none() {
+                o.raw_log = Some(RotatingFile::open(
+                    &logs.dir.join(format!("{}.log", spec.name)),
+                    logs.rotate_size_mb << 20,
+                    logs.rotate_every,
+                )?);
+            }
+        }
+        // Release the reserved port just before the child binds it.
+        self.roles[i].reserved = None;
+        let mut child = spawn_role(kind, &role_args(&spec.params), self.args)?;
+        if let Some(f) = self.pids.as_mut() {
+            f.add(&spec.name, child.id());
+        }
+        let p = &mut self.roles[i];
+        if let Ok(mut o) = p.output.lock() {
+            o.established = None;
+            o.last_line = None;
+            o.metrics = Snapshot::new(&spec.name, child.id());
+        }
+        pipe_child_output(
+            &spec.name,
+            kind,
+            &mut child,
+            self.tx.clone(),
+            p.output.clone(),
+        );
+        let note = if p.respawns() > 0 {
+            format!(" restart={}", p.respawns())
+        } else {
+            String::new()
+        };
+        logging::println_tag(
+            &self.tag,
+            &format!("started {} pid={}{note}", p.name, child.id()),
+        );
+        p.pid = Some(child.id());
+        p.spawned = Some((Instant::now(), OffsetDateTime::now_utc()));
+        p.ready_at = None;
+        p.exit = None;
+        p.activity = Activity::new();
+        p.child = Some(child);
+        p.ready_timeout = false;
+        p.state = RoleState::Starting {
+            deadline: Instant::now() + Duration::from_millis(self.args.ready_ms),
+        };
+        Ok(())
+    }
+
+    fn wait_for_ready(&mut self, layer: &[usize]) -> Result<()> {
+        let expected: Vec<usize> = layer.iter().copied().filter(|&i| self.gates[i]).collect();
+        let deadline = Instant::now() + Duration::from_millis(self.args.ready_ms);
+        loop {
+            self.drain_ready();
+            let missing: Vec<usize> = expected
+                .iter()
+                .copied()
+                .filter(|&i| !matches!(self.roles[i].state, RoleState::Ready))
+                .collect();
+            if missing.is_empty() || self.interrupted() {
+                return Ok(());
+            }
+            for &i in &missing {
+                let p = &mut self.roles[i];
+                if let Some(Ok(Some(status))) = p.child.as_mut().map(|c| c.try_wait()) {
+                    p.child = None;
+                    p.exit = Some(status);
+                    let name = p.name.clone();
+                    self.fail(i, describe_exit(&status), error::status_code(&status));
+                    anyhow::bail!("{name} exited before READY ({status})");
+                }
+            }
+            if Instant::now() >= deadline {
+                let names: Vec<&str> = missing
+                    .iter()
+                    .map(|&i| self.roles[i].name.as_str())
+                    .collect();
+                let msg = format!(
+                    "{} did not report READY within {} ms",
+                    names.join(", "),
+                    self.args.ready_ms
+                );
+                let status = format!("no READY within {} ms", self.args.ready_ms);
+                self.fail(missing[0], status, error::EXIT_TIMEOUT);
+                anyhow::bail!(B2bError::Timeout(msg));
+            }
+            self.ui(false);
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    fn drain_ready(&mut self) {
+        while let Ok(name) = self.rx.try_recv() {
+            if let Some(i) = self.topo.index_of(&name) {
+                self.on_ready(i);
+            }
+        }
+    }
+
+    fn on_ready(&mut self, i: usize) {
+        self.roles[i].state = RoleState::Ready;
+        self.roles[i].ready_at.get_or_insert_with(Instant::now);
+        if !std::mem::take(&mut self.roles[i].respawned) {
+            return;
+        }
+        let name = self.roles[i].name.clone();
+        logging::println_tag(
+            &self.tag,
+            &format!("{name} READY after restart {}", self.roles[i].respawns()),
+        );
+        // Dependents still hold a call to the old instance; cycle them so
+        // they redial once this one is back.
+        for j in 0..self.roles.len() {
+            if !self.deps[j].contains(&i) || self.roles[j].child.is_none() {
+                continue;
+            }
+            logging::println_tag(
+                &self.tag,
+                &format!(
+                    "restarting dependent {} after {name} restart",
+                    self.roles[j].name
+                ),
+            );
+            self.stop(j, true);
+        }
+    }
+
+    /// SIGTERM a role on purpose, then respawn it (without touching its
+    /// restart budget) or leave it down.
+    fn stop(&mut self, i: usize, respawn: bool) {
+        let p = &mut self.roles[i];
+        p.respawned |= respawn;
+        let Some(ch) = p.child.as_ref() else {
+            p.state = if respawn {
+                RoleState::Waiting {
+                    not_before: Instant::now(),
+                }
+            } else {
+                RoleState::Stopped
+            };
+            return;
+        };
+        unsafe {
+            libc::kill(ch.id() as i32, libc::SIGTERM);
+        }
+        let grace = self.grace(i);
+        self.roles[i].state = RoleState::Stopping {
+            kill_at: Instant::now() + grace,
+            respawn,
+        };
+    }
+
+    fn grace(&self, i: usize) -> Duration {
+        Duration::from_millis(
+            self.topo.roles[i]
+                .stop_grace_ms
+                .unwrap_or(self.args.grace_ms),
+        )
+    }
+
+    /// Apply the role's restart policy to an exit. Returns false when the
+    /// run should end.
+    fn on_exit(&mut self, i: usize, status: ExitStatus) -> bool {
+        let spec = self.topo.roles[i].restart;
+        let p = &mut self.roles[i];
+        if let RoleState::Stopping { respawn, .. } = p.state {
+            p.state = if respawn {
+                RoleState::Waiting {
+                    not_before: Instant::now(),
+                }
+            } else {
+                logging::println_tag(&self.tag, &format!("{} stopped", p.name));
+                RoleState::Stopped
+            };
+            return true;
+        }
+        let (what, code) = if p.ready_timeout {
+            let what = format!("missed its READY deadline ({} ms)", self.args.ready_ms);
+            (what, error::EXIT_TIMEOUT)
+        } else {
+            (describe_exit(&status), error::status_code(&status))
+        };
+        let failed = p.ready_timeout || !status.success();
+        let delay = match next_restart(&spec, p.restarts, failed) {
+            Restart::No => {
+                logging::println_tag(
+                    &self.tag,
+                    &format!("child {} {what}; shutting down others", p.name),
+                );
+                if failed {
+                    self.fail(i, what, code);
+                }
+                return false;
+            }
+            Restart::Exhausted => {
+                logging::println_tag(
+                    &self.tag,
+                    &format!(
+                        "child {} {what}; restart budget exhausted ({}); shutting down others",
+                        p.name, spec.max_restarts
+                    ),
+                );
+                self.fail(i, what, code);
+                return false;
+            }
+            Restart::After(delay) => delay,
+        };
+        p.restarts += 1;
+        logging::println_tag(
+            &self.tag,
+            &format!(
+                "child {} {what}; restart {}/{} in {} ms",
+                p.name,
+                p.restarts,
+                spec.max_restarts,
+                delay.as_millis()
+            ),
+        );
+        p.state = RoleState::Waiting {
+            not_before: Instant::now() + delay,
+        };
+        p.respawned = true;
+        true
+    }
+
+    /// Flag READY roles whose packet counters stayed flat, or whose output
+    /// went silent, for `--stall-ms`. A flat role is only blamed when it is
+    /// a source or something feeding it is still active, so one stall does
+    /// not cascade down the pipeline. Returns false when the run should end.
+    fn check_stalls(&mut self) -> bool {
+        if self.args.stall_ms == 0 {
+            return true;
+        }
+        let window = Duration::from_millis(self.args.stall_ms);
+        let now = Instant::now();
+        let outputs: Vec<(Snapshot, Option<Instant>)> = self
+            .roles
+            .iter()
+            .map(|p| {
+                p.output
+                    .lock()
+                    .map(|o| (o.metrics.clone(), o.last_line))
+                    .unwrap_or_default()
+            })
+            .collect();
+        for (p, (s, _)) in self.roles.iter_mut().zip(&outputs) {
+            // Packets are what crossed the wire; the sample counters keep
+            // moving while a role fills or drains its own buffers.
+            let flow = s.packets_tx + s.packets_rx;
+            if flow != p.activity.flow {
+                p.activity.flow = flow;
+                p.activity.since = now;
+                if std::mem::take(&mut p.activity.stalled) {
+                    logging::println_tag(&self.tag, &format!("STALL cleared role={}", p.name));
+                }
+            }
+        }
+        let active = |p: &RoleProc| p.activity.active(now, window);
+        for (i, (snap, last_line)) in outputs.iter().enumerate() {
+            let p = &self.roles[i];
+            if p.activity.stalled || p.child.is_none() || !matches!(p.state, RoleState::Ready) {
+                continue;
+            }
+            // Roles that dial this one are the ones sending it media.
+            let upstream: Vec<usize> = (0..self.roles.len())
+                .filter(|&j| self.deps[j].contains(&i) && self.roles[j].child.is_some())
+                .collect();
+            let up_activity: Vec<&Activity> =
+                upstream.iter().map(|&j| &self.roles[j].activity).collect();
+            let Some(reason) = stall_reason(
+                &p.activity,
+                snap.updates,
+                *last_line,
+                &up_activity,
+                now,
+                window,
+            ) else {
+                continue;
+            };
+            let up: Vec<String> = upstream
+                .iter()
+                .map(|&j| {
+                    let state = if active(&self.roles[j]) {
+                        "active"
+                    } else {
+                        "flat"
+                    };
+                    format!("{}:{state}", self.roles[j].name)
+                })
+                .collect();
+            let action = match self.args.stall_action {
+                StallAction::Warn => "warn",
+                StallAction::Restart => "restart",
+                StallAction::Fail => "fail",
+            };
+            logging::println_tag(
+                &self.tag,
+                &format!(
+                    "STALL role={} reason={reason} window_ms={} upstream={} action={action}",
+                    p.name,
+                    self.args.stall_ms,
+                    if up.is_empty() {
+                        "-".into()
+                    } else {
+                        up.join(",")
+                    }
+                ),
+            );
+            let p = &mut self.roles[i];
+            p.activity.stalled = true;
+            p.stalls += 1;
+            let status = format!("stalled ({reason} for {} ms)", self.args.stall_ms);
+            match self.args.stall_action {
+                StallAction::Warn => {}
+                StallAction::Fail => {
+                    self.fail(i, status, error::EXIT_TIMEOUT);
+                    return false;
+                }
+                // Stall restarts have their own `max_restarts` budget, so
+                // crash recovery is not spent on them.
+                StallAction::Restart => {
+                    let max = self.topo.roles[i].restart.max_restarts;
+                    if p.stall_restarts >= max {
+                        logging::println_tag(
+                            &self.tag,
+                            &format!(
+                                "{} stalled; restart budget exhausted ({max}); shutting down others",
+                                p.name
+                            ),
+                        );
+                        self.fail(i, status, error::EXIT_TIMEOUT);
+                        return false;
+                    }
+                    p.stall_restarts += 1;
+                    logging::println_tag(
+                        &self.tag,
+                        &format!(
+                            "restarting {} after stall (stall restart {}/{max})",
+                            p.name, p.stall_restarts
+                        ),
+                    );
+                    self.stop(i, true);
+                }
+            }
+        }
+        true
+    }
+
+    /// Run the `[[timeline]]` steps that are due. Returns false on `end`.
+    fn run_timeline(&mut self) -> bool {
+        let (t0, next) = self.timeline;
+        let due = self.topo.timeline[next..]
+            .iter()
+            .take_while(|s| t0.elapsed() >= s.at)
+            .count();
+        self.timeline.1 += due;
+        for n in next..next + due {
+            let step = &self.topo.timeline[n];
+            let (action, signal) = (step.action, step.signal);
+            let role = step.role.clone().unwrap_or_default();
+            logging::println_tag(
+                &self.tag,
+                format!(
+                    "timeline t+{:.1}s: {} {role}",
+                    step.at.as_secs_f64(),
+                    step.action.name()
+                )
+                .trim_end(),
+            );
+            let Some(i) = self.topo.index_of(&role) else {
+                // `end`; validation guarantees the other actions name a role
+                return false;
+            };
+            match action {
+                TimelineAction::Stop => self.stop(i, false),
+                TimelineAction::Restart => {
+                    self.roles[i].scripted_restarts += 1;
+                    self.stop(i, true);
+                }
+                TimelineAction::Kill | TimelineAction::Signal => {
+                    let sig = match action {
+                        TimelineAction::Kill => libc::SIGKILL,
+                        _ => signal.unwrap_or(libc::SIGTERM),
+                    };
+                    let p = &self.roles[i];
+                    let Some(ch) = p.child.as_ref() else {
+                        logging::println_tag(
+                            &self.tag,
+                            &format!("timeline: {} is not running; signal {sig} not sent", p.name),
+                        );
+                        continue;
+                    };
+                    if unsafe { libc::kill(ch.id() as i32, sig) } != 0 {
+                        let e = std::io::Error::last_os_error();
+                        logging::println_tag(
+                            &self.tag,
+                            &format!("timeline: signal {sig} to {} failed: {e}", p.name),
+                        );
+                    }
+                }
+                TimelineAction::End => return false,
+            }
+        }
+        true
+    }
+
+    /// Timers: pending (re)spawns, READY deadlines and cycling kills.
+    /// Fails when a respawn fails.
+    fn tick(&mut self) -> Result<()> {
+        let now = Instant::now();
+        for i in 0..self.roles.len() {
+            match self.roles[i].state {
+                RoleState::Waiting { not_before } if now >= not_before && self.deps_ready(i) => {
+                    if let Err(e) = self.spawn(i) {
+                        logging::println_tag(
+                            &self.tag,
+                            &format!("respawn of {} failed: {e:#}", self.roles[i].name),
+                        );
+                        return Err(e);
+                    }
+                }
+                RoleState::Starting { deadline } if self.gates[i] && now >= deadline => {
+                    let p = &mut self.roles[i];
+                    logging::println_tag(
+                        &self.tag,
+                        &format!(
+                            "{} did not report READY within {} ms; killing",
+                            p.name, self.args.ready_ms
+                        ),
+                    );
+                    if let Some(ch) = p.child.as_mut() {
+                        let _ = ch.kill();
+                    }
+                    p.ready_timeout = true;
+                    p.state = RoleState::Starting {
+                        deadline: now + Duration::from_millis(self.args.ready_ms),
+                    };
+                }
+                RoleState::Stopping { kill_at, respawn } if now >= kill_at => {
+                    let p = &mut self.roles[i];
+                    if let Some(ch) = p.child.as_mut() {
+                        let _ = ch.kill();
+                    }
+                    p.state = RoleState::Stopping {
+                        kill_at: now + Duration::from_millis(self.args.kill_ms),
+                        respawn,
+                    };
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn deps_ready(&self, i: usize) -> bool {
+        self.deps[i]
+            .iter()
+            .all(|&d| matches!(self.roles[d].state, RoleState::Ready))
+    }
+
+    /// Re-read the plan and apply it to the running pipeline: roles whose
+    /// arguments changed are restarted (dependents redial once they are
+    /// READY again, as after any restart), new roles start once their
+    /// dependencies are READY, removed roles are stopped, and everything
+    /// else keeps running. A plan that fails to load changes nothing.
+    fn reload(&mut self, why: &str) {
+        let plan = self.args.plan.as_deref().unwrap_or(Path::new("?"));
+        logging::println_tag(
+            &self.tag,
+            &format!("reload ({why}): reading {}", plan.display()),
+        );
+        let (topo, mut reserved) = match reload_plan(self.args, &self.topo) {
+            Ok(t) => t,
+            Err(e) => {
+                logging::println_tag(
+                    &self.tag,
+                    &format!("reload failed: {e:#}; keeping the running plan"),
+                );
+                return;
+            }
+        };
+        if let Some(w) = self.watch.as_mut() {
+            w.set_files(&topo.sources);
+        }
+        let diff = reload::diff(&self.topo, &topo);
+        if topo.logs.as_ref().map(|l| &l.dir) != self.topo.logs.as_ref().map(|l| &l.dir) {
+            logging::println_tag(
+                &self.tag,
+                "reload: [logs] changes take effect on the next run",
+            );
+        }
+        let mut old: Vec<Option<RoleProc>> = std::mem::take(&mut self.roles)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let mut roles = Vec::with_capacity(topo.roles.len());
+        for (j, spec) in topo.roles.iter().enumerate() {
+            let kept = self.topo.index_of(&spec.name).and_then(|i| old[i].take());
+            roles.push(match kept {
+                Some(mut p) => {
+                    if let Some(r) = reserved[j].take() {
+                        p.reserved = Some(r);
+                    }
+                    p
+                }
+                None => RoleProc::new(&spec.name, reserved[j].take()),
+            });
+        }
+        for (i, p) in old.into_iter().enumerate() {
+            if let Some(p) = p {
+                let grace = self.grace(i);
+                self.retire(p, grace);
+            }
+        }
+        self.roles = roles;
+        self.topo = topo;
+        self.deps = graph::dependencies(&self.topo);
+        self.gates = graph::has_dependents(&self.topo);
+        // Steps whose time has passed do not run late.
+        let elapsed = self.timeline.0.elapsed();
+        self.timeline.1 = self
+            .topo
+            .timeline
+            .iter()
+            .take_while(|s| s.at <= elapsed)
+            .count();
+        for (name, what) in &diff.changed {
+            let Some(i) = self.topo.index_of(name) else {
+                continue;
+            };
+            if self.roles[i].child.is_some() {
+                logging::println_tag(&self.tag, &format!("reload: restarting {name} ({what})"));
+                self.stop(i, true);
+            } else {
+                logging::println_tag(
+                    &self.tag,
+                    &format!("reload: {name} changed ({what}); applies on its next start"),
+                );
+            }
+        }
+        for name in &diff.added {
+            logging::println_tag(&self.tag, &format!("reload: adding {name}"));
+        }
+        let line = if diff.is_empty() {
+            "reload: no role changes".to_string()
+        } else {
+            format!(
+                "reload: {} changed, {} added, {} removed, {} unchanged",
+                diff.changed.len(),
+                diff.added.len(),
+                diff.removed.len(),
+                diff.unchanged.len()
+            )
+        };
+        logging::println_tag(&self.tag, &line);
+    }
+
+    /// SIGTERM a role that a reload removed; `reap_retired` collects it.
+    fn retire(&mut self, mut p: RoleProc, grace: Duration) {
+        let Some(ch) = p.child.as_ref() else {
+            logging::println_tag(&self.tag, &format!("reload: removed {}", p.name));
+            return;
+        };
+        logging::println_tag(
+            &self.tag,
+            &format!("reload: stopping removed role {} pid={}", p.name, ch.id()),
+        );
+        unsafe {
+            libc::kill(ch.id() as i32, libc::SIGTERM);
+        }
+        p.state = RoleState::Stopped;
+        self.retired.push((p, Instant::now() + grace));
+    }
+
+    fn reap_retired(&mut self) {
+        let now = Instant::now();
+        let kill_ms = Duration::from_millis(self.args.kill_ms);
+        self.retired.retain_mut(|(p, kill_at)| {
+            let Some(ch) = p.child.as_mut() else {
+                return false;
+            };
+            match ch.try_wait() {
+                Ok(None) if now >= *kill_at => {
+                    let _ = ch.kill();
+                    *kill_at = now + kill_ms;
+                    true
+                }
+                Ok(None) => true,
+                Ok(Some(status)) => {
+                    logging::println_tag(
+                        &self.tag,
+                        &format!("reload: {} stopped ({})", p.name, describe_exit(&status)),
+                    );
+                    false
+                }
+                Err(_) => false,
+            }
+        });
+    }
+
+    /// Redraw the dashboard and apply its key commands (only once startup
+    /// is done, when `commands` is set). Returns false on quit.
+    fn ui(&mut self, commands: bool) -> bool {
+        if self.tui.is_none() {
+            return true;
+        }
+        let views = self.views();
+        let cmds = self
+            .tui
+            .as_mut()
+            .map(|t| t.frame(&views))
+            .unwrap_or_default();
+        if !commands {
+            return true;
+        }
+        for cmd in cmds {
+            match cmd {
+                tui::Command::Quit => return false,
+                tui::Command::Restart(i) => {
+                    logging::println_tag(
+                        &self.tag,
+                        &format!("restart {} (dashboard)", self.roles[i].name),
+                    );
+                    self.roles[i].scripted_restarts += 1;
+                    self.stop(i, true);
+                }
+                tui::Command::Stop(i) => {
+                    logging::println_tag(
+                        &self.tag,
+                        &format!("stop {} (dashboard)", self.roles[i].name),
+                    );
+                    self.stop(i, false);
+                }
+            }
+        }
+        true
+    }
+
+    fn views(&self) -> Vec<RoleView> {
+        self.roles
+            .iter()
+            .zip(&self.topo.roles)
+            .map(|(p, spec)| {
+                let out = p.output.lock().ok();
+                let in_call = out.as_ref().is_some_and(|o| o.established.is_some());
+                let state = match p.state {
+                    RoleState::Waiting { .. } if p.exit.is_some() => "exited",
+                    RoleState::Waiting { .. } => "waiting",
+                    RoleState::Starting { .. } => "spawned",
+                    RoleState::Ready if in_call => "in-call",
+                    RoleState::Ready => "READY",
+                    RoleState::Stopping { .. } => "stopping",
+                    RoleState::Stopped => "stopped",
+                };
+                RoleView {
+                    name: p.name.clone(),
+                    tag: logging::strip_ansi(&logging::instance_tag(
+                        role_str(spec.params.kind()),
+                        &p.name,
+                    )),
+                    state,
+                    pid: p.child.as_ref().map(|c| c.id()),
+                    restarts: p.respawns(),
+                    stalls: p.stalls,
+                    uptime: p.child.as_ref().and(p.spawned).map(|(t, _)| t.elapsed()),
+                    metrics: out.as_ref().map(|o| o.metrics.brief()).unwrap_or_default(),
+                    last_error: out.as_ref().and_then(|o| o.last_error.clone()),
+                }
+            })
+            .collect()
+    }
+
+    /// Stop roles in reverse startup order, a layer at a time, so callers
+    /// hang up before the roles they dial go away. Each role gets SIGTERM
+    /// and its grace window before SIGKILL; `--kill-ms` bounds the whole
+    /// shutdown.
+    fn shutdown(&mut self) {
+        // Restore the terminal so shutdown and summary lines stay visible.
+        self.tui = None;
+        // Already past their SIGTERM; do not wait on them again.
+        for (mut p, _) in self.retired.drain(..) {
+            if let Some(ch) = p.child.as_mut() {
+                let _ = ch.kill();
+                let _ = ch.wait();
+            }
+        }
+        let started = Instant::now();
+        let hard = started + Duration::from_millis(self.args.kill_ms);
+        // Grandchildren (aplay) outlive a SIGKILLed sink otherwise. The
+        // group is signalled only while one of its members is known to be
+        // around, so its id cannot have been reused.
+        let mut group_killed = false;
+        for layer in stop_order(&self.topo, self.args.grace_ms) {
+            // (role, SIGTERM sent, SIGKILL due, SIGKILL sent at)
+            let mut pending: Vec<(usize, Instant, Instant, Option<Instant>)> = Vec::new();
+            for (i, grace) in layer {
+                let p = &mut self.roles[i];
+                let Some(ch) = p.child.as_ref() else {
+                    let how = match &p.exit {
+                        Some(status) => describe_exit(status),
+                        None => "never started".into(),
+                    };
+                    logging::println_tag(
+                        &self.tag,
+                        &format!("shutdown: {} already gone ({how})", p.name),
+                    );
+                    continue;
+                };
+                unsafe {
+                    libc::kill(ch.id() as i32, libc::SIGTERM);
+                }
+                let now = Instant::now();
+                pending.push((i, now, (now + grace).min(hard), None));
+            }
+            while !pending.is_empty() {
+                let now = Instant::now();
+                let mut k = 0;
+                while k < pending.len() {
+                    let (i, sent, kill_at, killed) = pending[k];
+                    let last = self.roles.iter().filter(|p| p.child.is_some()).count() == 1;
+                    let p = &mut self.roles[i];
+                    // The last child is reaped only after the group is
+                    // signalled: until then its zombie holds the group id.
+                    let status = if !last || group_killed {
+                        p.child.as_mut().map(|c| c.try_wait())
+                    } else if p.child.as_ref().is_some_and(has_exited) {
+                        kill_child_group();
+                        group_killed = true;
+                        p.child.as_mut().map(|c| c.try_wait())
+                    } else {
+                        Some(Ok(None))
+                    };
+                    match status {
+                        Some(Ok(None)) => {}
+                        other => {
+                            if let Some(Ok(Some(status))) = other {
+                                p.exit = Some(status);
+                            }
+                            p.child = None;
+                            let how = p.exit.as_ref().map(describe_exit).unwrap_or_default();
+                            let ms = now.duration_since(sent).as_millis();
+                            let clean = p.exit.as_ref().is_some_and(|st| {
+                                st.success() || exit_signal(st) == Some(libc::SIGTERM)
+                            });
+                            let outcome = if killed.is_some() {
+                                format!("needed SIGKILL ({how} after {ms} ms)")
+                            } else if clean {
+                                format!("exited cleanly in {ms} ms")
+                            } else {
+                                format!("exited after SIGTERM in {ms} ms ({how})")
+                            };
+                            logging::println_tag(
+                                &self.tag,
+                                &format!("shutdown: {} {outcome}", p.name),
+                            );
+                            pending.swap_remove(k);
+                            continue;
+                        }
+                    }
+                    match killed {
+                        None if now >= kill_at => {
+                            if let Some(ch) = p.child.as_mut() {
+                                let _ = ch.kill();
+                            }
+                            pending[k].3 = Some(now);
+                            if kill_at == hard {
+                                logging::println_tag(
+                                    &self.tag,
+                                    &format!(
+                                        "shutdown: kill deadline ({} ms) reached; SIGKILL {}",
+                                        self.args.kill_ms, p.name
+                                    ),
+                                );
+                            }
+                        }
+                        // SIGKILL normally lands at once; do not hang on a
+                        // child stuck in the kernel. It stays unreaped.
+                        Some(at) if now >= at + Duration::from_secs(1) => {
+                            logging::println_tag(
+                                &self.tag,
+                                &format!("shutdown: {} did not exit after SIGKILL", p.name),
+                            );
+                            pending.swap_remove(k);
+                            continue;
+                        }
+                        _ => {}
+                    }
+                    k += 1;
+                }
+                if !pending.is_empty() {
+                    thread::sleep(Duration::from_millis(20));
+                }
+            }
+        }
+        // Children stuck after SIGKILL are still members.
+        if !group_killed && self.roles.iter().any(|p| p.child.is_some()) {
+            kill_child_group();
+        }
+        logging::println_tag(
+            &self.tag,
+            &format!("shutdown: done in {} ms", started.elapsed().as_millis()),
+        );
+    }
+
+    /// Per-role timings, exits and last metrics for `--report`.
+    pub fn report(&self, plan: &Path) -> RunReport {
+        let ms = |from: Instant, to: Instant| to.saturating_duration_since(from).as_millis() as u64;
+        let roles = self
+            .roles
+            .iter()
+            .zip(&self.topo.roles)
+            .map(|(p, spec)| {
+                let out = p.output.lock().ok();
+                let spawned_at = p.spawned.map(|(t, _)| t);
+                let established = out.as_ref().and_then(|o| o.established);
+                let metrics_at = out.as_ref().and_then(|o| o.metrics_at);
+                RoleReport {
+                    name: p.name.clone(),
+                    kind: role_str(spec.params.kind()).into(),
+                    pid: p.pid,
+                    spawned_at: p.spawned.map(|(_, wall)| report::rfc3339(wall)),
+                    time_to_ready_ms: spawned_at.zip(p.ready_at).map(|(s, r)| ms(s, r)),
+                    call_established_ms: spawned_at.zip(established).map(|(s, e)| ms(s, e)),
+                    call_ms: established.zip(metrics_at).map(|(e, m)| ms(e, m)),
+                    exit_code: p.exit.and_then(|s| s.code()),
+                    exit_signal: p.exit.and_then(|s| exit_signal(&s)),
+                    restarts: p.restarts,
+                    stalls: p.stalls,
+                    stall_restarts: p.stall_restarts,
+                    scripted_restarts: p.scripted_restarts,
+                    log_file: out
+                        .as_ref()
+                        .and_then(|o| o.raw_log.as_ref())
+                        .map(|f| f.path().display().to_string()),
+                    last_error: out.as_ref().and_then(|o| o.last_error.clone()),
+                    last_metrics: out.map(|o| o.metrics.clone()).filter(|m| m.updates > 0),
+                }
+            })
+            .collect();
+        RunReport {
+            plan: plan.display().to_string(),
+            started_at: report::rfc3339(self.started.1),
+            ended_at: report::rfc3339(OffsetDateTime::now_utc()),
+            duration_ms: ms(self.started.0, Instant::now()),
+            exit_code: 0,
+            error: None,
+            roles,
+            expectations: Vec::new(),
+            merged_log: None,
+        }
+    }
+}
+
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        status.signal()
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = status;
+        None
+    }
+}
+
+fn describe_exit(status: &ExitStatus) -> String {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(sig) = status.signal() {
+            return format!("terminated by signal {sig}");
+        }
+    }
+    match status.code() {
+        Some(code) => format!("exited with code {code}"),
+        None => "exited".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(policy: RestartPolicy) -> RestartSpec {
+        RestartSpec {
+            policy,
+            max_restarts: 3,
+            backoff_ms: 500,
+            backoff_max_ms: 3_000,
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let s = spec(RestartPolicy::Always);
+        let ms: Vec<u128> = (1..=6).map(|n| s.backoff(n).as_millis()).collect();
+        assert_eq!(ms, [500, 1000, 2000, 3000, 3000, 3000]);
+        assert_eq!(s.backoff(0), s.backoff(1));
+    }
+
+    #[test]
+    fn backoff_saturates_on_large_attempts() {
+        let s = RestartSpec {
+            backoff_ms: u64::MAX / 2,
+            backoff_max_ms: u64::MAX,
+            ..spec(RestartPolicy::Always)
+        };
+        assert_eq!(s.backoff(u32::MAX), Duration::from_millis(u64::MAX));
+    }
+
+    #[test]
+    fn policy_decides_which_exits_restart() {
+        for (policy, failed, want) in [
+            (RestartPolicy::Never, true, false),
+            (RestartPolicy::Never, false, false),
+            (RestartPolicy::OnFailure, true, true),
+            (RestartPolicy::OnFailure, false, false),
+            (RestartPolicy::Always, true, true),
+            (RestartPolicy::Always, false, true),
+        ] {
+            let got = next_restart(&spec(policy), 0, failed);
+            assert_eq!(got != Restart::No, want, "{policy:?} failed={failed}");
+        }
+    }
+
+    #[test]
+    fn restarts_back_off_until_the_budget_is_spent() {
+        let s = spec(RestartPolicy::OnFailure);
+        let got: Vec<Restart> = (0..5).map(|n| next_restart(&s, n, true)).collect();
+        assert_eq!(
+            got,
+            [
+                Restart::After(Duration::from_millis(500)),
+                Restart::After(Duration::from_millis(1000)),
+                Restart::After(Duration::from_millis(2000)),
+                Restart::Exhausted,
+                Restart::Exhausted,
+            ]
+        );
+        let none = RestartSpec {
+            max_restarts: 0,
+            ..s
+        };
+        assert_eq!(next_restart(&none, 0, true), Restart::Exhausted);
+    }
+
+    /// Activity whose flow last moved `idle` before `now`.
+    fn activity(now: Instant, idle_ms: u64) -> Activity {
+        Activity {
+            flow: 1,
+            since: now - Duration::from_millis(idle_ms),
+            stalled: false,
+        }
+    }
+
+    #[test]
+    fn stall_predicate() {
+        // Well after any process start, so `now - window` is representable.
+        let now = Instant::now() + Duration::from_secs(60);
+        let window = Duration::from_millis(3000);
+        let line = |ago_ms| Some(now - Duration::from_millis(ago_ms));
+        let (busy, idle) = (activity(now, 100), activity(now, 3000));
+        assert!(busy.active(now, window));
+        assert!(!idle.active(now, window));
+        assert!(activity(now, 2999).active(now, window));
+
+        let reason = |a: &Activity, updates, last, up: &[&Activity]| {
+            stall_reason(a, updates, last, up, now, window)
+        };
+        // Moving packets and fresh output.
+        assert_eq!(reason(&busy, 3, line(10), &[]), None);
+        // No output line for the window, or none at all.
+        assert_eq!(reason(&busy, 3, line(3000), &[]), Some("silent"));
+        assert_eq!(reason(&busy, 0, None, &[]), Some("silent"));
+        // Flat counters: a source (nothing upstream) is to blame...
+        assert_eq!(reason(&idle, 3, line(10), &[]), Some("flat"));
+        // ...as is a role whose feeder still sends...
+        assert_eq!(reason(&idle, 3, line(10), &[&idle, &busy]), Some("flat"));
+        // ...but not one whose feeders are flat too: the stall is upstream.
+        assert_eq!(reason(&idle, 3, line(10), &[&idle]), None);
+        // No metrics line yet: nothing to call flat.
+        assert_eq!(reason(&idle, 0, line(10), &[]), None);
+    }
+
+    #[test]
+    fn shutdown_stops_dependants_first_with_their_own_grace() {
+        #[derive(serde::Deserialize)]
+        struct Roles {
+            role: Vec<crate::orchestrator::plan::RoleSpec>,
+        }
+        let plan = r#"
+            [[role]]
+            name = "src"
+            kind = "source"
+            sip_target = "@mix"
+            [[role]]
+            name = "mix"
+            kind = "mixer"
+            sip_bind = "127.0.0.1:5070"
+            sip_target = "@snk"
+            stop_grace_ms = 250
+            [[role]]
+            name = "snk"
+            kind = "sink"
+            sip_bind = "127.0.0.1:5062"
+            stop_grace_ms = 0
+            [[role]]
+            name = "snk2"
+            kind = "sink"
+            sip_bind = "127.0.0.1:5064"
+            "#;
+        let topo = PlanTopology {
+            roles: toml::from_str::<Roles>(plan).unwrap().role,
+            ..Default::default()
+        };
+        let ms = Duration::from_millis;
+        assert_eq!(
+            stop_order(&topo, 1500),
+            vec![
+                vec![(0, ms(1500))],
+                vec![(1, ms(250))],
+                vec![(2, ms(0)), (3, ms(1500))],
+            ]
+        );
+    }
+}
@@ -0,0 +1,451 @@
+use crate::logging;
+use anyhow::{Result, bail};
+use std::{
+    collections::VecDeque,
+    io::{Read, Write, stdout},
+    sync::{Arc, Mutex, mpsc},
+    time::{Duration, Instant},
+};
+
+/// Console lines kept for the log pane.
+const LOG_LINES: usize = 5000;
+/// Lines printed to the restored terminal when the dashboard closes.
+const TAIL_ON_EXIT: usize = 20;
+const FRAME_EVERY: Duration = Duration::from_millis(200);
+const PANE_ROWS: usize = 7;
+const PANE_MIN_COLS: usize = 28;
+
+/// Terminal settings from before `Tui::start`, held while the dashboard
+/// owns the terminal.
+static SAVED: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+/// What the supervisor shows for one role.
+pub struct RoleView {
+    pub name: String,
+    /// Console tag without colors, e.g. `[SRC-A]`; the role filter matches it.
+    pub tag: String,
+    pub state: &'static str,
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    pub stalls: u32,
+    pub uptime: Option<Duration>,
+    pub metrics: String,
+    pub last_error: Option<String>,
+}
+
+/// Requests from the keyboard, by role index.
+pub enum Command {
+    Restart(usize),
+    Stop(usize),
+    Quit,
+}
+
+#[derive(Clone, Copy)]
+enum Key {
+    Char(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    Tab,
+    BackTab,
+    Enter,
+    Backspace,
+    Esc,
+}
+
+/// Full-screen dashboard for `--tui`: a pane per role and a scrollable,
+/// filterable log. Owns the terminal until dropped.
+pub struct Tui {
+    log: Arc<Mutex<VecDeque<String>>>,
+    keys: mpsc::Receiver<Key>,
+    started: Instant,
+    last_frame: Option<Instant>,
+    selected: usize,
+    /// Lines scrolled back from the tail; 0 follows new output.
+    scroll: usize,
+    role_filter: bool,
+    text_filter: String,
+    /// Filter being typed after `/`.
+    input: Option<String>,
+}
+
+impl Tui {
+    pub fn start() -> Result<Self> {
+        let tty = unsafe { libc::isatty(0) == 1 && libc::isatty(1) == 1 };
+        if !tty {
+            bail!("--tui needs a terminal on stdin and stdout");
+        }
+        let mut saved: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(0, &mut saved) } != 0 {
+            bail!("--tui: tcgetattr: {}", std::io::Error::last_os_error());
+        }
+        // Keystrokes unbuffered and unechoed; ISIG stays on so Ctrl-C still
+        // runs the normal shutdown.
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        unsafe { libc::tcsetattr(0, libc::TCSANOW, &raw) };
+        if let Ok(mut s) = SAVED.lock() {
+            *s = Some(saved);
+        }
+
+        let log = Arc::new(Mutex::new(VecDeque::with_capacity(LOG_LINES)));
+        let sink = log.clone();
+        logging::set_console(Some(Box::new(move |line: &str| {
+            if let Ok(mut l) = sink.lock() {
+                if l.len() == LOG_LINES {
+                    l.pop_front();
+                }
+                l.push_back(logging::strip_ansi(line));
+            }
+        })));
+        let (tx, keys) = mpsc::channel();
+        std::thread::spawn(move || read_keys(tx));
+        let mut out = stdout().lock();
+        // Alternate screen, hidden cursor.
+        let _ = write!(out, "\x1b[?1049h\x1b[?25l");
+        let _ = out.flush();
+        Ok(Self {
+            log,
+            keys,
+            started: Instant::now(),
+            last_frame: None,
+            selected: 0,
+            scroll: 0,
+            role_filter: false,
+            text_filter: String::new(),
+            input: None,
+        })
+    }
+
+    /// Handle pending keys and redraw (at most every `FRAME_EVERY`).
+    pub fn frame(&mut self, roles: &[RoleView]) -> Vec<Command> {
+        let mut cmds = Vec::new();
+        while let Ok(key) = self.keys.try_recv() {
+            if let Some(c) = self.on_key(key, roles.len()) {
+                cmds.push(c);
+            }
+        }
+        let due = self.last_frame.is_none_or(|t| t.elapsed() >= FRAME_EVERY);
+        if due || !cmds.is_empty() {
+            self.draw(roles);
+            self.last_frame = Some(Instant::now());
+        }
+        cmds
+    }
+
+    fn on_key(&mut self, key: Key, n: usize) -> Option<Command> {
+        if let Some(input) = self.input.as_mut() {
+            match key {
+                Key::Enter => self.text_filter = self.input.take().unwrap_or_default(),
+                Key::Esc => self.input = None,
+                Key::Backspace => {
+                    input.pop();
+                }
+                Key::Char(c) => input.push(c),
+                _ => {}
+            }
+            self.scroll = 0;
+            return None;
+        }
+        let n = n.max(1);
+        match key {
+            Key::Char('q') => return Some(Command::Quit),
+            Key::Char('r') => return Some(Command::Restart(self.selected)),
+            Key::Char('s') => return Some(Command::Stop(self.selected)),
+            Key::Tab | Key::Right | Key::Char('l') => self.selected = (self.selected + 1) % n,
+            Key::BackTab | Key::Left | Key::Char('h') => {
+                self.selected = (self.selected + n - 1) % n
+            }
+            Key::Char(c @ '1'..='9') => {
+                let i = c as usize - '1' as usize;
+                if i < n {
+                    self.selected = i;
+                }
+            }
+            Key::Up | Key::Char('k') => self.scroll += 1,
+            Key::Down | Key::Char('j') => self.scroll = self.scroll.saturating_sub(1),
+            Key::PageUp => self.scroll += 10,
+            Key::PageDown => self.scroll = self.scroll.saturating_sub(10),
+            Key::Home | Key::Char('g') => self.scroll = usize::MAX / 2,
+            Key::End | Key::Char('G') => self.scroll = 0,
+            Key::Char('f') => {
+                self.role_filter = !self.role_filter;
+                self.scroll = 0;
+            }
+            Key::Char('/') => self.input = Some(String::new()),
+            Key::Esc => {
+                self.role_filter = false;
+                self.text_filter.clear();
+                self.scroll = 0;
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn draw(&mut self, roles: &[RoleView]) {
+        let (rows, cols) = term_size();
+        self.selected = self.selected.min(roles.len().saturating_sub(1));
+        let mut lines: Vec<String> = Vec::with_capacity(rows);
+        let up = self.started.elapsed().as_secs();
+        lines.push(reverse(&fit(
+            &format!(
+                " b2b orchestrator  up {:02}:{:02}:{:02}  {} role(s)",
+                up / 3600,
+                up / 60 % 60,
+                up % 60,
+                roles.len()
+            ),
+            cols,
+        )));
+
+        // Panes, as many per row as fit.
+        let per_row = (cols / PANE_MIN_COLS).clamp(1, roles.len().max(1));
+        let width = cols / per_row;
+        for (r, chunk) in roles.chunks(per_row).enumerate() {
+            let mut block = vec![String::new(); PANE_ROWS];
+            for (c, view) in chunk.iter().enumerate() {
+                let i = r * per_row + c;
+                for (row, text) in pane(view, i, width, i == self.selected)
+                    .into_iter()
+                    .enumerate()
+                {
+                    block[row].push_str(&text);
+                }
+            }
+            lines.extend(block);
+        }
+
+        // Log pane fills the rest, above the status line.
+        let filter = self.describe_filter(roles);
+        let log_rows = rows.saturating_sub(lines.len() + 2);
+        let shown: Vec<String> = {
+            let log = self.log.lock().map(|l| l.clone()).unwrap_or_default();
+            let tag = roles.get(self.selected).map(|v| v.tag.as_str());
+            log.into_iter()
+                .filter(|l| !self.role_filter || tag.is_some_and(|t| l.contains(t)))
+                .filter(|l| self.text_filter.is_empty() || l.contains(&self.text_filter))
+                .collect()
+        };
+        let max_scroll = shown.len().saturating_sub(log_rows);
+        self.scroll = self.scroll.min(max_scroll);
+        let end = shown.len() - self.scroll;
+        let start = end.saturating_sub(log_rows);
+        let pos = if self.scroll == 0 {
+            "following".to_string()
+        } else {
+            format!("scrolled back {} line(s)", self.scroll)
+        };
+        lines.push(reverse(&fit(&format!(" log  {filter}  {pos}"), cols)));
+        for l in &shown[start..end] {
+            lines.push(color_line(&fit(l, cols)));
+        }
+        while lines.len() < rows.saturating_sub(1) {
+            lines.push(String::new());
+        }
+        lines.truncate(rows.saturating_sub(1));
+        let status = match &self.input {
+            Some(text) => format!(" filter: {text}_   (Enter apply, Esc cancel)"),
+            None => " Tab/1-9 select  r restart  s stop  f role filter  / text filter  Esc clear  \u{2191}\u{2193} PgUp/PgDn g/G scroll  q quit".into(),
+        };
+        lines.push(reverse(&fit(&status, cols)));
+
+        let mut buf = String::from("\x1b[H");
+        for (n, l) in lines.iter().enumerate() {
+            buf.push_str(l);
+            buf.push_str("\x1b[K");
+            if n + 1 < lines.len() {
+                buf.push_str("\r\n");
+            }
+        }
+        let mut out = stdout().lock();
+        let _ = out.write_all(buf.as_bytes());
+        let _ = out.flush();
+    }
+
+    fn describe_filter(&self, roles: &[RoleView]) -> String {
+        let mut parts = Vec::new();
+        if self.role_filter {
+            if let Some(v) = roles.get(self.selected) {
+                parts.push(format!("role={}", v.name));
+            }
+        }
+        if !self.text_filter.is_empty() {
+            parts.push(format!("text={:?}", self.text_filter));
+        }
+        if parts.is_empty() {
+            "all lines".into()
+        } else {
+            parts.join(" ")
+        }
+    }
+}
+
+/// Hand the terminal back if the dashboard holds it: console lines to
+/// stderr, the normal screen and cursor, and the saved termios. Does nothing
+/// otherwise, so the panic hook can call it before `process::exit` skips
+/// `Tui`'s destructor.
+pub fn restore_terminal() {
+    let Some(saved) = SAVED.lock().ok().and_then(|mut s| s.take()) else {
+        return;
+    };
+    logging::set_console(None);
+    let mut out = stdout().lock();
+    let _ = write!(out, "\x1b[?25h\x1b[?1049l");
+    let _ = out.flush();
+    unsafe { libc::tcsetattr(0, libc::TCSANOW, &saved) };
+}
+
+impl Drop for Tui {
+    fn drop(&mut self) {
+        restore_terminal();
+        // Leave the last lines on the normal screen for context.
+        if let Ok(log) = self.log.lock() {
+            let skip = log.len().saturating_sub(TAIL_ON_EXIT);
+            let mut e = std::io::stderr().lock();
+            for l in log.iter().skip(skip) {
+                let _ = writeln!(e, "{l}");
+            }
+        }
+    }
+}
+
+fn pane(v: &RoleView, i: usize, width: usize, selected: bool) -> Vec<String> {
+    let inner = width.saturating_sub(4);
+    let title = format!(" {} {} ", i + 1, v.name);
+    let bar = "\u{2500}".repeat(width.saturating_sub(3 + title.chars().count()));
+    let top = fit(&format!("\u{250c}\u{2500}{title}{bar}\u{2510}"), width);
+    let bottom = format!(
+        "\u{2514}{}\u{2518}",
+        "\u{2500}".repeat(width.saturating_sub(2))
+    );
+    let up = v.uptime.map_or("-".into(), |d| format!("{}s", d.as_secs()));
+    let pid = v.pid.map_or("-".into(), |p| p.to_string());
+    let body = [
+        format!("{}  pid {pid}  up {up}", v.state),
+        format!("restarts {}  stalls {}", v.restarts, v.stalls),
+        v.metrics.clone(),
+        String::new(),
+        v.last_error.clone().unwrap_or_default(),
+    ];
+    let mut out = vec![top];
+    for (n, text) in body.iter().enumerate() {
+        let cell = pad(&fit(text, inner), inner);
+        let cell = match n {
+            0 => state_color(v.state, &cell),
+            4 if !text.is_empty() => format!("\x1b[31m{cell}\x1b[0m"),
+            _ => cell,
+        };
+        out.push(format!("\u{2502} {cell} \u{2502}"));
+    }
+    out.push(bottom);
+    if selected {
+        for l in &mut out {
+            *l = format!("\x1b[1m{l}\x1b[0m");
+        }
+    }
+    out
+}
+
+fn state_color(state: &str, s: &str) -> String {
+    let code = match state {
+        "in-call" | "READY" => "32",
+        "spawned" | "waiting" | "stopping" => "33",
+        "exited" | "stopped" => "31",
+        _ => "0",
+    };
+    format!("\x1b[{code}m{s}\x1b[0m")
+}
+
+fn color_line(l: &str) -> String {
+    if l.contains("error") || l.contains("panic") || l.contains("FAIL") {
+        format!("\x1b[31m{l}\x1b[0m")
+    } else if l.contains("warn") || l.contains("stalled") {
+        format!("\x1b[33m{l}\x1b[0m")
+    } else {
+        l.to_string()
+    }
+}
+
+fn reverse(s: &str) -> String {
+    format!("\x1b[7m{s}\x1b[0m")
+}
+
+/// First `cols` characters of `s`.
+fn fit(s: &str, cols: usize) -> String {
+    s.chars().take(cols).collect()
+}
+
+fn pad(s: &str, cols: usize) -> String {
+    format!("{s}{}", " ".repeat(cols.saturating_sub(s.chars().count())))
+}
+
+fn term_size() -> (usize, usize) {
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(1, libc::TIOCGWINSZ, &mut ws) } == 0 && ws.ws_row > 0 {
+        (ws.ws_row as usize, ws.ws_col as usize)
+    } else {
+        (24, 80)
+    }
+}
+
+/// Decode keystrokes from stdin until it closes.
+fn read_keys(tx: mpsc::Sender<Key>) {
+    let mut stdin = std::io::stdin().lock();
+    let mut buf = [0u8; 64];
+    loop {
+        let n = match stdin.read(&mut buf) {
+            Ok(0) | Err(_) => return,
+            Ok(n) => n,
+        };
+        let mut bytes = &buf[..n];
+        while !bytes.is_empty() {
+            let (key, used) = decode(bytes);
+            bytes = &bytes[used..];
+            if let Some(k) = key {
+                if tx.send(k).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn decode(b: &[u8]) -> (Option<Key>, usize) {
+    const SEQS: [(&[u8], Key); 12] = [
+        (b"\x1b[A", Key::Up),
+        (b"\x1b[B", Key::Down),
+        (b"\x1b[C", Key::Right),
+        (b"\x1b[D", Key::Left),
+        (b"\x1b[5~", Key::PageUp),
+        (b"\x1b[6~", Key::PageDown),
+        (b"\x1b[H", Key::Home),
+        (b"\x1b[1~", Key::Home),
+        (b"\x1b[F", Key::End),
+        (b"\x1b[4~", Key::End),
+        (b"\x1b[Z", Key::BackTab),
+        (b"\x1bOH", Key::Home),
+    ];
+    for (seq, key) in SEQS {
+        if b.starts_with(seq) {
+            return (Some(key), seq.len());
+        }
+    }
+    match b[0] {
+        // Lone ESC, or an unknown sequence: drop the rest of the read.
+        0x1b if b.len() == 1 => (Some(Key::Esc), 1),
+        0x1b => (None, b.len()),
+        b'\t' => (Some(Key::Tab), 1),
+        b'\r' | b'\n' => (Some(Key::Enter), 1),
+        0x7f | 0x08 => (Some(Key::Backspace), 1),
+        c if c.is_ascii_graphic() || c == b' ' => (Some(Key::Char(c as char)), 1),
+        _ => (None, 1),
+    }
+}
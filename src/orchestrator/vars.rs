@@ -0,0 +1,455 @@
+use anyhow::{Result, anyhow, bail};
+use std::{collections::HashMap, ffi::CStr, net::Ipv4Addr};
+use toml_edit::{DocumentMut, Item, Value};
+
+/// One plan string that changed under interpolation, for `--dry-run`.
+#[derive(Debug, Clone)]
+pub struct Substitution {
+    /// Dotted key path, e.g. `role[1].sip_target`.
+    pub key: String,
+    pub from: String,
+    pub to: String,
+}
+
+/// Resolved `[vars]` plus every substituted plan string.
+#[derive(Debug, Default)]
+pub struct Resolved {
+    pub vars: Vec<(String, String)>,
+    pub substitutions: Vec<Substitution>,
+}
+
+/// Expand `${...}` references in every string value of a plan document:
+///
+/// - `${NAME}`: a `[vars]` entry (vars may reference each other)
+/// - `${env:NAME}` / `${env:NAME:-default}`: environment, shell semantics
+/// - `${iface:eth0}`: first IPv4 address of an interface
+/// - `${loopback}`: first IPv4 address of a loopback interface
+/// - `${host}`: first IPv4 address of an up, non-loopback interface
+///
+/// `$${` is a literal `${`. The `[vars]` table itself is left in place so
+/// parse errors keep pointing at the right line.
+pub fn interpolate(doc: &mut DocumentMut) -> Result<Resolved> {
+    let mut raw = HashMap::new();
+    let mut order = Vec::new();
+    if let Some(item) = doc.get("vars") {
+        let table = item
+            .as_table_like()
+            .ok_or_else(|| anyhow!("vars: expected a table"))?;
+        for (k, v) in table.iter() {
+            let s = match v.as_value() {
+                Some(Value::String(s)) => s.value().clone(),
+                Some(Value::Integer(i)) => i.value().to_string(),
+                Some(Value::Float(f)) => f.value().to_string(),
+                Some(Value::Boolean(b)) => b.value().to_string(),
+                _ => bail!("vars.{k}: expected a string, number or boolean"),
+            };
+            raw.insert(k.to_string(), s);
+            order.push(k.to_string());
+        }
+    }
+    let mut ctx = Context {
+        raw,
+        done: HashMap::new(),
+        active: Vec::new(),
+        ifaces: None,
+    };
+    let mut out = Resolved::default();
+    for k in &order {
+        let v = ctx.var(k).map_err(|e| anyhow!("vars.{k}: {e}"))?;
+        out.vars.push((k.clone(), v));
+    }
+    for (key, item) in doc.iter_mut() {
+        if key.get() == "vars" {
+            continue;
+        }
+        walk_item(&mut ctx, key.get(), item, &mut out.substitutions)?;
+    }
+    Ok(out)
+}
+
+fn walk_item(
+    ctx: &mut Context,
+    path: &str,
+    item: &mut Item,
+    subs: &mut Vec<Substitution>,
+) -> Result<()> {
+    match item {
+        Item::Value(v) => walk_value(ctx, path, v, subs),
+        Item::Table(t) => {
+            for (k, it) in t.iter_mut() {
+                walk_item(ctx, &format!("{path}.{}", k.get()), it, subs)?;
+            }
+            Ok(())
+        }
+        Item::ArrayOfTables(a) => {
+            for (i, t) in a.iter_mut().enumerate() {
+                for (k, it) in t.iter_mut() {
+                    walk_item(ctx, &format!("{path}[{i}].{}", k.get()), it, subs)?;
+                }
+            }
+            Ok(())
+        }
+        Item::None => Ok(()),
+    }
+}
+
+fn walk_value(
+    ctx: &mut Context,
+    path: &str,
+    v: &mut Value,
+    subs: &mut Vec<Substitution>,
+) -> Result<()> {
+    match v {
+        Value::String(s) => {
+            let from = s.value().clone();
+            if !from.contains('$') {
+                return Ok(());
+            }
+            let to = ctx.expand(&from).map_err(|e| anyhow!("{path}: {e}"))?;
+            if to != from {
+                let decor = s.decor().clone();
+                let mut new = toml_edit::Formatted::new(to.clone());
+                *new.decor_mut() = decor;
+                *s = new;
+                subs.push(Substitution {
+                    key: path.to_string(),
+                    from,
+                    to,
+                });
+            }
+            Ok(())
+        }
+        Value::Array(a) => {
+            for (i, v) in a.iter_mut().enumerate() {
+                walk_value(ctx, &format!("{path}[{i}]"), v, subs)?;
+            }
+            Ok(())
+        }
+        Value::InlineTable(t) => {
+            for (k, v) in t.iter_mut() {
+                walk_value(ctx, &format!("{path}.{}", k.get()), v, subs)?;
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+struct Context {
+    raw: HashMap<String, String>,
+    done: HashMap<String, String>,
+    /// Vars being expanded, to report reference cycles.
+    active: Vec<String>,
+    ifaces: Option<Vec<Iface>>,
+}
+
+impl Context {
+    fn var(&mut self, name: &str) -> Result<String> {
+        if let Some(v) = self.done.get(name) {
+            return Ok(v.clone());
+        }
+        let Some(raw) = self.raw.get(name).cloned() else {
+            bail!("unknown variable ${{{name}}} (not in [vars])");
+        };
+        if self.active.iter().any(|a| a == name) {
+            bail!("variable cycle: {} -> {name}", self.active.join(" -> "));
+        }
+        self.active.push(name.to_string());
+        let v = self.expand(&raw);
+        self.active.pop();
+        let v = v?;
+        self.done.insert(name.to_string(), v.clone());
+        Ok(v)
+    }
+
+    fn expand(&mut self, s: &str) -> Result<String> {
+        let mut out = String::with_capacity(s.len());
+        let mut rest = s;
+        while let Some(i) = rest.find('$') {
+            out.push_str(&rest[..i]);
+            rest = &rest[i..];
+            if let Some(r) = rest.strip_prefix("$${") {
+                out.push_str("${");
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix("${") {
+                let end = closing_brace(r).ok_or_else(|| anyhow!("unterminated ${{ in {s:?}"))?;
+                out.push_str(&self.lookup(&r[..end])?);
+                rest = &r[end + 1..];
+            } else {
+                out.push('$');
+                rest = &rest[1..];
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+
+    fn lookup(&mut self, expr: &str) -> Result<String> {
+        if let Some(e) = expr.strip_prefix("env:") {
+            let (name, default) = match e.split_once(":-") {
+                Some((n, d)) => (n, Some(d)),
+                None => (e, None),
+            };
+            return match (std::env::var(name), default) {
+                (Ok(v), Some(_)) if !v.is_empty() => Ok(v),
+                (Ok(v), None) => Ok(v),
+                (_, Some(d)) => self.expand(d),
+                (Err(_), None) => bail!("environment variable {name} is not set"),
+            };
+        }
+        if let Some(name) = expr.strip_prefix("iface:") {
+            let ifaces = self.ifaces();
+            return ifaces
+                .iter()
+                .find(|i| i.name == name)
+                .map(|i| i.addr.to_string())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no IPv4 address on interface {name:?} (have: {})",
+                        describe(ifaces)
+                    )
+                });
+        }
+        match expr {
+            "loopback" => Ok(self
+                .ifaces()
+                .iter()
+                .find(|i| i.loopback)
+                .map_or(Ipv4Addr::LOCALHOST, |i| i.addr)
+                .to_string()),
+            "host" => host_ipv4_in(self.ifaces())
+                .map(|ip| ip.to_string())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no up, non-loopback IPv4 interface (have: {})",
+                        describe(self.ifaces())
+                    )
+                }),
+            name => self.var(name),
+        }
+    }
+
+    fn ifaces(&mut self) -> &[Iface] {
+        self.ifaces.get_or_insert_with(interfaces)
+    }
+}
+
+/// Index of the `}` closing a `${`, skipping nested references such as
+/// `${env:HOST:-${loopback}}`.
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' if s[..i].ends_with('$') => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// An IPv4 address of a local interface.
+#[derive(Debug, Clone)]
+struct Iface {
+    name: String,
+    addr: Ipv4Addr,
+    up: bool,
+    loopback: bool,
+}
+
+fn describe(ifaces: &[Iface]) -> String {
+    if ifaces.is_empty() {
+        return "none".into();
+    }
+    let v: Vec<String> = ifaces
+        .iter()
+        .map(|i| format!("{}={}", i.name, i.addr))
+        .collect();
+    v.join(", ")
+}
+
+fn host_ipv4_in(ifaces: &[Iface]) -> Option<Ipv4Addr> {
+    ifaces.iter().find(|i| i.up && !i.loopback).map(|i| i.addr)
+}
+
+/// First IPv4 address of an up, non-loopback interface.
+pub fn host_ipv4() -> Option<Ipv4Addr> {
+    host_ipv4_in(&interfaces())
+}
+
+/// IPv4 interface addresses from `getifaddrs`, in kernel order.
+fn interfaces() -> Vec<Iface> {
+    let mut out = Vec::new();
+    let mut head: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut head) } != 0 {
+        return out;
+    }
+    let mut cur = head;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null()
+            || i32::from(unsafe { (*ifa.ifa_addr).sa_family }) != libc::AF_INET
+        {
+            continue;
+        }
+        let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+        let flags = ifa.ifa_flags;
+        out.push(Iface {
+            name: unsafe { CStr::from_ptr(ifa.ifa_name) }
+                .to_string_lossy()
+                .into_owned(),
+            addr: Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)),
+            up: flags & libc::IFF_UP as u32 != 0,
+            loopback: flags & libc::IFF_LOOPBACK as u32 != 0,
+        });
+    }
+    unsafe { libc::freeifaddrs(head) };
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(vars: &[(&str, &str)]) -> Context {
+        Context {
+            raw: vars
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            done: HashMap::new(),
+            active: Vec::new(),
+            ifaces: Some(vec![
+                Iface {
+                    name: "lo".into(),
+                    addr: Ipv4Addr::new(127, 0, 0, 1),
+                    up: true,
+                    loopback: true,
+                },
+                Iface {
+                    name: "eth0".into(),
+                    addr: Ipv4Addr::new(10, 0, 0, 5),
+                    up: false,
+                    loopback: false,
+                },
+                Iface {
+                    name: "eth1".into(),
+                    addr: Ipv4Addr::new(192, 168, 1, 9),
+                    up: true,
+                    loopback: false,
+                },
+            ]),
+        }
+    }
+
+    #[test]
+    fn vars_expand_through_each_other() {
+        let mut c = ctx(&[("port", "5062"), ("bind", "${loopback}:${port}")]);
+        assert_eq!(
+            c.expand("sip:${bind};x=1").unwrap(),
+            "sip:127.0.0.1:5062;x=1"
+        );
+        assert_eq!(
+            c.expand("${iface:eth0} ${host}").unwrap(),
+            "10.0.0.5 192.168.1.9"
+        );
+    }
+
+    #[test]
+    fn dollars_that_are_not_references_are_kept() {
+        let mut c = ctx(&[]);
+        assert_eq!(
+            c.expand("$${port} costs $5 $").unwrap(),
+            "${port} costs $5 $"
+        );
+        assert_eq!(c.expand("no refs").unwrap(), "no refs");
+    }
+
+    #[test]
+    fn unknown_unterminated_and_cyclic_references_fail() {
+        let mut c = ctx(&[("a", "${b}"), ("b", "x${a}")]);
+        let e = c.expand("${nope}").unwrap_err().to_string();
+        assert_eq!(e, "unknown variable ${nope} (not in [vars])");
+        let e = c.expand("sip:${a").unwrap_err().to_string();
+        assert!(e.starts_with("unterminated ${"), "{e}");
+        let e = c.var("a").unwrap_err().to_string();
+        assert_eq!(e, "variable cycle: a -> b -> a");
+        let e = c.expand("${iface:wlan9}").unwrap_err().to_string();
+        assert!(e.contains("have: lo=127.0.0.1, eth0=10.0.0.5"), "{e}");
+    }
+
+    #[test]
+    fn env_references_use_shell_default_rules() {
+        let set = "B2B_VARS_TEST_SET";
+        let empty = "B2B_VARS_TEST_EMPTY";
+        let unset = "B2B_VARS_TEST_UNSET";
+        std::env::set_var(set, "10.9.8.7");
+        std::env::set_var(empty, "");
+        std::env::remove_var(unset);
+        let mut c = ctx(&[("port", "5070")]);
+        assert_eq!(c.expand(&format!("${{env:{set}:-x}}")).unwrap(), "10.9.8.7");
+        assert_eq!(c.expand(&format!("${{env:{empty}}}")).unwrap(), "");
+        assert_eq!(
+            c.expand(&format!("${{env:{empty}:-${{loopback}}}}:${{port}}"))
+                .unwrap(),
+            "127.0.0.1:5070"
+        );
+        assert_eq!(c.expand(&format!("${{env:{unset}:-}}")).unwrap(), "");
+        let e = c
+            .expand(&format!("${{env:{unset}}}"))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(e, format!("environment variable {unset} is not set"));
+    }
+
+    #[test]
+    fn closing_brace_skips_nested_references() {
+        assert_eq!(closing_brace("env:A:-${loopback}}:1"), Some(18));
+        assert_eq!(closing_brace("a{b}"), Some(3));
+        assert_eq!(closing_brace("env:A:-${x"), None);
+    }
+
+    #[test]
+    fn interpolate_rewrites_strings_and_records_them() {
+        let mut doc: DocumentMut = "[vars]\nport = 5062\nsink = \"sip:127.0.0.1:${port}\"\n\n\
+             [[role]]\nname = \"src\"\nsip_target = \"${sink}\"  # dial the sink\n\
+             note = \"$${literal}\"\nlist = [\"${port}\", 1]\n"
+            .parse()
+            .unwrap();
+        let r = interpolate(&mut doc).unwrap();
+        assert_eq!(
+            r.vars,
+            [
+                ("port".to_string(), "5062".to_string()),
+                ("sink".to_string(), "sip:127.0.0.1:5062".to_string()),
+            ]
+        );
+        let keys: Vec<&str> = r.substitutions.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            ["role[0].sip_target", "role[0].note", "role[0].list[0]"]
+        );
+        let text = doc.to_string();
+        assert!(
+            text.contains("sip_target = \"sip:127.0.0.1:5062\"  # dial the sink"),
+            "{text}"
+        );
+        assert!(text.contains("note = \"${literal}\""), "{text}");
+        // [vars] stays as written so error positions do not move.
+        assert!(text.contains("sink = \"sip:127.0.0.1:${port}\""), "{text}");
+    }
+
+    #[test]
+    fn interpolate_names_the_failing_key() {
+        let mut doc: DocumentMut = "[[role]]\nname = \"a\"\nsip_bind = \"${nope}:5062\"\n"
+            .parse()
+            .unwrap();
+        let e = interpolate(&mut doc).unwrap_err().to_string();
+        assert_eq!(
+            e,
+            "role[0].sip_bind: unknown variable ${nope} (not in [vars])"
+        );
+    }
+}
@@ -0,0 +1,503 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+
+/// Extensions picked up when a directory is given; glob patterns take
+/// whatever they match.
+const AUDIO_EXTS: [&str; 11] = [
+    "wav", "wave", "flac", "ogg", "oga", "mp3", "mka", "mkv", "m4a", "aac", "mp4",
+];
+
+pub struct Item {
+    pub path: PathBuf,
+    /// `#EXTINF` title from an M3U playlist.
+    pub title: Option<String>,
+}
+
+/// What the source plays, in file order or shuffled per pass.
+pub struct Playlist {
+    items: Vec<Item>,
+    shuffle: Option<Rng>,
+    last: Option<usize>,
+}
+
+impl Playlist {
+    pub fn single(path: &Path) -> Self {
+        Self::new(vec![Item {
+            path: path.to_path_buf(),
+            title: None,
+        }])
+    }
+
+    /// An M3U/M3U8 file, a directory (its audio files, recursively, sorted
+    /// by path) or a glob pattern (`*`, `?`, `[a-z]`, and `**` for any
+    /// number of directories). Any other file is a one-item playlist.
+    pub fn load(spec: &str) -> Result<Self> {
+        let path = Path::new(spec);
+        let items = if has_wildcards(spec) {
+            glob(spec)?
+        } else if path.is_dir() {
+            let mut files = Vec::new();
+            list_dir(path, &mut files).with_context(|| format!("reading {spec}"))?;
+            files.sort();
+            files
+                .into_iter()
+                .map(|path| Item { path, title: None })
+                .collect()
+        } else if is_m3u(path) {
+            m3u(path)?
+        } else if path.is_file() {
+            return Ok(Self::single(path));
+        } else {
+            bail!("{spec}: no such playlist, directory or file");
+        };
+        if items.is_empty() {
+            bail!("{spec}: playlist is empty");
+        }
+        Ok(Self::new(items))
+    }
+
+    fn new(items: Vec<Item>) -> Self {
+        Self {
+            items,
+            shuffle: None,
+            last: None,
+        }
+    }
+
+    /// Play in a random order, drawn again for every pass. The same seed
+    /// gives the same sequence.
+    pub fn shuffle(&mut self, seed: u64) {
+        self.shuffle = Some(Rng(seed));
+    }
+
+    pub fn items(&self) -> &[Item] {
+        &self.items
+    }
+
+    /// Item indices in play order for the next pass through the list.
+    pub fn next_pass(&mut self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.items.len()).collect();
+        if let Some(rng) = &mut self.shuffle {
+            for i in (1..order.len()).rev() {
+                order.swap(i, rng.below(i + 1));
+            }
+            // Do not play the same item twice in a row across passes.
+            if order.len() > 1 && order.first() == self.last.as_ref() {
+                order.swap(0, 1);
+            }
+        }
+        self.last = order.last().copied();
+        order
+    }
+}
+
+/// splitmix64: tiny and seedable, plenty for ordering prompts.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+fn is_m3u(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("m3u") || e.eq_ignore_ascii_case("m3u8"))
+}
+
+/// Entries of an M3U file, relative to the file's directory. `#EXTINF`
+/// titles are kept; other comments are skipped.
+fn m3u(path: &Path) -> Result<Vec<Item>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading playlist {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut items = Vec::new();
+    let mut title = None;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim_start_matches('\u{feff}').trim();
+        if let Some(info) = line.strip_prefix("#EXTINF:") {
+            title = info
+                .split_once(',')
+                .map(|(_, t)| t.trim().to_string())
+                .filter(|t| !t.is_empty());
+            continue;
+        }
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let entry = line.strip_prefix("file://").unwrap_or(line);
+        if entry.contains("://") {
+            bail!(
+                "{}:{}: {entry}: only local files are supported",
+                path.display(),
+                n + 1
+            );
+        }
+        items.push(Item {
+            path: dir.join(entry),
+            title: title.take(),
+        });
+    }
+    Ok(items)
+}
+
+fn list_dir(dir: &Path, out: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if is_hidden(&path) {
+            continue;
+        }
+        if path.is_dir() {
+            list_dir(&path, out)?;
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| AUDIO_EXTS.iter().any(|a| e.eq_ignore_ascii_case(a)))
+        {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Files matching `pattern`, sorted. Leading components without wildcards
+/// name the directory the search starts from.
+fn glob(pattern: &str) -> Result<Vec<Item>> {
+    let mut base = PathBuf::new();
+    let mut parts: Vec<String> = Vec::new();
+    for c in Path::new(pattern).components() {
+        let s = c.as_os_str().to_string_lossy();
+        if parts.is_empty() && !has_wildcards(&s) {
+            base.push(c);
+        } else {
+            parts.push(s.into_owned());
+        }
+    }
+    let mut files = Vec::new();
+    if base.as_os_str().is_empty() {
+        walk(Path::new("."), &parts, &mut files);
+        for f in &mut files {
+            *f = f.strip_prefix(".").unwrap_or(f).to_path_buf();
+        }
+    } else {
+        walk(&base, &parts, &mut files);
+    }
+    files.sort();
+    files.dedup();
+    if files.is_empty() {
+        bail!("{pattern}: no files match");
+    }
+    Ok(files
+        .into_iter()
+        .map(|path| Item { path, title: None })
+        .collect())
+}
+
+fn walk(dir: &Path, parts: &[String], out: &mut Vec<PathBuf>) {
+    let Some((first, rest)) = parts.split_first() else {
+        return;
+    };
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let paths = entries.flatten().map(|e| e.path());
+    if first == "**" {
+        walk(dir, rest, out);
+        for sub in paths.filter(|p| p.is_dir() && !is_hidden(p)) {
+            walk(&sub, parts, out);
+        }
+        return;
+    }
+    let pat: Vec<char> = first.chars().collect();
+    for path in paths {
+        let name: Vec<char> = match path.file_name() {
+            Some(n) => n.to_string_lossy().chars().collect(),
+            None => continue,
+        };
+        // Like the shell, `*` does not match a leading dot.
+        if name.first() == Some(&'.') && pat.first() != Some(&'.') {
+            continue;
+        }
+        if !matches(&pat, &name) {
+            continue;
+        }
+        if rest.is_empty() {
+            if path.is_file() {
+                out.push(path);
+            }
+        } else if path.is_dir() {
+            walk(&path, rest, out);
+        }
+    }
+}
+
+/// Shell-style match of one path component: `*`, `?`, `[abc]`, `[a-z]`,
+/// `[!abc]`.
+fn matches(pat: &[char], name: &[char]) -> bool {
+    match pat.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| matches(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+        Some(('[', rest)) if rest.contains(&']') => {
+            let Some((&c, tail)) = name.split_first() else {
+                return false;
+            };
+            let close = rest.iter().position(|&p| p == ']').unwrap_or_default();
+            let (set, negate) = match rest[..close].split_first() {
+                Some(('!' | '^', set)) => (set, true),
+                _ => (&rest[..close], false),
+            };
+            let mut hit = false;
+            let mut i = 0;
+            while i < set.len() {
+                if i + 2 < set.len() && set[i + 1] == '-' {
+                    hit |= (set[i]..=set[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    hit |= set[i] == c;
+                    i += 1;
+                }
+            }
+            hit != negate && matches(&rest[close + 1..], tail)
+        }
+        Some((p, rest)) => name.first() == Some(p) && matches(rest, &name[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Removes a test's directory when dropped.
+    struct TempDir(PathBuf);
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// A fresh directory holding `files` (parents created, contents empty
+    /// unless given).
+    fn tree(test: &str, files: &[(&str, &str)]) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("b2b-playlist-{}-{test}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (name, text) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, text).unwrap();
+        }
+        TempDir(dir)
+    }
+
+    fn m(pat: &str, name: &str) -> bool {
+        let pat: Vec<char> = pat.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        matches(&pat, &name)
+    }
+
+    /// Paths of `items` relative to `dir`.
+    fn rel(dir: &TempDir, items: &[Item]) -> Vec<String> {
+        items
+            .iter()
+            .map(|i| i.path.strip_prefix(&dir.0).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn component_matching() {
+        assert!(m("*", "a.wav"));
+        assert!(m("*.wav", "a.wav"));
+        assert!(m("*.wav", ".wav"));
+        assert!(!m("*.wav", "a.wav.bak"));
+        assert!(m("a*b*c", "aXbYc"));
+        assert!(m("a*b*c", "abc"));
+        assert!(!m("a*b*c", "acb"));
+        assert!(m("?.wav", "1.wav"));
+        assert!(!m("?.wav", "12.wav"));
+        assert!(!m("?", ""));
+        assert!(m("[a-c]x", "bx"));
+        assert!(!m("[a-c]x", "dx"));
+        assert!(m("[xa-cz]", "z"));
+        assert!(m("[!a-c]x", "dx"));
+        assert!(m("[^a-c]x", "dx"));
+        assert!(!m("[!a-c]x", "ax"));
+        assert!(!m("[ab]", ""));
+        // An unclosed bracket is a literal.
+        assert!(m("[ab", "[ab"));
+        assert!(m("é?", "éa"));
+    }
+
+    #[test]
+    fn glob_walks_directories() {
+        let dir = tree(
+            "glob",
+            &[
+                ("a/1.wav", ""),
+                ("a/2.WAV", ""),
+                ("a/x.mp3", ""),
+                ("a/y.m4a", ""),
+                ("a/notes.txt", ""),
+                ("a/.3.wav", ""),
+                ("a/b/4.wav", ""),
+                ("a/b/c/5.wav", ""),
+                ("a/.hidden/6.wav", ""),
+            ],
+        );
+        let base = dir.0.display().to_string();
+        let load = |pat: &str| {
+            rel(
+                &dir,
+                Playlist::load(&format!("{base}/{pat}")).unwrap().items(),
+            )
+        };
+        assert_eq!(load("a/*.wav"), ["a/1.wav"]);
+        assert_eq!(load("a/[0-9].*"), ["a/1.wav", "a/2.WAV"]);
+        assert_eq!(load("a/[!0-9]*"), ["a/notes.txt", "a/x.mp3", "a/y.m4a"]);
+        assert_eq!(load("a/.*.wav"), ["a/.3.wav"]);
+        assert_eq!(load("?/*/?.wav"), ["a/b/4.wav"]);
+        assert_eq!(load("**/*.wav"), ["a/1.wav", "a/b/4.wav", "a/b/c/5.wav"]);
+        assert_eq!(load("a/**/c/*"), ["a/b/c/5.wav"]);
+        let err = Playlist::load(&format!("{base}/*.flac")).err().unwrap();
+        assert!(err.to_string().ends_with("*.flac: no files match"), "{err}");
+
+        // A directory lists audio files at any depth, hidden ones and
+        // other files left out.
+        assert_eq!(
+            rel(&dir, Playlist::load(&base).unwrap().items()),
+            [
+                "a/1.wav",
+                "a/2.WAV",
+                "a/b/4.wav",
+                "a/b/c/5.wav",
+                "a/x.mp3",
+                "a/y.m4a"
+            ]
+        );
+    }
+
+    #[test]
+    fn m3u_entries_titles_and_paths() {
+        let dir = tree(
+            "m3u",
+            &[(
+                "lists/p.m3u8",
+                "\u{feff}#EXTM3U\n\
+                 #EXTINF:12,Welcome prompt\n\
+                 welcome.wav\n\
+                 \n\
+                 # a comment\n\
+                 sub/menu.wav\n\
+                 #EXTINF:-1,\n\
+                 /abs/bye.wav\n\
+                 #EXTINF:3, Spaced title \n\
+                 file:///abs/last.wav\n",
+            )],
+        );
+        let list = Playlist::load(&dir.0.join("lists/p.m3u8").display().to_string()).unwrap();
+        let got: Vec<(String, Option<&str>)> = list
+            .items()
+            .iter()
+            .map(|i| (i.path.display().to_string(), i.title.as_deref()))
+            .collect();
+        let lists = dir.0.join("lists");
+        assert_eq!(
+            got,
+            [
+                (
+                    lists.join("welcome.wav").display().to_string(),
+                    Some("Welcome prompt")
+                ),
+                (lists.join("sub/menu.wav").display().to_string(), None),
+                ("/abs/bye.wav".to_string(), None),
+                ("/abs/last.wav".to_string(), Some("Spaced title")),
+            ]
+        );
+    }
+
+    #[test]
+    fn m3u_rejects_urls_and_empty_lists() {
+        let dir = tree(
+            "m3u-bad",
+            &[
+                ("net.m3u", "a.wav\nhttp://example.com/b.mp3\n"),
+                ("empty.M3U", "#EXTM3U\n# nothing\n"),
+            ],
+        );
+        let err = Playlist::load(&dir.0.join("net.m3u").display().to_string())
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .ends_with("net.m3u:2: http://example.com/b.mp3: only local files are supported"),
+            "{err}"
+        );
+        let err = Playlist::load(&dir.0.join("empty.M3U").display().to_string())
+            .err()
+            .unwrap();
+        assert!(err.to_string().ends_with("playlist is empty"), "{err}");
+    }
+
+    fn list(n: usize) -> Playlist {
+        Playlist::new(
+            (0..n)
+                .map(|i| Item {
+                    path: PathBuf::from(format!("{i}.wav")),
+                    title: None,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn passes_keep_file_order_without_shuffle() {
+        let mut p = list(4);
+        assert_eq!(p.next_pass(), [0, 1, 2, 3]);
+        assert_eq!(p.next_pass(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn shuffle_is_seeded_and_never_repeats_across_passes() {
+        let passes = |seed: u64, n: usize| {
+            let mut p = list(n);
+            p.shuffle(seed);
+            (0..200).map(|_| p.next_pass()).collect::<Vec<_>>()
+        };
+        assert_eq!(passes(7, 5), passes(7, 5));
+        assert_ne!(passes(7, 5), passes(8, 5));
+        for n in [2, 3, 5] {
+            let runs = passes(42, n);
+            for order in &runs {
+                let mut sorted = order.clone();
+                sorted.sort();
+                assert_eq!(sorted, (0..n).collect::<Vec<_>>());
+            }
+            for w in runs.windows(2) {
+                assert_ne!(w[0].last(), w[1].first(), "{n} items: {w:?}");
+            }
+        }
+        // More than one order actually comes up.
+        let runs = passes(42, 5);
+        assert!(runs.iter().any(|o| o != &runs[0]));
+        // One item has nothing to shuffle.
+        assert_eq!(passes(1, 1)[0], [0]);
+    }
+}
@@ -1,5 +1,6 @@
-use crate::{cli::Cli, logging, sip::UaHandle, sip_shim};
+use crate::{cli::Cli, error::B2bError, logging, sip::UaHandle, sip_shim};
 use anyhow::{Context, Result};
+use serde_json::json;
 
 pub fn run(args: &Cli) -> Result<()> {
     let tag = logging::role_tag("mixer");
@@ -9,13 +10,14 @@ pub fn run(args: &Cli) -> Result<()> {
     let target = args
         .target
         .as_deref()
-        .context("mixer requires --target (sink)")?;
+        .context(B2bError::Usage("mixer requires --target (sink)".into()))?;
     let sink_label = target.strip_prefix("sip:").unwrap_or(target).to_string();
 
     // Preload config before UA init: codec modules + sip_listen so inbound leg actually listens
     let preload = format!(
         "module\t\tg711\n\
          module\t\tl16\n\
+         module\t\tg722\n\
          sip_listen\t{}\n\
          call_accept\tyes\n\
          audio_player\tb2b_mix,inbound\n",
@@ -25,7 +27,7 @@ pub fn run(args: &Cli) -> Result<()> {
         std::env::set_var("BRS_CONF_BUF", preload);
     }
     // Initialize baresip core/UA first (shared reactor/event bus)
-    let ua = UaHandle::init().context("init UA")?;
+    let ua = UaHandle::init().context(B2bError::Sip("init UA".into()))?;
     let (_bridge_ev, rx_ev) = ua.reactor.register_events();
     let (ready_tx, ready_rx) = std::sync::mpsc::channel::<std::result::Result<(), String>>();
     // Background: clear, leg-labelled logs for sink vs source
@@ -40,26 +42,56 @@ pub fn run(args: &Cli) -> Result<()> {
                 let text = ev.text.clone().unwrap_or_default();
                 let is_sink_leg = !sink_label.is_empty() && text.contains(&sink_label);
                 if kind.contains("CALL_INCOMING") {
-                    logging::println_tag(&tag, &format!("source: incoming {}", text));
+                    logging::event(
+                        &tag,
+                        "sip",
+                        &format!("source: incoming {}", text),
+                        json!({ "leg": "source", "state": "incoming", "text": text }),
+                    );
                 } else if kind.contains("CALL_LOCAL_SDP") {
                     let side = if is_sink_leg { "sink" } else { "source" };
-                    logging::println_tag(&tag, &format!("{}: sdp answer", side));
+                    logging::event(
+                        &tag,
+                        "sip",
+                        &format!("{}: sdp answer", side),
+                        json!({ "leg": side, "state": "local_sdp" }),
+                    );
                 } else if kind.contains("CALL_REMOTE_SDP") {
                     let side = if is_sink_leg { "sink" } else { "source" };
-                    logging::println_tag(&tag, &format!("{}: sdp offer", side));
+                    logging::event(
+                        &tag,
+                        "sip",
+                        &format!("{}: sdp offer", side),
+                        json!({ "leg": side, "state": "remote_sdp" }),
+                    );
                 } else if kind.contains("CALL_ESTABLISHED") {
                     let side = if is_sink_leg { "sink" } else { "source" };
-                    logging::println_tag(&tag, &format!("{}: established {}", side, text));
+                    logging::event(
+                        &tag,
+                        "sip",
+                        &format!("{}: established {}", side, text),
+                        json!({ "leg": side, "state": "established", "text": text }),
+                    );
                     if is_sink_leg && !sink_reported {
                         sink_reported = true;
                         let _ = ready_tx.send(Ok(()));
                     }
                 } else if kind.contains("CALL_RTPESTAB") {
                     let side = if is_sink_leg { "sink" } else { "source" };
-                    logging::println_tag(&tag, &format!("{}: rtp established", side));
+                    logging::event(
+                        &tag,
+                        "sip",
+                        &format!("{}: rtp established", side),
+                        json!({ "leg": side, "state": "rtp_established" }),
+                    );
                 } else if kind.contains("CALL_CLOSED") {
                     let side = if is_sink_leg { "sink" } else { "source" };
-                    logging::println_tag(&tag, &format!("{}: closed {}", side, text));
+                    logging::event(
+                        &tag,
+                        "sip",
+                        &format!("{}: closed {}", side, text),
+                        json!({ "leg": side, "state": "closed", "text": text }),
+                    );
                     if is_sink_leg {
                         let _ = ready_tx.send(Err(text.clone()));
                     }
@@ -80,7 +112,8 @@ pub fn run(args: &Cli) -> Result<()> {
     }
 
     // Start mixer legs: inbound catch-all and outbound dial
-    sip_shim::mixer_init(sip, target, 8000, 1, args.ptime_ms)?;
+    let codec = super::select_codec(args)?;
+    sip_shim::mixer_init(sip, target, args.sample_rate.hz(), 1, args.ptime_ms)?;
     // Configure mixing: dtmf sequence and gains
     sip_shim::mixer_config(
         &args.dtmf_seq,
@@ -100,22 +133,29 @@ pub fn run(args: &Cli) -> Result<()> {
             .checked_sub(wait_start.elapsed())
             .unwrap_or_default();
         if remaining.is_zero() {
-            anyhow::bail!("mixer: timeout waiting for sink leg to establish");
+            anyhow::bail!(B2bError::Timeout(
+                "mixer: timeout waiting for sink leg to establish".into()
+            ));
         }
         match ready_rx.recv_timeout(remaining) {
             Ok(Ok(())) => break,
             Ok(Err(reason)) => {
-                anyhow::bail!("mixer: sink leg closed before ready ({})", reason);
+                anyhow::bail!(B2bError::Sip(format!(
+                    "mixer: sink leg closed before ready ({})",
+                    reason
+                )));
             }
             Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
-            Err(_) => anyhow::bail!("mixer: lost event channel while waiting for sink leg"),
+            Err(_) => anyhow::bail!(B2bError::Sip(
+                "mixer: lost event channel while waiting for sink leg".into()
+            )),
         }
     }
-    logging::ready_line("mixer", sip, &args.codec, args.ptime_ms);
+    logging::ready_line("mixer", sip, codec, args.ptime_ms);
 
     // Keep process alive until Ctrl+C
     wait_for_ctrl_c();
-    logging::println_tag(&tag, "Ctrl+C received; shutting down");
+    logging::println_tag(&tag, "SIGINT/SIGTERM received; shutting down");
 
     let _ = sip_shim::mixer_shutdown();
     let _ = ua.reactor.shutdown();
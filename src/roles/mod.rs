@@ -1,3 +1,130 @@
 pub mod mixer;
 pub mod sink;
 pub mod source;
+
+use crate::{
+    cli::{Cli, SampleRate},
+    error::B2bError,
+    sip_shim,
+};
+use anyhow::Result;
+
+/// Largest RTP payload that fits a 1500-byte MTU after the IPv4, UDP and
+/// RTP headers.
+const MAX_PAYLOAD: u32 = 1500 - 20 - 8 - 12;
+
+/// Limit the UAs to `--codec`, or the codec matching `--sample-rate`, and
+/// return it. Call before the shim allocates its UAs.
+fn select_codec(args: &Cli) -> Result<&str> {
+    let codec = check_codec(args.codec.as_deref(), args.sample_rate, args.ptime_ms)?;
+    sip_shim::set_audio_codecs(codec)?;
+    Ok(codec)
+}
+
+/// `codec` (default: the one matching `rate`) if every codec it lists
+/// carries `rate` audio unchanged and a `ptime_ms` packet of it fits the
+/// MTU. The shims cannot resample between codec and audio device. The
+/// orchestrator checks this too, before any role starts.
+pub(crate) fn check_codec(
+    codec: Option<&str>,
+    rate: SampleRate,
+    ptime_ms: u32,
+) -> Result<&str, B2bError> {
+    let codec = codec.unwrap_or(rate.codec());
+    if ptime_ms == 0 {
+        return Err(B2bError::Usage("--ptime-ms must be at least 1".into()));
+    }
+    for entry in codec.split(',').map(str::trim) {
+        let mut parts = entry.split('/');
+        let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+        // Audio rate in Hz and payload bits per sample.
+        let (hz, bits) = match name.as_str() {
+            "pcmu" | "pcma" => (8000, 8),
+            "g722" => (16000, 4),
+            "l16" => {
+                let hz = parts.next().and_then(|r| r.parse::<u32>().ok());
+                let mono = parts.next().is_none_or(|c| c == "1");
+                match hz {
+                    Some(hz) if mono => (hz, 16),
+                    _ => {
+                        return Err(B2bError::Usage(format!(
+                            "codec {entry}: expected l16/RATE or l16/RATE/1"
+                        )));
+                    }
+                }
+            }
+            _ => {
+                return Err(B2bError::Usage(format!(
+                    "codec {entry}: unknown codec (pcmu, pcma, g722 or l16/RATE/1)"
+                )));
+            }
+        };
+        if hz != rate.hz() {
+            return Err(B2bError::Usage(format!(
+                "codec {entry} carries {hz} Hz audio, not --sample-rate {} Hz; use {}",
+                rate.hz(),
+                rate.codec()
+            )));
+        }
+        let payload = hz * ptime_ms / 1000 * bits / 8;
+        if payload > MAX_PAYLOAD {
+            let max_ms = MAX_PAYLOAD * 1000 / (hz * bits / 8);
+            return Err(B2bError::Usage(format!(
+                "codec {entry} at --ptime-ms {ptime_ms} sends {payload}-byte packets, over \
+                 the {MAX_PAYLOAD}-byte limit of a 1500-byte MTU; use --ptime-ms {max_ms} or less"
+            )));
+        }
+    }
+    Ok(codec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_codec_follows_the_sample_rate() {
+        for (rate, codec) in [
+            (SampleRate::Narrow, "pcmu"),
+            (SampleRate::Wide, "g722"),
+            (SampleRate::Full, "l16/48000/1"),
+        ] {
+            let ptime = if rate == SampleRate::Full { 10 } else { 20 };
+            assert_eq!(check_codec(None, rate, ptime).unwrap(), codec);
+        }
+        assert_eq!(
+            check_codec(Some("PCMA, pcmu"), SampleRate::Narrow, 20).unwrap(),
+            "PCMA, pcmu"
+        );
+        assert!(check_codec(Some("l16/16000"), SampleRate::Wide, 20).is_ok());
+    }
+
+    #[test]
+    fn codec_must_carry_the_sample_rate() {
+        let err = |codec, rate| check_codec(Some(codec), rate, 20).unwrap_err().to_string();
+        assert_eq!(
+            err("pcmu", SampleRate::Wide),
+            "codec pcmu carries 8000 Hz audio, not --sample-rate 16000 Hz; use g722"
+        );
+        assert!(err("pcmu,g722", SampleRate::Narrow).contains("codec g722 carries 16000 Hz"));
+        assert!(err("l16/8000", SampleRate::Wide).contains("carries 8000 Hz"));
+        assert!(err("l16", SampleRate::Full).contains("expected l16/RATE"));
+        assert!(err("l16/48000/2", SampleRate::Full).contains("expected l16/RATE"));
+        assert!(err("opus/48000/2", SampleRate::Full).contains("unknown codec"));
+    }
+
+    #[test]
+    fn packets_must_fit_the_mtu() {
+        // 48 kHz L16 is 96 bytes per ms: 1440 at 15 ms fits, 1920 at 20 does not.
+        assert!(check_codec(None, SampleRate::Full, 15).is_ok());
+        let e = check_codec(None, SampleRate::Full, 20).unwrap_err();
+        assert!(matches!(e, B2bError::Usage(_)));
+        assert_eq!(
+            e.to_string(),
+            "codec l16/48000/1 at --ptime-ms 20 sends 1920-byte packets, over the \
+             1460-byte limit of a 1500-byte MTU; use --ptime-ms 15 or less"
+        );
+        assert!(check_codec(None, SampleRate::Narrow, 180).is_ok());
+        assert!(check_codec(None, SampleRate::Narrow, 0).is_err());
+    }
+}
@@ -1,8 +1,10 @@
 use crate::{
     cli::{BufferMode, Cli, JbufType},
+    error::B2bError,
     logging,
 };
 use anyhow::{Context, Result};
+use serde_json::json;
 use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
 use std::{
     io::Write,
@@ -35,6 +37,7 @@ pub fn run(args: &Cli) -> Result<()> {
     let conf = format!(
         "module\t\tg711\n\
          module\t\tl16\n\
+         module\t\tg722\n\
          sip_listen\t{sip}\n\
          call_accept\tyes\n\
          audio_buffer\t{bmin}-{bmax}\n\
@@ -54,7 +57,7 @@ pub fn run(args: &Cli) -> Result<()> {
     }
 
     // Start baresip reactor (UA/core) and shim init
-    let ua = UaHandle::init().context("init UA")?;
+    let ua = UaHandle::init().context(B2bError::Sip("init UA".into()))?;
     let (_bridge, rx) = ua.reactor.register_events();
     {
         let codecs = crate::sip_shim::codecs_csv();
@@ -64,13 +67,16 @@ pub fn run(args: &Cli) -> Result<()> {
         let tag = logging::role_tag("sink");
         std::thread::spawn(move || {
             while let Ok(ev) = rx.recv() {
-                logging::println_tag(
+                logging::event(
                     &tag,
+                    "sip",
                     &format!("bevent kind={:?} text={:?}", ev.kind(), ev.text),
+                    json!({ "kind": format!("{:?}", ev.kind()), "text": ev.text }),
                 );
             }
         });
     }
+    let codec = super::select_codec(args)?;
     sip_shim::sink_init(sip)?;
     // Clear preloaded config var to avoid affecting other roles
     unsafe {
@@ -78,9 +84,11 @@ pub fn run(args: &Cli) -> Result<()> {
     }
 
     // Start aplay and keep stdin open for future PCM writes.
-    let mut aplay = spawn_aplay(&args.aplay_cmd).context("spawn aplay")?;
+    let rate = args.sample_rate.hz();
+    let aplay_cmd = playout_cmd(&args.aplay_cmd, rate);
+    let mut aplay = spawn_aplay(&aplay_cmd).context(B2bError::Audio("spawn aplay".into()))?;
 
-    logging::ready_line("sink", sip, &args.codec, args.ptime_ms);
+    logging::ready_line("sink", sip, codec, args.ptime_ms);
 
     // Wire shim PCM callback to a bounded channel and a dedicated writer thread.
     if let Some(stdin) = aplay.stdin.take() {
@@ -126,6 +134,7 @@ pub fn run(args: &Cli) -> Result<()> {
     // Periodic metrics log
     {
         let tag = logging::role_tag("sink");
+        let frame_len = (u64::from(rate) * u64::from(args.ptime_ms) / 1000).max(1);
         std::thread::spawn(move || {
             let mut last = 0u64;
             loop {
@@ -133,17 +142,19 @@ pub fn run(args: &Cli) -> Result<()> {
                 let now = RX_SAMPLES.load(Ordering::Relaxed);
                 let delta = now.saturating_sub(last);
                 last = now;
-                let frames = delta / 160; // 20ms @ 8kHz mono
-                logging::println_tag(
+                let frames = delta / frame_len;
+                logging::event(
                     &tag,
+                    "metrics",
                     &format!("rx_samples={} (+{}), rx_frames+{}", now, delta, frames),
+                    json!({ "rx_samples": now, "delta": delta, "rx_frames": frames }),
                 );
             }
         });
     }
 
     wait_for_ctrl_c();
-    logging::println_tag(&tag, "Ctrl+C received; shutting down");
+    logging::println_tag(&tag, "SIGINT/SIGTERM received; shutting down");
 
     logging::println_tag(&tag, "shutdown: UA + aplay");
     // Best-effort shutdown
@@ -172,6 +183,11 @@ fn wait_for_ctrl_c() {
     let _ = rx.recv();
 }
 
+/// `--aplay-cmd` with every `{rate}` replaced by the sample rate in Hz.
+fn playout_cmd(template: &str, rate: u32) -> String {
+    template.replace("{rate}", &rate.to_string())
+}
+
 fn spawn_aplay(cmdline: &str) -> Result<Child> {
     // Use a shell to interpret the provided command string.
     let mut cmd = Command::new("sh");
@@ -187,3 +203,23 @@ fn spawn_aplay(cmdline: &str) -> Result<Child> {
 }
 
 // no non-sip fallback anymore
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn aplay_cmd_gets_the_sample_rate() {
+        let args = Cli::try_parse_from(["b2b", "--role", "sink", "--sample-rate", "16k"]).unwrap();
+        assert_eq!(
+            playout_cmd(&args.aplay_cmd, args.sample_rate.hz()),
+            "aplay -f S16_LE -r 16000 -c 1 -t raw"
+        );
+        assert_eq!(
+            playout_cmd("sox -r {rate} - out-{rate}.wav", 48000),
+            "sox -r 48000 - out-48000.wav"
+        );
+        assert_eq!(playout_cmd("cat > /dev/null", 8000), "cat > /dev/null");
+    }
+}
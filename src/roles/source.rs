@@ -1,5 +1,8 @@
-use crate::{cli::Cli, logging, media, sip::UaHandle, sip_shim};
+use crate::{
+    cli::Cli, error::B2bError, logging, media, playlist::Playlist, sip::UaHandle, sip_shim,
+};
 use anyhow::{Context, Result};
+use serde_json::json;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::{thread, time::Duration};
 
@@ -7,14 +10,88 @@ static TX_SAMPLES: AtomicU64 = AtomicU64::new(0);
 
 pub fn run(args: &Cli) -> Result<()> {
     let tag = logging::role_tag("source");
-    logging::println_tag(&tag, "starting (mp3 decode + prebuffer skeleton)");
+    logging::println_tag(&tag, "starting (streaming decode + prebuffer)");
+
+    let playlist = match (&args.playlist, &args.audio_file) {
+        (Some(spec), _) => {
+            let mut list = Playlist::load(spec)
+                .with_context(|| B2bError::Audio(format!("playlist {spec}")))?;
+            let seed = args.shuffle.then(|| args.seed.unwrap_or_else(time_seed));
+            if let Some(seed) = seed {
+                list.shuffle(seed);
+            }
+            let order = if seed.is_some() {
+                "shuffled"
+            } else {
+                "in order"
+            };
+            logging::event(
+                &tag,
+                "playlist",
+                &format!(
+                    "playlist {spec}: {} entries, {order}{}, crossfade {}ms",
+                    list.items().len(),
+                    seed.map_or(String::new(), |s| format!(" (seed {s})")),
+                    args.crossfade_ms
+                ),
+                json!({
+                    "playlist": spec,
+                    "entries": list.items().len(),
+                    "shuffle": args.shuffle,
+                    "seed": seed,
+                    "crossfade_ms": args.crossfade_ms,
+                }),
+            );
+            Some(list)
+        }
+        (None, Some(path)) => Some(Playlist::single(path)),
+        (None, None) => None,
+    };
+
+    // Start decoding right away so the ring is full by the time the call is
+    // up. Holds the prebuffer plus a second of headroom, never the file.
+    let rate = args.sample_rate.hz();
+    let feed = match playlist {
+        Some(list) => {
+            let opts = media::FeedOptions {
+                rate,
+                capacity_ms: args.prebuffer_ms + 1000,
+                quality: args.resample_quality,
+                crossfade_ms: args.crossfade_ms,
+                looping: true,
+            };
+            Some(media::Feed::start(list, opts).context(B2bError::Audio("decode".into()))?)
+        }
+        None => None,
+    };
+    // Entry changes are only worth logging for a real playlist.
+    let announce = args.playlist.is_some();
+    // One ptime of mono audio per frame; silence without an audio file.
+    let mut frame = vec![0i16; (rate * args.ptime_ms / 1000).max(1) as usize];
+    let frame_len = frame.len() as u64;
+    // Every frame handed to the shim is counted, primed ones included;
+    // notices log the count before their frame, so offsets are exact to
+    // the frame.
+    let next_frame = |frame: &mut [i16]| -> Result<()> {
+        if let Some(feed) = &feed {
+            if !feed.fill(frame) {
+                let why = feed.error().unwrap_or_else(|| "decoder stopped".into());
+                anyhow::bail!(B2bError::Audio(why));
+            }
+            for notice in feed.notices() {
+                log_notice(&tag, notice, announce);
+            }
+        }
+        TX_SAMPLES.fetch_add(frame.len() as u64, Ordering::Relaxed);
+        Ok(())
+    };
 
     // Initialize UA/reactor and start outbound call using shim ausrc.
     // Ensure PCMU (g711) module is loaded before UA init via preloaded config
     unsafe {
-        std::env::set_var("BRS_CONF_BUF", "module\tg711\nmodule\tl16\n");
+        std::env::set_var("BRS_CONF_BUF", "module\tg711\nmodule\tl16\nmodule\tg722\n");
     }
-    let ua = UaHandle::init().context("init UA")?;
+    let ua = UaHandle::init().context(B2bError::Sip("init UA".into()))?;
     // Single event registration; we multiplex for logging and readiness
     let (_bridge_ev, rx_ev) = ua.reactor.register_events();
     {
@@ -32,18 +109,48 @@ pub fn run(args: &Cli) -> Result<()> {
                 let kind = format!("{:?}", ev.kind());
                 let text = ev.text.clone().unwrap_or_default();
                 if kind.contains("CALL_LOCAL_SDP") {
-                    logging::println_tag(&tag, "SDP: Sent offer");
+                    logging::event(
+                        &tag,
+                        "sip",
+                        "SDP: Sent offer",
+                        json!({ "state": "local_sdp" }),
+                    );
                 } else if kind.contains("CALL_REMOTE_SDP") {
-                    logging::println_tag(&tag, "SDP: Received answer");
+                    logging::event(
+                        &tag,
+                        "sip",
+                        "SDP: Received answer",
+                        json!({ "state": "remote_sdp" }),
+                    );
                 } else if kind.contains("CALL_PROGRESS") || text.contains("180 Ringing") {
-                    logging::println_tag(&tag, "SIP: Ringing (180)");
+                    logging::event(
+                        &tag,
+                        "sip",
+                        "SIP: Ringing (180)",
+                        json!({ "state": "ringing" }),
+                    );
                 } else if kind.contains("CALL_RTPESTAB") {
-                    logging::println_tag(&tag, "RTP: Flow established");
+                    logging::event(
+                        &tag,
+                        "sip",
+                        "RTP: Flow established",
+                        json!({ "state": "rtp_established" }),
+                    );
                 } else if kind.contains("CALL_ESTABLISHED") {
-                    logging::println_tag(&tag, "SIP: Call established");
+                    logging::event(
+                        &tag,
+                        "sip",
+                        "SIP: Call established",
+                        json!({ "state": "established" }),
+                    );
                     let _ = sig_tx.send(Ok(()));
                 } else if kind.contains("CALL_CLOSED") {
-                    logging::println_tag(&tag, &format!("SIP: Call closed ({})", text));
+                    logging::event(
+                        &tag,
+                        "sip",
+                        &format!("SIP: Call closed ({})", text),
+                        json!({ "state": "closed", "reason": text }),
+                    );
                     let _ = sig_tx.send(Err(text));
                 }
             }
@@ -55,7 +162,8 @@ pub fn run(args: &Cli) -> Result<()> {
         let tag = logging::role_tag("source");
         logging::println_tag(&tag, &format!("Dialing target: {}", target));
     }
-    sip_shim::source_start(target, 8000, 1, args.ptime_ms)?;
+    let codec = super::select_codec(args)?;
+    sip_shim::source_start(target, rate, 1, args.ptime_ms)?;
     unsafe {
         std::env::remove_var("BRS_CONF_BUF");
     }
@@ -72,52 +180,40 @@ pub fn run(args: &Cli) -> Result<()> {
             Ok(Ok(())) => break true,
             Ok(Err(reason)) => {
                 logging::println_tag(&tag_ready, &format!("call setup failed: {}", reason));
-                return Err(anyhow::anyhow!("call setup failed: {}", reason));
+                anyhow::bail!(B2bError::Sip(format!("call setup failed: {}", reason)));
             }
             Err(std::sync::mpsc::RecvTimeoutError::Timeout) => continue,
             Err(_) => break false,
         }
     };
     if !established {
-        return Err(anyhow::anyhow!(
-            "call setup timeout waiting for ESTABLISHED"
+        anyhow::bail!(B2bError::Timeout(
+            "call setup timeout waiting for ESTABLISHED".into()
         ));
     }
 
-    // Decode audio file if provided; otherwise synthesize silence frames.
-    let frames: Vec<Vec<i16>> = if let Some(path) = &args.audio_file {
-        let pcm = media::decode_mp3_to_pcm_8k(path)
-            .with_context(|| format!("decode {}", path.display()))?;
-        media::split_into_20ms_frames(&pcm.data, pcm.sample_rate)
-    } else {
-        vec![vec![0i16; 160]; 50 * 5] // 5s of silence @ 8kHz mono, 20ms frames
-    };
-
     let prebuffer_frames = (args.prebuffer_ms as usize / args.ptime_ms as usize).max(1);
-    // Use a cyclic index instead of a producer thread to avoid busy loop
-    let mut idx = 0usize;
+    if let Some(feed) = &feed {
+        feed.wait_for(prebuffer_frames * frame.len(), Duration::from_secs(2));
+    }
 
     // Prime C-side aubuf with prebuffer frames but keep TX gated off.
-    {
-        let mut primed = 0usize;
-        while primed < prebuffer_frames {
-            let frame = &frames[idx];
-            let _ = sip_shim::source_push_pcm(frame);
-            idx = (idx + 1) % frames.len();
-            primed += 1;
-        }
+    for _ in 0..prebuffer_frames {
+        next_frame(&mut frame)?;
+        let _ = sip_shim::source_push_pcm(&frame);
     }
 
     // Small pre-roll to allow remote jitter/rtp to settle
     std::thread::sleep(std::time::Duration::from_millis(args.preroll_ms as u64));
     // Enable TX now that buffer is primed
     let _ = sip_shim::source_tx_enable(true);
-    logging::ready_line("source", target, &args.codec, args.ptime_ms);
+    logging::ready_line("source", target, codec, args.ptime_ms);
 
     // Start metrics thread before main loop
     let (stop_tx, stop_rx) = std::sync::mpsc::channel::<()>();
     let metrics_handle = {
         let tag = logging::role_tag("source");
+        let underruns = feed.as_ref().map(|f| f.underrun_counter());
         std::thread::spawn(move || {
             let mut last = 0u64;
             loop {
@@ -127,10 +223,21 @@ pub fn run(args: &Cli) -> Result<()> {
                         let now = TX_SAMPLES.load(Ordering::Relaxed);
                         let delta = now.saturating_sub(last);
                         last = now;
-                        let frames = delta / 160;
-                        logging::println_tag(
+                        let frames = delta / frame_len;
+                        let underruns = underruns.as_ref().map_or(0, |u| u.load(Ordering::Relaxed));
+                        logging::event(
                             &tag,
-                            &format!("tx_samples={} (+{}), tx_frames+{}", now, delta, frames),
+                            "metrics",
+                            &format!(
+                                "tx_samples={} (+{}), tx_frames+{}, decode_underruns={}",
+                                now, delta, frames, underruns
+                            ),
+                            json!({
+                                "tx_samples": now,
+                                "delta": delta,
+                                "tx_frames": frames,
+                                "decode_underruns": underruns,
+                            }),
                         );
                     }
                     Err(_) => break, // Channel disconnected
@@ -145,20 +252,18 @@ pub fn run(args: &Cli) -> Result<()> {
         // Top-up loop: push frames rapidly until backlog >= target
         let mut loops = 0;
         while sip_shim::source_backlog_ms() < target_backlog_ms {
-            let frame = &frames[idx];
-            let _ = sip_shim::source_push_pcm(frame);
-            TX_SAMPLES.fetch_add(frame.len() as u64, Ordering::Relaxed);
-            idx = (idx + 1) % frames.len();
+            next_frame(&mut frame)?;
+            let _ = sip_shim::source_push_pcm(&frame);
             loops += 1;
             if loops > 50 {
                 break;
             } // avoid monopolizing CPU; yield soon
         }
-        // Sleep a bit; C ausrc will drain at 20ms/frame
+        // Sleep a bit; C ausrc will drain a frame per ptime
         thread::sleep(Duration::from_millis(10));
         // break only on ctrl-c
         if ctrlc_tripped() {
-            logging::println_tag(&tag, "Ctrl+C received; shutting down");
+            logging::println_tag(&tag, "SIGINT/SIGTERM received; shutting down");
             break;
         }
     }
@@ -171,9 +276,77 @@ pub fn run(args: &Cli) -> Result<()> {
     Ok(())
 }
 
+/// Log a playlist notice as it reaches the wire. `tx_samples` is the
+/// entry's offset in the sent stream, to line up with the sink's
+/// `rx_samples`.
+fn log_notice(tag: &str, notice: media::Notice, announce: bool) {
+    let tx_samples = TX_SAMPLES.load(Ordering::Relaxed);
+    match notice {
+        media::Notice::Started {
+            index,
+            total,
+            path,
+            title,
+            pass,
+        } if announce => {
+            let name = title
+                .as_deref()
+                .map_or(String::new(), |t| format!(" \"{t}\""));
+            logging::event(
+                tag,
+                "playlist",
+                &format!(
+                    "playing {index}/{total}: {}{name} (pass {pass}, tx_samples={tx_samples})",
+                    path.display()
+                ),
+                json!({
+                    "state": "item_start",
+                    "index": index,
+                    "total": total,
+                    "path": path,
+                    "title": title,
+                    "pass": pass,
+                    "tx_samples": tx_samples,
+                }),
+            );
+        }
+        media::Notice::Started { .. } => {}
+        media::Notice::Skipped { path, error } => logging::event(
+            tag,
+            "playlist",
+            &format!("skipping {}: {error}", path.display()),
+            json!({ "state": "item_skipped", "path": path, "error": error }),
+        ),
+        media::Notice::Damaged {
+            path,
+            packets,
+            error,
+        } => logging::event(
+            tag,
+            "playlist",
+            &format!(
+                "dropped {packets} damaged packet(s) from {}: {error}",
+                path.display()
+            ),
+            json!({
+                "state": "item_damaged",
+                "path": path,
+                "packets": packets,
+                "error": error,
+            }),
+        ),
+    }
+}
+
+fn time_seed() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64)
+}
+
 fn ctrlc_tripped() -> bool {
-    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Once;
+    use std::sync::atomic::{AtomicBool, Ordering};
     static HIT: AtomicBool = AtomicBool::new(false);
     static INIT: Once = Once::new();
 
@@ -1,3 +1,4 @@
+use crate::error::B2bError;
 use anyhow::Result;
 use std::os::raw::{c_char, c_int, c_void};
 
@@ -22,13 +23,14 @@ unsafe extern "C" {
     fn sip_mixer_shutdown() -> c_int;
     fn sip_mixer_config(seq: *const c_char, period_ms: u32, gain_in: f32, gain_dtmf: f32) -> c_int;
     fn brs_codecs_csv() -> *const c_char;
+    fn sip_set_audio_codecs(list: *const c_char) -> c_int;
 }
 
 pub fn sink_init(bind_addr: &str) -> Result<()> {
     let c = std::ffi::CString::new(bind_addr).unwrap();
     let rc = unsafe { sip_sink_init(c.as_ptr()) };
     if rc != 0 {
-        anyhow::bail!("sip_sink_init rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_sink_init rc={rc}")));
     }
     Ok(())
 }
@@ -39,7 +41,7 @@ pub fn sink_set_pcm_callback(
 ) -> Result<()> {
     let rc = unsafe { sip_sink_set_pcm_callback(cb, user) };
     if rc != 0 {
-        anyhow::bail!("sip_sink_set_pcm_callback rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_sink_set_pcm_callback rc={rc}")));
     }
     Ok(())
 }
@@ -47,7 +49,7 @@ pub fn sink_set_pcm_callback(
 pub fn sink_shutdown() -> Result<()> {
     let rc = unsafe { sip_sink_shutdown() };
     if rc != 0 {
-        anyhow::bail!("sip_sink_shutdown rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_sink_shutdown rc={rc}")));
     }
     Ok(())
 }
@@ -56,7 +58,7 @@ pub fn source_start(target: &str, srate: u32, ch: u8, ptime_ms: u32) -> Result<(
     let c = std::ffi::CString::new(target).unwrap();
     let rc = unsafe { sip_source_start(c.as_ptr(), srate, ch, ptime_ms) };
     if rc != 0 {
-        anyhow::bail!("sip_source_start rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_source_start rc={rc}")));
     }
     Ok(())
 }
@@ -64,7 +66,7 @@ pub fn source_start(target: &str, srate: u32, ch: u8, ptime_ms: u32) -> Result<(
 pub fn source_push_pcm(samples: &[i16]) -> Result<()> {
     let rc = unsafe { sip_source_push_pcm(samples.as_ptr(), samples.len()) };
     if rc != 0 {
-        anyhow::bail!("sip_source_push_pcm rc={}", rc);
+        anyhow::bail!(B2bError::Audio(format!("sip_source_push_pcm rc={rc}")));
     }
     Ok(())
 }
@@ -76,7 +78,7 @@ pub fn source_backlog_ms() -> u32 {
 pub fn source_tx_enable(on: bool) -> Result<()> {
     let rc = unsafe { sip_source_tx_enable(if on { 1 } else { 0 }) };
     if rc != 0 {
-        anyhow::bail!("sip_source_tx_enable rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_source_tx_enable rc={rc}")));
     }
     Ok(())
 }
@@ -91,12 +93,22 @@ pub fn codecs_csv() -> String {
     }
 }
 
+/// Codecs every UA created afterwards offers and accepts, e.g. `g722`.
+pub fn set_audio_codecs(list: &str) -> Result<()> {
+    let c = std::ffi::CString::new(list).unwrap();
+    let rc = unsafe { sip_set_audio_codecs(c.as_ptr()) };
+    if rc != 0 {
+        anyhow::bail!(B2bError::Usage(format!("invalid codec list {list:?}")));
+    }
+    Ok(())
+}
+
 pub fn mixer_init(bind_addr: &str, target: &str, srate: u32, ch: u8, ptime_ms: u32) -> Result<()> {
     let b = std::ffi::CString::new(bind_addr).unwrap();
     let t = std::ffi::CString::new(target).unwrap();
     let rc = unsafe { sip_mixer_init(b.as_ptr(), t.as_ptr(), srate, ch, ptime_ms) };
     if rc != 0 {
-        anyhow::bail!("sip_mixer_init rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_mixer_init rc={rc}")));
     }
     Ok(())
 }
@@ -104,7 +116,7 @@ pub fn mixer_init(bind_addr: &str, target: &str, srate: u32, ch: u8, ptime_ms: u
 pub fn mixer_shutdown() -> Result<()> {
     let rc = unsafe { sip_mixer_shutdown() };
     if rc != 0 {
-        anyhow::bail!("sip_mixer_shutdown rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_mixer_shutdown rc={rc}")));
     }
     Ok(())
 }
@@ -113,7 +125,7 @@ pub fn mixer_config(seq: &str, period_ms: u32, gain_in: f32, gain_dtmf: f32) ->
     let c = std::ffi::CString::new(seq).unwrap_or_else(|_| std::ffi::CString::new("123#").unwrap());
     let rc = unsafe { sip_mixer_config(c.as_ptr(), period_ms, gain_in, gain_dtmf) };
     if rc != 0 {
-        anyhow::bail!("sip_mixer_config rc={}", rc);
+        anyhow::bail!(B2bError::Sip(format!("sip_mixer_config rc={rc}")));
     }
     Ok(())
 }